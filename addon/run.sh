@@ -64,11 +64,67 @@ if bashio::config.has_value scan ; then
   export GOVEE_LAN_SCAN="$(bashio::config scan)"
 fi
 
+if bashio::config.has_value lan_poll_interval ; then
+  export GOVEE_LAN_POLL_INTERVAL="$(bashio::config lan_poll_interval)"
+fi
+
+if bashio::config.has_value lan_interface ; then
+  export GOVEE_LAN_INTERFACE="$(bashio::config lan_interface)"
+fi
+
+if bashio::config.has_value lan_listen_addr ; then
+  export GOVEE_LAN_LISTEN_ADDR="$(bashio::config lan_listen_addr)"
+fi
+
+if bashio::config.has_value transport_preference ; then
+  export GOVEE_TRANSPORT_PREFERENCE="$(bashio::config transport_preference)"
+fi
+
+if bashio::config.has_value device_list_refresh_minutes ; then
+  export GOVEE_DEVICE_LIST_REFRESH_MINUTES="$(bashio::config device_list_refresh_minutes)"
+fi
+
+if bashio::config.has_value http_timeout ; then
+  export GOVEE_HTTP_TIMEOUT="$(bashio::config http_timeout)"
+fi
+
+if bashio::config.has_value http_proxy ; then
+  export GOVEE_HTTP_PROXY="$(bashio::config http_proxy)"
+fi
+
+if bashio::config.has_value http_ca_bundle ; then
+  export GOVEE_HTTP_CA_BUNDLE="$(bashio::config http_ca_bundle)"
+fi
+
+if bashio::config.has_value http_legacy_get_routes ; then
+  export GOVEE_HTTP_LEGACY_GET_ROUTES="$(bashio::config http_legacy_get_routes)"
+fi
+
+if bashio::config.has_value http_bind ; then
+  export GOVEE_HTTP_BIND="$(bashio::config http_bind)"
+fi
+
+if bashio::config.has_value http_control_token ; then
+  export GOVEE_HTTP_CONTROL_TOKEN="$(bashio::config http_control_token)"
+fi
+
+if bashio::config.has_value http_read_only_token ; then
+  export GOVEE_HTTP_READ_ONLY_TOKEN="$(bashio::config http_read_only_token)"
+fi
+
+if bashio::config.has_value http_control_basic_auth ; then
+  export GOVEE_HTTP_CONTROL_BASIC_AUTH="$(bashio::config http_control_basic_auth)"
+fi
+
+if bashio::config.has_value http_read_only_basic_auth ; then
+  export GOVEE_HTTP_READ_ONLY_BASIC_AUTH="$(bashio::config http_read_only_basic_auth)"
+fi
+
 if bashio::config.has_value temperature_scale ; then
   export GOVEE_TEMPERATURE_SCALE="$(bashio::config temperature_scale)"
 fi
 
-env | grep GOVEE_ | sed -r 's/_(EMAIL|KEY|PASSWORD)=.*/_\1=REDACTED/'
+env | grep GOVEE_ | sed -r 's/_(EMAIL|KEY|PASSWORD|TOKEN|BASIC_AUTH)=.*/_\1=REDACTED/'
 set -x
 
 cd /app
@@ -5,6 +5,7 @@ import { timeAgo } from './timeago.js';
 
 export class DeviceList extends LitElement {
   timer;
+  events;
   deviceList;
 
   static properties = {
@@ -51,17 +52,32 @@ export class DeviceList extends LitElement {
     return this;
   }
 
+  // Refresh the list whenever the server tells us that something
+  // changed, and also periodically so that the relative times stay
+  // current and so that we recover if the event stream drops out.
   ensureTimerStarted() {
     if (this.timer === undefined) {
       this.timer = setInterval(() => {
         this._deviceListTask.run();
-      }, 5000);
+      }, 60000);
+    }
+    if (this.events === undefined) {
+      this.events = new EventSource('/api/events');
+      for (const name of ['device_state_changed', 'device_discovered', 'device_removed']) {
+        this.events.addEventListener(name, () => {
+          this._deviceListTask.run();
+        });
+      }
     }
   }
 
   ensureTimerStopped() {
     clearInterval(this.timer);
     this.timer = undefined;
+    if (this.events !== undefined) {
+      this.events.close();
+      this.events = undefined;
+    }
   }
 
   disconnectedCallback() {
@@ -74,17 +90,24 @@ export class DeviceList extends LitElement {
     this.ensureTimerStarted();
   }
 
+  _set_state(device_id, state) {
+    fetch(`/api/device/${encodeURIComponent(device_id)}/state`, {
+      method: 'PUT',
+      headers: {'Content-Type': 'application/json'},
+      body: JSON.stringify(state),
+    });
+  }
+
   _set_power_on(e) {
     const device_id = e.target.dataset.id;
-    const power = e.target.checked ? 'on' : 'off';
-    fetch(`/api/device/${device_id}/power/${power}`);
+    this._set_state(device_id, {power: e.target.checked});
   }
 
   _set_color(e) {
     const device_id = e.target.dataset.id;
-    const color = encodeURIComponent(e.target.value);
+    const color = e.target.value;
     console.log(`color will change to ${color}`);
-    fetch(`/api/device/${device_id}/color/${color}`);
+    this._set_state(device_id, {color});
   }
 
   _render_item = (item) => {
@@ -1,3 +1,4 @@
+use crate::service::metrics::CACHE_REQUESTS;
 use anyhow::Context;
 use arc_swap::ArcSwap;
 use chrono::{DateTime, Utc};
@@ -6,21 +7,36 @@ use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use sqlite_cache::{Cache, CacheConfig};
 use std::future::Future;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
 
 pub static CACHE: Lazy<ArcSwap<Cache>> =
     Lazy::new(|| open_cache().expect("failed to initialize cache").into());
 
-fn cache_file_name() -> PathBuf {
-    let cache_dir = std::env::var("GOVEE_CACHE_DIR")
+/// Returns the directory in which we keep persistent state
+pub fn cache_dir() -> PathBuf {
+    std::env::var("GOVEE_CACHE_DIR")
         .ok()
         .map(PathBuf::from)
         .or_else(|| dirs_next::cache_dir())
-        .expect("failed to resolve cache dir");
+        .expect("failed to resolve cache dir")
+}
+
+/// Write `data` to `path`.  The data is written to a temporary
+/// file first and then renamed into place, so that a crash part way
+/// through cannot leave a truncated file behind.
+pub fn write_file_atomically(path: &Path, data: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let temp = PathBuf::from(temp);
+    std::fs::write(&temp, data).with_context(|| format!("writing {temp:?}"))?;
+    std::fs::rename(&temp, path).with_context(|| format!("renaming {temp:?} -> {path:?}"))?;
+    Ok(())
+}
 
-    cache_dir.join("govee2mqtt-cache.sqlite")
+fn cache_file_name() -> PathBuf {
+    cache_dir().join("govee2mqtt-cache.sqlite")
 }
 
 fn open_cache() -> anyhow::Result<Arc<Cache>> {
@@ -96,6 +112,58 @@ pub fn invalidate_key(topic: &str, key: &str) -> anyhow::Result<()> {
     Ok(topic.delete(key)?)
 }
 
+/// An entry in the cache, as returned by `dump_cache`
+#[derive(Serialize, Debug)]
+pub struct CacheDumpEntry {
+    pub topic: String,
+    pub key: String,
+    /// The cached value, if it is JSON, otherwise the raw text
+    pub value: serde_json::Value,
+}
+
+/// Returns the contents of the cache, for use in diagnostics.
+/// The cache is read via a separate read-only connection, so that
+/// this doesn't contend with regular cache access.
+pub fn dump_cache() -> anyhow::Result<Vec<CacheDumpEntry>> {
+    use sqlite_cache::rusqlite::{Connection, OpenFlags};
+
+    let cache_file = cache_file_name();
+    let conn = Connection::open_with_flags(&cache_file, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("opening {cache_file:?}"))?;
+
+    let tables = conn
+        .prepare("select name from sqlite_master where type = 'table' and name like 'topic_%'")?
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+
+    let mut entries = vec![];
+    for table in tables {
+        // sqlite_cache names the table after the base32 encoded topic
+        let topic = table
+            .strip_prefix("topic_")
+            .and_then(|encoded| data_encoding::BASE32_NOPAD.decode(encoded.as_bytes()).ok())
+            .map(|topic| String::from_utf8_lossy(&topic).to_string())
+            .unwrap_or_else(|| table.clone());
+        let mut stmt = conn.prepare(&format!("select k, v from {table} order by k"))?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+        for row in rows {
+            let (key, data) = row?;
+            let value = serde_json::from_slice(&data).unwrap_or_else(|_| {
+                serde_json::Value::String(String::from_utf8_lossy(&data).to_string())
+            });
+            entries.push(CacheDumpEntry {
+                topic: topic.clone(),
+                key,
+                value,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
 /// Cache an item with a soft TTL; we'll retry the operation
 /// if the TTL has expired, but allow stale reads
 pub async fn cache_get<T, Fut>(options: CacheGetOptions<'_>, future: Fut) -> anyhow::Result<T>
@@ -114,6 +182,7 @@ where
             Ok(entry) => {
                 if now < entry.expires {
                     log::trace!("cache hit for {}", options.key);
+                    CACHE_REQUESTS.inc(&[options.topic, "hit"]);
                     return entry.result.into_result();
                 }
 
@@ -129,6 +198,7 @@ where
     }
 
     log::trace!("cache miss for {}", options.key);
+    CACHE_REQUESTS.inc(&[options.topic, "miss"]);
     let value: anyhow::Result<CacheComputeResult<T>> = future.await;
     match value {
         Ok(CacheComputeResult::WithTtl(value, ttl)) => {
@@ -163,17 +233,22 @@ where
                 let data = serde_json::to_string_pretty(&entry)?;
                 updater.write(data.as_bytes(), options.hard_ttl)?;
 
-                entry.result.into_result()
+                match entry.result {
+                    CacheResult::Ok(value) => Ok(value),
+                    // Return the original error, rather than its text,
+                    // so that the caller can tell what kind it is
+                    CacheResult::Err(_) => Err(err),
+                }
             }
             _ => {
-                let entry = CacheEntry {
+                let entry: CacheEntry<T> = CacheEntry {
                     expires: Utc::now() + options.negative_ttl,
                     result: CacheResult::Err(format!("{err:#}")),
                 };
 
                 let data = serde_json::to_string_pretty(&entry)?;
                 updater.write(data.as_bytes(), options.hard_ttl)?;
-                entry.result.into_result()
+                Err(err)
             }
         },
     }
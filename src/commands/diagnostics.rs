@@ -0,0 +1,44 @@
+use crate::commands::list::discover_devices;
+use crate::service::diagnostics::{build_diagnostics_bundle, bundle_file_name};
+use anyhow::Context;
+use std::path::PathBuf;
+
+/// Produce an archive containing diagnostic information that is
+/// suitable for attaching to an issue report.  Sensitive values,
+/// such as credentials and MAC addresses, are removed.
+/// When the service is running, prefer fetching the archive from
+/// its /api/diagnostics HTTP endpoint, as that also includes its
+/// recent logs and packets.
+#[derive(clap::Parser, Debug)]
+pub struct DiagnosticsCommand {
+    #[arg(long)]
+    skip_lan: bool,
+
+    /// Where to write the archive.  The default is a timestamped
+    /// file name in the current directory.
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+impl DiagnosticsCommand {
+    pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
+        // Diagnostics are most needed when something is broken,
+        // so carry on even if the cloud APIs are failing
+        let state = discover_devices(args, self.skip_lan, true).await?;
+        state
+            .set_temperature_scale(args.hass_args.temperature_scale()?)
+            .await;
+
+        let bundle = build_diagnostics_bundle(&state).await?;
+
+        let output = self
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(bundle_file_name()));
+        std::fs::write(&output, bundle).with_context(|| format!("writing {output:?}"))?;
+
+        println!("Wrote diagnostics to {output:?}. Please review its contents before sharing it.");
+
+        Ok(())
+    }
+}
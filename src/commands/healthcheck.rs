@@ -0,0 +1,89 @@
+use crate::service::http::resolve_bind_address;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Query the health endpoint of a running service, exiting with
+/// a non-zero status if it is unhealthy.  This is intended for use
+/// as a container health check, as the container has no curl.
+#[derive(clap::Parser, Debug)]
+pub struct HealthCheckCommand {
+    /// The port on which the service's HTTP API listens
+    #[arg(long, default_value_t = 8056)]
+    http_port: u16,
+
+    /// The address on which the service's HTTP API listens.
+    /// If it listens on all addresses, loopback is used.
+    /// You may also set this via the GOVEE_HTTP_BIND environment
+    /// variable, which is shared with the service.
+    #[arg(long)]
+    http_bind: Option<IpAddr>,
+
+    /// The URL to query, overriding --http-port and --http-bind.
+    /// Use /readyz to also require that each of the subsystems is ok.
+    #[arg(long)]
+    url: Option<String>,
+}
+
+impl HealthCheckCommand {
+    fn url(&self) -> anyhow::Result<String> {
+        if let Some(url) = &self.url {
+            return Ok(url.clone());
+        }
+        let ip = match resolve_bind_address(self.http_bind)? {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        Ok(format!(
+            "http://{}/healthz",
+            SocketAddr::new(ip, self.http_port)
+        ))
+    }
+
+    pub async fn run(&self, _args: &crate::Args) -> anyhow::Result<()> {
+        let url = self.url()?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        let response = client.get(&url).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        println!("{body}");
+
+        if !status.is_success() {
+            anyhow::bail!("{url} returned {status}");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    fn url(args: &[&str]) -> String {
+        let args = std::iter::once("health-check").chain(args.iter().copied());
+        HealthCheckCommand::parse_from(args).url().unwrap()
+    }
+
+    #[test]
+    fn default_url() {
+        assert_eq!(
+            url(&["--http-bind", "0.0.0.0"]),
+            "http://127.0.0.1:8056/healthz"
+        );
+        assert_eq!(
+            url(&["--http-bind", "::", "--http-port", "9000"]),
+            "http://[::1]:9000/healthz"
+        );
+        assert_eq!(
+            url(&["--http-bind", "192.168.1.10"]),
+            "http://192.168.1.10:8056/healthz"
+        );
+        assert_eq!(
+            url(&["--url", "http://localhost:1234/readyz"]),
+            "http://localhost:1234/readyz"
+        );
+    }
+}
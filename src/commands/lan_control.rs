@@ -1,9 +1,10 @@
 use crate::ble::{Base64HexBytes, SetSceneCode};
-use crate::lan_api::{Client, DiscoOptions};
+use crate::lan_api::{Client, DeviceColor};
 use crate::undoc_api::GoveeUndocumentedApi;
 use clap_num::maybe_hex;
 use std::collections::BTreeMap;
 use std::net::IpAddr;
+use tokio::time::{Duration, Instant};
 use uncased::Uncased;
 
 #[derive(clap::Parser, Debug)]
@@ -45,31 +46,48 @@ enum SubCommand {
         #[arg(required_unless_present = "list")]
         scene: Option<String>,
     },
+    /// Stream a moving rainbow to the device using the real-time
+    /// mode that powers screen sync (DreamView)
+    Stream {
+        /// The number of segments to address
+        #[arg(long, default_value_t = 10)]
+        segments: u8,
+
+        /// The number of frames to send per second
+        #[arg(long, default_value_t = 20)]
+        fps: u32,
+
+        /// How long to stream for, in seconds
+        #[arg(long, default_value_t = 10)]
+        duration: u64,
+    },
 }
 
 impl LanControlCommand {
-    pub async fn run(&self, _args: &crate::Args) -> anyhow::Result<()> {
-        let (client, _scan) = Client::new(DiscoOptions::default()).await?;
+    pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
+        let (client, _scan) = Client::new(args.lan_disco_args.to_disco_options()?).await?;
 
         let device = client.scan_ip(self.ip).await?;
 
         match &self.cmd {
             SubCommand::On => {
-                device.send_turn(true).await?;
+                device.send_turn(&client, true).await?;
             }
             SubCommand::Off => {
-                device.send_turn(false).await?;
+                device.send_turn(&client, false).await?;
             }
             SubCommand::Brightness { percent } => {
-                device.send_brightness(*percent).await?;
+                device.send_brightness(&client, *percent).await?;
             }
             SubCommand::Temperature { kelvin } => {
-                device.send_color_temperature_kelvin(*kelvin).await?;
+                device
+                    .send_color_temperature_kelvin(&client, *kelvin)
+                    .await?;
             }
             SubCommand::Color { color } => {
                 let [r, g, b, _a] = color.to_rgba8();
                 device
-                    .send_color_rgb(crate::lan_api::DeviceColor { r, g, b })
+                    .send_color_rgb(&client, DeviceColor { r, g, b })
                     .await?;
             }
             SubCommand::Scene { list, scene } => {
@@ -97,16 +115,40 @@ impl LanControlCommand {
                         let encoded =
                             Base64HexBytes::encode_for_sku("Generic:Light", code)?.base64();
                         println!("Computed {encoded:?}");
-                        device.send_real(encoded).await?;
+                        device.send_real(&client, encoded).await?;
                     } else {
                         anyhow::bail!("scene {scene} not found");
                     }
                 }
             }
+            SubCommand::Stream {
+                segments,
+                fps,
+                duration,
+            } => {
+                let stream = device.start_razer_stream(&client, *fps).await?;
+                let started = Instant::now();
+                let mut interval = tokio::time::interval(Duration::from_secs(1) / *fps);
+                while started.elapsed() < Duration::from_secs(*duration) {
+                    interval.tick().await;
+                    // One revolution of the color wheel every 5 seconds
+                    let offset = started.elapsed().as_secs_f32() * 72.;
+                    let frame = (0..*segments)
+                        .map(|n| {
+                            let hue = offset + n as f32 * 360. / *segments as f32;
+                            let [r, g, b, _a] =
+                                csscolorparser::Color::from_hsva(hue, 1., 1., 1.).to_rgba8();
+                            DeviceColor { r, g, b }
+                        })
+                        .collect();
+                    stream.send_frame(frame)?;
+                }
+                stream.stop().await?;
+            }
             SubCommand::Command { data } => {
                 let encoded = Base64HexBytes::with_bytes(data.to_vec()).base64();
                 println!("encoded: {encoded:?}");
-                device.send_real(encoded).await?;
+                device.send_real(&client, encoded).await?;
             }
         }
 
@@ -0,0 +1,106 @@
+use crate::lan_api::{CMD_PORT, LISTEN_PORT, SCAN_PORT};
+use crate::lan_sim::{LanSimulator, Quirk, SimOptions};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Emulate one or more LAN API devices, for testing without
+/// real hardware.  Point a client at them using `--scan`.
+#[derive(clap::Parser, Debug)]
+pub struct LanSimCommand {
+    /// The address of the first device. Each additional
+    /// device uses the next address, eg: 127.0.0.2
+    #[arg(long, default_value = "127.0.0.1")]
+    ip: IpAddr,
+
+    /// How many devices to emulate
+    #[arg(long, default_value_t = 1)]
+    count: usize,
+
+    /// The SKU that the devices report
+    #[arg(long, default_value = "H6072")]
+    sku: String,
+
+    /// The port on which the devices listen for scan requests
+    #[arg(long, default_value_t = SCAN_PORT)]
+    scan_port: u16,
+
+    /// The port on which the devices listen for control requests
+    #[arg(long, default_value_t = CMD_PORT)]
+    cmd_port: u16,
+
+    /// The port to which the devices send their replies
+    #[arg(long, default_value_t = LISTEN_PORT)]
+    reply_port: u16,
+
+    /// How long each device takes to act on a request, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    latency: u64,
+
+    /// The percentage of requests that are lost
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=100))]
+    loss: u8,
+
+    /// Firmware quirks to emulate. Can be specified multiple times.
+    #[arg(long, value_enum)]
+    quirk: Vec<Quirk>,
+}
+
+impl LanSimCommand {
+    pub async fn run(&self, _args: &crate::Args) -> anyhow::Result<()> {
+        let sim = LanSimulator::start(SimOptions {
+            ip: self.ip,
+            count: self.count,
+            sku: self.sku.clone(),
+            scan_port: self.scan_port,
+            cmd_port: self.cmd_port,
+            reply_port: self.reply_port,
+            latency: Duration::from_millis(self.latency),
+            loss: self.loss as f64 / 100.0,
+            quirks: self.quirk.clone(),
+        })
+        .await?;
+
+        for device in sim.devices() {
+            println!(
+                "{ip:<15} {sku:<10} {id} scan={scan} cmd={cmd}",
+                ip = device.info().ip,
+                sku = device.info().sku,
+                id = device.info().device,
+                scan = device.scan_addr(),
+                cmd = device.cmd_addr(),
+            );
+        }
+
+        // Report changes made by clients to the state of the devices
+        let mut last: Vec<_> = sim
+            .devices()
+            .iter()
+            .map(|d| (d.status(), d.real_packets().len(), d.razer_frame()))
+            .collect();
+        loop {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            for (device, (last_status, num_packets, last_frame)) in
+                sim.devices().iter().zip(last.iter_mut())
+            {
+                let status = device.status();
+                if status != *last_status {
+                    println!("{}: {status:?}", device.info().ip);
+                    *last_status = status;
+                }
+                let packets = device.real_packets();
+                for packet in &packets[*num_packets..] {
+                    println!("{}: ptReal {packet:02x?}", device.info().ip);
+                }
+                *num_packets = packets.len();
+                let frame = device.razer_frame();
+                if frame != *last_frame {
+                    match &frame {
+                        Some(colors) => println!("{}: razer {colors:?}", device.info().ip),
+                        None => println!("{}: razer disabled", device.info().ip),
+                    }
+                    *last_frame = frame;
+                }
+            }
+        }
+    }
+}
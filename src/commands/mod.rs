@@ -1,6 +1,9 @@
+pub mod diagnostics;
+pub mod healthcheck;
 pub mod http_control;
 pub mod lan_control;
 pub mod lan_disco;
+pub mod lan_sim;
 pub mod list;
 pub mod list_http;
 pub mod serve;
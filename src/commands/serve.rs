@@ -1,27 +1,111 @@
+use crate::hass_mqtt::instance::EntityInstance;
+use crate::hass_mqtt::sensor::PlatformQuotaSensor;
 use crate::lan_api::Client as LanClient;
+use crate::opt_env_var;
+use crate::platform_api::{GoveeApiClient, HttpDeviceInfo};
 use crate::service::device::Device;
+use crate::service::events::{run_history_recorder, DeviceDiscovered, ServiceEvent};
 use crate::service::hass::spawn_hass_integration;
-use crate::service::http::run_http_server;
+use crate::service::health::{Subsystem, HEALTH};
+use crate::service::http::{run_http_server, HttpArguments};
 use crate::service::iot::start_iot_client;
+use crate::service::lan_poll::periodic_lan_poll;
+use crate::service::quota::PLATFORM_QUOTA;
 use crate::service::state::StateHandle;
+use crate::service::transport::TransportArguments;
+use crate::service::transport_health::periodic_transport_probe;
+use crate::undoc_api::GoveeUndocumentedApi;
 use crate::version_info::govee_version;
 use anyhow::Context;
 use chrono::Utc;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
 use tokio::time::{sleep, Duration};
 
-pub const POLL_INTERVAL: Lazy<chrono::Duration> = Lazy::new(|| chrono::Duration::seconds(900));
+pub const POLL_INTERVAL: chrono::Duration = chrono::Duration::seconds(900);
 
 #[derive(clap::Parser, Debug)]
 pub struct ServeCommand {
-    /// The port on which the HTTP API will listen
-    #[arg(long, default_value_t = 8056)]
-    http_port: u16,
+    /// How often, in minutes, to re-query Govee's cloud APIs for the
+    /// list of devices, so that devices added to or removed from your
+    /// account are noticed without restarting. 0 disables the refresh.
+    /// You may also set this via the GOVEE_DEVICE_LIST_REFRESH_MINUTES
+    /// environment variable. If unspecified, uses 60.
+    #[arg(long)]
+    device_list_refresh_minutes: Option<u64>,
+
+    /// How often, in seconds, to query the status of devices via
+    /// the LAN API, so that changes made via the Govee app or the
+    /// device itself are noticed. 0 disables polling.
+    /// You may also set this via the GOVEE_LAN_POLL_INTERVAL
+    /// environment variable. If unspecified, uses 30.
+    #[arg(long)]
+    lan_poll_interval: Option<u64>,
+
+    #[command(flatten)]
+    transport_args: TransportArguments,
+
+    #[command(flatten)]
+    http_args: HttpArguments,
 }
 
-async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Result<()> {
+impl ServeCommand {
+    fn device_list_refresh_interval(&self) -> anyhow::Result<Option<Duration>> {
+        let minutes = match self.device_list_refresh_minutes {
+            Some(m) => m,
+            None => opt_env_var("GOVEE_DEVICE_LIST_REFRESH_MINUTES")?.unwrap_or(60),
+        };
+        Ok(if minutes == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(minutes * 60))
+        })
+    }
+
+    fn lan_poll_interval(&self) -> anyhow::Result<Option<Duration>> {
+        let seconds = match self.lan_poll_interval {
+            Some(s) => s,
+            None => opt_env_var("GOVEE_LAN_POLL_INTERVAL")?.unwrap_or(30),
+        };
+        Ok(if seconds == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(seconds))
+        })
+    }
+}
+
+/// Returns the priority with which `device` should receive a share
+/// of the Platform API polling budget, or None if polling it won't
+/// use the Platform API
+fn platform_poll_weight(device: &Device) -> Option<u32> {
+    if device.http_device_info.is_none() || device.is_ble_only_device() == Some(true) {
+        return None;
+    }
+    let needs_platform = device.needs_platform_poll();
+    if device.lan_device.is_some() && !needs_platform {
+        return None;
+    }
+
+    // Devices whose state is only available via the Platform API
+    // are more important than those for which it is a fallback
+    // from the IoT API, and devices that want to be polled more
+    // often than usual get a proportionally larger share
+    let base = if needs_platform { 2 } else { 1 };
+    let preferred = device.preferred_poll_interval().num_seconds().max(1);
+    let urgency = (POLL_INTERVAL.num_seconds() / preferred).max(1) as u32;
+    Some(base * urgency)
+}
+
+/// Poll `device` for its state, if it is due.
+/// `platform_interval` is the minimum interval between Platform API
+/// polls that fits within the daily quota, or None if the polling
+/// budget has been used up.
+async fn poll_single_device(
+    state: &StateHandle,
+    device: &Device,
+    platform_interval: Option<chrono::Duration>,
+) -> anyhow::Result<()> {
     let now = Utc::now();
 
     if device.is_ble_only_device() == Some(true) {
@@ -52,7 +136,8 @@ async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Res
 
     let needs_platform = device.needs_platform_poll();
 
-    // Don't interrogate via HTTP if we can use the LAN.
+    // Don't interrogate via HTTP if we can use the LAN; LAN devices
+    // are polled by periodic_lan_poll.
     // If we have LAN and the device is stale, it is likely
     // offline and there is little sense in burning up request
     // quota to the platform API for it
@@ -67,6 +152,17 @@ async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Res
         }
     }
 
+    let Some(platform_interval) = platform_interval else {
+        log::trace!("Platform API polling budget is exhausted; not polling {device}");
+        return Ok(());
+    };
+    if let Some(last) = &device.last_polled {
+        if now - last < platform_interval {
+            log::trace!("Deferring Platform API poll of {device} to stay within quota");
+            return Ok(());
+        }
+    }
+
     state.poll_platform_api(&device).await?;
 
     Ok(())
@@ -74,51 +170,277 @@ async fn poll_single_device(state: &StateHandle, device: &Device) -> anyhow::Res
 
 async fn periodic_state_poll(state: StateHandle) -> anyhow::Result<()> {
     sleep(Duration::from_secs(20)).await;
+    let mut budget_exhausted = false;
     loop {
-        for d in state.devices().await {
-            if let Err(err) = poll_single_device(&state, &d).await {
+        let devices = state.devices().await;
+        let quota = PLATFORM_QUOTA.snapshot();
+        let total_weight: u32 = devices.iter().filter_map(platform_poll_weight).sum();
+        let now = Utc::now();
+
+        if quota.poll_interval(1, total_weight, now).is_none() != budget_exhausted {
+            budget_exhausted = !budget_exhausted;
+            if budget_exhausted {
+                log::warn!(
+                    "Only {} of {} Platform API requests remain until {}; \
+                     pausing Platform API polling to leave room for commands",
+                    quota.remaining,
+                    quota.limit,
+                    quota.reset
+                );
+            } else {
+                log::info!("Resuming Platform API polling");
+            }
+        }
+
+        for d in devices {
+            let platform_interval = platform_poll_weight(&d)
+                .and_then(|weight| quota.poll_interval(weight, total_weight, now))
+                .map(|interval| interval.max(d.preferred_poll_interval()));
+            if let Err(err) = poll_single_device(&state, &d, platform_interval).await {
                 log::error!("while polling {d}: {err:#}");
             }
         }
 
+        if let Err(err) = PLATFORM_QUOTA.persist() {
+            log::warn!("Unable to save the Platform API quota: {err:#}");
+        }
+
+        // Availability depends on how recently we heard from each
+        // device, so re-evaluate it even if nothing has changed
+        if let Some(hass) = state.get_hass_client().await {
+            for d in state.devices().await {
+                if let Err(err) = hass.publish_device_availability(&d).await {
+                    log::error!("while publishing availability of {d}: {err:#}");
+                }
+            }
+            if state.get_platform_client().await.is_some() {
+                if let Err(err) = PlatformQuotaSensor::new().notify_state(&hass).await {
+                    log::error!("while publishing Platform API quota: {err:#}");
+                }
+            }
+        }
+
         sleep(Duration::from_secs(60)).await;
     }
 }
 
+/// Merge the platform API device list into the state.
+/// Returns the ids of the reported devices, along with the ids of
+/// the devices whose capabilities have changed since we last saw them.
+async fn refresh_platform_device_list(
+    state: &StateHandle,
+    client: &GoveeApiClient,
+) -> anyhow::Result<(HashSet<String>, HashSet<String>)> {
+    let mut ids = HashSet::new();
+    let mut changed = HashSet::new();
+    for info in client.get_devices().await? {
+        ids.insert(info.device.clone());
+        let mut device = state.device_mut(&info.sku, &info.device).await;
+        if let Some(prior) = &device.http_device_info {
+            if capabilities_changed(prior, &info) {
+                changed.insert(info.device.clone());
+            }
+        }
+        device.set_http_device_info(info);
+    }
+    Ok((ids, changed))
+}
+
+/// The capability types don't implement PartialEq, so compare
+/// their serialized form instead
+fn capabilities_changed(prior: &HttpDeviceInfo, info: &HttpDeviceInfo) -> bool {
+    serde_json::to_value(&prior.capabilities).ok() != serde_json::to_value(&info.capabilities).ok()
+}
+
+/// Merge the undocumented API device and room list into the state.
+/// Returns the ids of the reported devices.
+async fn refresh_undoc_device_list(
+    state: &StateHandle,
+    client: &GoveeUndocumentedApi,
+    token: &str,
+) -> anyhow::Result<HashSet<String>> {
+    let info = client.get_device_list(token).await?;
+    Ok(state.merge_undoc_device_list(info).await)
+}
+
+/// Re-query the cloud APIs for the device list, registering new
+/// devices with hass and removing devices that are no longer present
+async fn refresh_device_list(state: &StateHandle) -> anyhow::Result<()> {
+    let known: HashSet<String> = state.devices().await.into_iter().map(|d| d.id).collect();
+    let mut seen = HashSet::new();
+    let mut capabilities_changed = HashSet::new();
+    let mut complete = true;
+    let mut queried = false;
+
+    if let Some(client) = state.get_platform_client().await {
+        queried = true;
+        match refresh_platform_device_list(state, &client).await {
+            Ok((ids, changed)) => {
+                seen.extend(ids);
+                capabilities_changed = changed;
+            }
+            Err(err) => {
+                log::warn!("Unable to refresh platform API device list: {err:#}");
+                complete = false;
+            }
+        }
+    }
+
+    if let Some(client) = state.get_undoc_client().await {
+        queried = true;
+        let result = match client.login_account_cached().await {
+            Ok(acct) => refresh_undoc_device_list(state, &client, &acct.token).await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(ids) => seen.extend(ids),
+            Err(err) => {
+                log::warn!("Unable to refresh undocumented API device list: {err:#}");
+                complete = false;
+            }
+        }
+    }
+
+    if !queried {
+        return Ok(());
+    }
+
+    let hass = state.get_hass_client().await;
+    let mut changed = false;
+
+    for id in seen.difference(&known) {
+        let Some(device) = state.device_by_id(id).await else {
+            continue;
+        };
+        log::info!("New device {device} found in the Govee account");
+        changed = true;
+        state.publish_event(ServiceEvent::DeviceDiscovered(DeviceDiscovered {
+            device_id: device.id.clone(),
+            sku: device.sku.clone(),
+            source: "Govee account",
+            ip: device.ip_addr(),
+            timestamp: Utc::now(),
+        }));
+        if let Some(hass) = &hass {
+            if let Err(err) = hass.register_device(state, &device).await {
+                log::error!("Failed to register {device} with hass: {err:#}");
+            }
+        }
+    }
+
+    for id in capabilities_changed.intersection(&known) {
+        let Some(device) = state.device_by_id(id).await else {
+            continue;
+        };
+        log::info!("The capabilities of {device} have changed");
+        changed = true;
+        if let Some(hass) = &hass {
+            if let Err(err) = hass.register_device(state, &device).await {
+                log::error!("Failed to re-register {device} with hass: {err:#}");
+            }
+        }
+    }
+
+    for device in devices_to_remove(state.devices().await, &seen, complete) {
+        log::info!("Device {device} is no longer present in the Govee account, removing it");
+        state.remove_device(&device.id).await;
+        changed = true;
+        if let Some(hass) = &hass {
+            if let Err(err) = hass.unregister_device(state, &device).await {
+                log::error!("Failed to unregister {device} from hass: {err:#}");
+            }
+        }
+    }
+
+    // Now that we know which devices are in the account, we can
+    // tell which of the entities from a prior run are stale
+    if complete && state.set_device_list_complete().await {
+        if let Some(hass) = &hass {
+            if let Err(err) = hass.collect_stale_entities(state).await {
+                log::error!("Failed to remove stale entities from hass: {err:#}");
+            }
+        }
+    }
+
+    if changed {
+        state.persist_device_registry().await?;
+    }
+
+    Ok(())
+}
+
+/// Returns the devices that a refresh of the device list, which
+/// reported the devices in `seen`, should remove.
+/// Only remove devices if every configured API answered (`complete`);
+/// otherwise a transient outage would drop all of our devices.
+/// Devices that we've heard from via the LAN in this session are
+/// kept, even if they are not associated with the account.
+fn devices_to_remove(devices: Vec<Device>, seen: &HashSet<String>, complete: bool) -> Vec<Device> {
+    if !complete {
+        return vec![];
+    }
+    devices
+        .into_iter()
+        .filter(|device| !seen.contains(&device.id) && device.last_lan_device_update.is_none())
+        .collect()
+}
+
+async fn periodic_device_list_refresh(state: StateHandle, interval: Duration) {
+    loop {
+        sleep(interval).await;
+        if let Err(err) = refresh_device_list(&state).await {
+            log::error!("refresh_device_list: {err:#}");
+        }
+    }
+}
+
 impl ServeCommand {
     pub async fn run(&self, args: &crate::Args) -> anyhow::Result<()> {
         log::info!("Starting service. version {}", govee_version());
         let state = Arc::new(crate::service::state::State::new());
+        state
+            .set_transport_preferences(self.transport_args.transport_preferences()?)
+            .await;
 
-        // First, use the HTTP APIs to determine the list of devices and
-        // their names.
+        if let Err(err) = PLATFORM_QUOTA.load() {
+            log::warn!("Unable to load the Platform API quota: {err:#}");
+        }
 
-        if let Ok(client) = args.api_args.api_client() {
-            log::info!("Querying platform API for device list");
-            for info in client.get_devices().await? {
-                let mut device = state.device_mut(&info.sku, &info.device).await;
-                device.set_http_device_info(info);
-            }
+        // Start with the devices we knew about last time, so that
+        // LAN devices keep working even if Govee's cloud is unreachable
+        match state.restore_device_registry().await {
+            Ok(0) => {}
+            Ok(n) => log::info!("Restored {n} devices from the device registry"),
+            Err(err) => log::warn!("Unable to load the device registry: {err:#}"),
+        }
+
+        // Register those with hass right away, rather than waiting
+        // on Govee's servers; devices that we learn about below are
+        // registered by refresh_device_list
+        spawn_hass_integration(state.clone(), &args.hass_args).await?;
 
+        if let Ok(client) = args.api_args.api_client() {
             state.set_platform_client(client).await;
+        } else {
+            HEALTH.set_disabled(Subsystem::PlatformApi);
         }
         if let Ok(client) = args.undoc_args.api_client() {
-            log::info!("Querying undocumented API for device + room list");
-            let acct = client.login_account_cached().await?;
-            let info = client.get_device_list(&acct.token).await?;
-            let mut group_by_id = HashMap::new();
-            for group in info.groups {
-                group_by_id.insert(group.group_id, group.group_name);
-            }
-            for entry in info.devices {
-                let mut device = state.device_mut(&entry.sku, &entry.device).await;
-                let room_name = group_by_id.get(&entry.group_id).map(|name| name.as_str());
-                device.set_undoc_device_info(entry, room_name);
+            match client.login_account_cached().await {
+                Ok(acct) => {
+                    if let Err(err) = start_iot_client(args, state.clone(), Some(acct)).await {
+                        log::warn!("Unable to start IoT client: {err:#}");
+                        HEALTH.set_error(Subsystem::Iot, format!("{err:#}"));
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Unable to login to undocumented API: {err:#}");
+                    HEALTH.set_error(Subsystem::Iot, format!("login: {err:#}"));
+                }
             }
 
-            start_iot_client(args, state.clone(), Some(acct)).await?;
-
             state.set_undoc_client(client).await;
+        } else {
+            HEALTH.set_disabled(Subsystem::Iot);
         }
 
         // Now start discovery
@@ -134,10 +456,39 @@ impl ServeCommand {
             tokio::spawn(async move {
                 while let Some(lan_device) = scan.recv().await {
                     log::trace!("LAN disco: {lan_device:?}");
-                    state
-                        .device_mut(&lan_device.sku, &lan_device.device)
-                        .await
-                        .set_lan_device(lan_device.clone());
+                    let is_new = state.device_by_id(&lan_device.device).await.is_none();
+                    let ip_changed = {
+                        let mut device =
+                            state.device_mut(&lan_device.sku, &lan_device.device).await;
+                        let prior_ip = device.lan_device.as_ref().map(|prior| prior.ip);
+                        device.set_lan_device(lan_device.clone());
+                        if let Some(prior_ip) = prior_ip.filter(|ip| *ip != lan_device.ip) {
+                            log::info!("{} moved from {prior_ip} to {}", *device, lan_device.ip);
+                        }
+                        prior_ip != Some(lan_device.ip)
+                    };
+                    if ip_changed {
+                        state.publish_event(ServiceEvent::DeviceDiscovered(DeviceDiscovered {
+                            device_id: lan_device.device.clone(),
+                            sku: lan_device.sku.clone(),
+                            source: "LAN API",
+                            ip: Some(lan_device.ip),
+                            timestamp: Utc::now(),
+                        }));
+                        if let Err(err) = state.persist_device_registry().await {
+                            log::warn!("Unable to save the device registry: {err:#}");
+                        }
+                    }
+                    if is_new {
+                        if let (Some(hass), Some(device)) = (
+                            state.get_hass_client().await,
+                            state.device_by_id(&lan_device.device).await,
+                        ) {
+                            if let Err(err) = hass.register_device(&state, &device).await {
+                                log::error!("Failed to register {device} with hass: {err:#}");
+                            }
+                        }
+                    }
 
                     let state = state.clone();
                     let client = client.clone();
@@ -162,6 +513,16 @@ impl ServeCommand {
             // enough to provide high-signal warnings.
             log::info!("Waiting 10 seconds for LAN API discovery");
             sleep(Duration::from_secs(10)).await;
+        } else {
+            HEALTH.set_disabled(Subsystem::LanDiscovery);
+        }
+
+        // Now use the HTTP APIs to determine the list of devices and
+        // their names. This runs after LAN discovery so that devices
+        // that are only reachable via the LAN are not removed.
+        log::info!("Querying Govee's APIs for the device list");
+        if let Err(err) = refresh_device_list(&state).await {
+            log::warn!("Unable to refresh the device list: {err:#}");
         }
 
         log::info!("Devices returned from Govee's APIs");
@@ -224,6 +585,10 @@ impl ServeCommand {
             log::info!("");
         }
 
+        if let Err(err) = state.persist_device_registry().await {
+            log::warn!("Unable to save the device registry: {err:#}");
+        }
+
         // Start periodic status polling
         {
             let state = state.clone();
@@ -234,11 +599,76 @@ impl ServeCommand {
             });
         }
 
-        // start advertising on local mqtt
-        spawn_hass_integration(state.clone(), &args.hass_args).await?;
+        tokio::spawn(run_history_recorder(state.clone()));
+
+        // Start periodic LAN status polling
+        if let Some(interval) = self.lan_poll_interval()? {
+            if state.get_lan_client().await.is_some() {
+                tokio::spawn(periodic_lan_poll(state.clone(), interval));
+            }
+        }
+
+        // Start periodic device list refresh
+        if let Some(interval) = self.device_list_refresh_interval()? {
+            let state = state.clone();
+            tokio::spawn(periodic_device_list_refresh(state, interval));
+        }
+
+        // Start probing transports whose circuits have opened
+        {
+            let state = state.clone();
+            tokio::spawn(async move {
+                if let Err(err) = periodic_transport_probe(state).await {
+                    log::error!("periodic_transport_probe: {err:#}");
+                }
+            });
+        }
 
-        run_http_server(state.clone(), self.http_port)
+        run_http_server(state.clone(), &self.http_args)
             .await
-            .with_context(|| format!("Starting HTTP service on port {}", self.http_port))
+            .with_context(|| format!("Starting HTTP service on port {}", self.http_args.http_port))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn removal_requires_complete_list() {
+        let listed = Device::new("H6000", "AA:BB:CC:DD:EE:FF:00:01");
+        let gone = Device::new("H6000", "AA:BB:CC:DD:EE:FF:00:02");
+        let mut lan = Device::new("H6159", "AA:BB:CC:DD:EE:FF:00:03");
+        lan.last_lan_device_update.replace(Utc::now());
+
+        let devices = vec![listed.clone(), gone.clone(), lan];
+        let seen: HashSet<String> = [listed.id.clone()].into_iter().collect();
+
+        // One of the APIs failed to answer, so we can't tell
+        // whether the unlisted devices are really gone
+        assert!(devices_to_remove(devices.clone(), &seen, false).is_empty());
+
+        // Devices that we heard from via the LAN are kept
+        let removed: Vec<String> = devices_to_remove(devices, &seen, true)
+            .into_iter()
+            .map(|device| device.id)
+            .collect();
+        assert_eq!(removed, vec![gone.id]);
+    }
+
+    #[test]
+    fn capability_changes() {
+        let list: serde_json::Value =
+            serde_json::from_str(include_str!("../../test-data/list_devices.json")).unwrap();
+        let info: HttpDeviceInfo = serde_json::from_value(list["data"][0].clone()).unwrap();
+        assert!(!capabilities_changed(&info, &info));
+
+        let mut fewer = info.clone();
+        fewer.capabilities.pop();
+        assert!(capabilities_changed(&info, &fewer));
+
+        let mut renamed = info.clone();
+        renamed.device_name = "Lamp".to_string();
+        assert!(!capabilities_changed(&info, &renamed));
     }
 }
@@ -0,0 +1,195 @@
+use crate::lan_api::LanError;
+use crate::platform_api::{HttpRequestFailed, PlatformApiError};
+use crate::retry::is_transient_status;
+use crate::service::iot::IotError;
+use crate::service::transport::CommandError;
+use crate::undoc_api::UndocApiError;
+use reqwest::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A broad classification of a failure, which determines whether
+/// it is worth retrying, whether another transport should be tried,
+/// and how it is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// Likely to succeed if tried again later, eg: a timeout,
+    /// a lost connection or rate limiting
+    Transient,
+    /// Will fail in the same way if tried again, eg: an invalid parameter
+    Permanent,
+    /// The device, or the means used to talk to it, cannot do
+    /// what was asked
+    Unsupported,
+    /// The credentials are missing, invalid or have expired
+    Auth,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str(match self {
+            Self::Transient => "transient",
+            Self::Permanent => "permanent",
+            Self::Unsupported => "unsupported",
+            Self::Auth => "auth",
+        })
+    }
+}
+
+/// Implemented by our typed errors, so that they can be classified
+pub trait Classify {
+    fn kind(&self) -> ErrorKind;
+
+    /// How long to wait before trying again, if that is known
+    fn retry_after(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Classifies an HTTP status code
+pub fn status_kind(status: StatusCode) -> ErrorKind {
+    if is_transient_status(status) {
+        ErrorKind::Transient
+    } else if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        ErrorKind::Auth
+    } else {
+        ErrorKind::Permanent
+    }
+}
+
+impl Classify for HttpRequestFailed {
+    fn kind(&self) -> ErrorKind {
+        status_kind(self.status())
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        HttpRequestFailed::retry_after(self)
+    }
+}
+
+impl Classify for reqwest::Error {
+    fn kind(&self) -> ErrorKind {
+        if self.is_timeout() || self.is_connect() {
+            ErrorKind::Transient
+        } else if let Some(status) = self.status() {
+            status_kind(status)
+        } else {
+            ErrorKind::Permanent
+        }
+    }
+}
+
+impl Classify for mosquitto_rs::Error {
+    /// Losing the connection to the broker is transient, as the client
+    /// reconnects on its own; other failures, such as an invalid topic
+    /// or an oversized payload, are not.
+    /// The mosquitto error codes are not exported, so match on their names.
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::IO(_) => ErrorKind::Transient,
+            Self::Mosq(code) => {
+                let code = format!("{code:?}");
+                if code.contains("NO_CONN")
+                    || code.contains("CONN_LOST")
+                    || code.contains("KEEPALIVE")
+                {
+                    ErrorKind::Transient
+                } else {
+                    ErrorKind::Permanent
+                }
+            }
+            _ => ErrorKind::Permanent,
+        }
+    }
+}
+
+/// Classifies `cause`, if it is of a type that we know about
+fn classify_cause<'a>(cause: &'a (dyn std::error::Error + 'static)) -> Option<&'a dyn Classify> {
+    macro_rules! try_type {
+        ($($ty:ty),* $(,)?) => {
+            $(
+                if let Some(err) = cause.downcast_ref::<$ty>() {
+                    return Some(err);
+                }
+            )*
+        };
+    }
+    try_type!(
+        CommandError,
+        LanError,
+        IotError,
+        PlatformApiError,
+        UndocApiError,
+        HttpRequestFailed,
+        reqwest::Error,
+        mosquitto_rs::Error,
+    );
+    None
+}
+
+/// Returns the kind of `err`, as determined by the outermost error
+/// in its chain that we know how to classify, along with how long
+/// to wait before trying again, if that is known.
+/// Errors that we know nothing about are considered to be permanent.
+pub fn classify(err: &anyhow::Error) -> (ErrorKind, Option<Duration>) {
+    for cause in err.chain() {
+        if let Some(classified) = classify_cause(cause) {
+            return (classified.kind(), classified.retry_after());
+        }
+        if cause.is::<std::io::Error>() || cause.is::<tokio::time::error::Elapsed>() {
+            return (ErrorKind::Transient, None);
+        }
+    }
+    (ErrorKind::Permanent, None)
+}
+
+pub fn error_kind(err: &anyhow::Error) -> ErrorKind {
+    classify(err).0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classification() {
+        let failed = |status| -> anyhow::Error {
+            HttpRequestFailed::new(status, "oops".to_string(), None).into()
+        };
+        assert_eq!(
+            error_kind(&failed(StatusCode::BAD_GATEWAY)),
+            ErrorKind::Transient
+        );
+        assert_eq!(
+            error_kind(&failed(StatusCode::UNAUTHORIZED).context("get_devices")),
+            ErrorKind::Auth
+        );
+        assert_eq!(
+            error_kind(&failed(StatusCode::BAD_REQUEST)),
+            ErrorKind::Permanent
+        );
+        assert_eq!(
+            error_kind(&PlatformApiError::MissingCapability("brightness".to_string()).into()),
+            ErrorKind::Unsupported
+        );
+        assert_eq!(
+            error_kind(
+                &anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut))
+                    .context("send_to")
+            ),
+            ErrorKind::Transient
+        );
+        assert_eq!(
+            error_kind(&anyhow::anyhow!("something else")),
+            ErrorKind::Permanent
+        );
+
+        // An outer classification takes precedence over the cause
+        let login = UndocApiError::LoginFailed(failed(StatusCode::BAD_REQUEST));
+        assert_eq!(error_kind(&login.into()), ErrorKind::Auth);
+        let login = UndocApiError::LoginFailed(failed(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(error_kind(&login.into()), ErrorKind::Transient);
+    }
+}
@@ -1,10 +1,10 @@
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::hass_mqtt::work_mode::ParsedWorkMode;
 use crate::platform_api::DeviceType;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{
-    availability_topic, topic_safe_id, HassClient, fan_in_stabilize_window, fan_pinned_pct,
+    topic_safe_id, HassClient, fan_in_stabilize_window, fan_pinned_pct,
 };
 use crate::service::state::StateHandle;
 use async_trait::async_trait;
@@ -97,7 +97,7 @@ impl AirPurifier {
         Ok(Self {
             air_purifier: AirPurifierConfig {
                 base: EntityConfig {
-                    availability_topic: availability_topic(),
+                    availability: Availability::for_device(device),
                     name: if device.device_type() == DeviceType::AirPurifier {
                         None
                     } else {
@@ -1,5 +1,5 @@
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::topic_safe_id;
+use crate::service::hass::{availability_topic, device_availability_topic, topic_safe_id};
 use crate::version_info::govee_version;
 use serde::Serialize;
 
@@ -8,7 +8,8 @@ const URL: &str = "https://github.com/CanDuru4/govee";
 
 #[derive(Serialize, Clone, Debug, Default)]
 pub struct EntityConfig {
-    pub availability_topic: String,
+    #[serde(flatten)]
+    pub availability: Availability,
     pub name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub device_class: Option<&'static str>,
@@ -21,6 +22,50 @@ pub struct EntityConfig {
     pub icon: Option<String>,
 }
 
+/// Describes which topics hass should consult to determine
+/// whether an entity is available
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Availability {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    availability_topic: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    availability: Vec<AvailabilityTopic>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    availability_mode: Option<&'static str>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct AvailabilityTopic {
+    topic: String,
+}
+
+impl Availability {
+    /// The entity is available whenever govee2mqtt is online
+    pub fn bridge() -> Self {
+        Self {
+            availability_topic: Some(availability_topic()),
+            ..Self::default()
+        }
+    }
+
+    /// The entity is available only when both govee2mqtt and
+    /// the device itself are online
+    pub fn for_device(device: &ServiceDevice) -> Self {
+        Self {
+            availability_topic: None,
+            availability: vec![
+                AvailabilityTopic {
+                    topic: availability_topic(),
+                },
+                AvailabilityTopic {
+                    topic: device_availability_topic(device),
+                },
+            ],
+            availability_mode: Some("all"),
+        }
+    }
+}
+
 #[derive(Serialize, Clone, Debug)]
 pub struct Origin {
     pub name: &'static str,
@@ -55,6 +100,12 @@ pub struct Device {
     pub connections: Vec<(String, String)>,
 }
 
+/// The identifier that groups all of the entities of a device
+/// together in Home Assistant
+pub fn device_identifier(device: &ServiceDevice) -> String {
+    format!("gv2mqtt-{}", topic_safe_id(device))
+}
+
 impl Device {
     pub fn for_device(device: &ServiceDevice) -> Self {
         Self {
@@ -65,7 +116,7 @@ impl Device {
             suggested_area: device.room_name().map(|s| s.to_string()),
             via_device: Some("gv2mqtt".to_string()),
             identifiers: vec![
-                format!("gv2mqtt-{}", topic_safe_id(device)),
+                device_identifier(device),
                 /*
                 device.computed_name(),
                 device.id.to_string(),
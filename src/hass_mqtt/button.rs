@@ -1,9 +1,9 @@
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::platform_api::DeviceCapability;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{
-    availability_topic, camel_case_to_space_separated, topic_safe_id, topic_safe_string, HassClient,
+    camel_case_to_space_separated, topic_safe_id, topic_safe_string, HassClient,
 };
 use crate::service::state::StateHandle;
 use async_trait::async_trait;
@@ -30,7 +30,6 @@ impl ButtonConfig {
             id = topic_safe_id(device),
             inst = instance.instance
         );
-        let availability_topic = availability_topic();
         let unique_id = format!(
             "gv2mqtt-{id}-{inst}",
             id = topic_safe_id(device),
@@ -39,7 +38,7 @@ impl ButtonConfig {
 
         Ok(Self {
             base: EntityConfig {
-                availability_topic,
+                availability: Availability::for_device(device),
                 name: Some(camel_case_to_space_separated(&instance.instance)),
                 device_class: None,
                 origin: Origin::default(),
@@ -58,7 +57,7 @@ impl ButtonConfig {
         let unique_id = format!("global-{}", topic_safe_string(&name));
         Self {
             base: EntityConfig {
-                availability_topic: availability_topic(),
+                availability: Availability::bridge(),
                 name: Some(name.to_string()),
                 entity_category: None,
                 origin: Origin::default(),
@@ -91,7 +90,7 @@ impl ButtonConfig {
         );
         Self {
             base: EntityConfig {
-                availability_topic: availability_topic(),
+                availability: Availability::for_device(device),
                 name: Some(name.to_string()),
                 entity_category: None,
                 origin: Origin::default(),
@@ -116,7 +115,7 @@ impl ButtonConfig {
         );
         Self {
             base: EntityConfig {
-                availability_topic: availability_topic(),
+                availability: Availability::bridge(),
                 name: Some("Request Platform API State".to_string()),
                 entity_category: Some("diagnostic".to_string()),
                 origin: Origin::default(),
@@ -1,9 +1,9 @@
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::EntityInstance;
 use crate::hass_mqtt::number::NumberConfig;
 use crate::platform_api::{DeviceCapability, DeviceParameters};
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, topic_safe_id, topic_safe_string, HassClient};
+use crate::service::hass::{topic_safe_id, topic_safe_string, HassClient};
 use crate::service::state::StateHandle;
 use crate::temperature::{
     TemperatureScale, TemperatureUnits, TemperatureValue, DEVICE_CLASS_TEMPERATURE,
@@ -104,7 +104,7 @@ impl TargetTemperatureEntity {
         Ok(Self {
             number: NumberConfig {
                 base: EntityConfig {
-                    availability_topic: availability_topic(),
+                    availability: Availability::for_device(device),
                     name: Some(name),
                     entity_category: None,
                     origin: Origin::default(),
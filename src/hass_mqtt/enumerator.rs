@@ -1,5 +1,5 @@
 use crate::hass_mqtt::air_purifier::AirPurifier;
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::button::ButtonConfig;
 use crate::hass_mqtt::climate::TargetTemperatureEntity;
 use crate::hass_mqtt::humidifier::Humidifier;
@@ -8,12 +8,14 @@ use crate::hass_mqtt::light::DeviceLight;
 use crate::hass_mqtt::number::WorkModeNumber;
 use crate::hass_mqtt::scene::SceneConfig;
 use crate::hass_mqtt::select::{SceneModeSelect, WorkModeSelect};
-use crate::hass_mqtt::sensor::{CapabilitySensor, DeviceStatusDiagnostic, GlobalFixedDiagnostic};
+use crate::hass_mqtt::sensor::{
+    CapabilitySensor, DeviceStatusDiagnostic, GlobalFixedDiagnostic, PlatformQuotaSensor,
+};
 use crate::hass_mqtt::switch::CapabilitySwitch;
 use crate::hass_mqtt::work_mode::ParsedWorkMode;
 use crate::platform_api::{DeviceCapability, DeviceCapabilityKind, DeviceType};
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, oneclick_topic, purge_cache_topic};
+use crate::service::hass::{oneclick_topic, purge_cache_topic};
 use crate::service::state::StateHandle;
 use crate::version_info::govee_version;
 use anyhow::Context;
@@ -38,11 +40,14 @@ pub async fn enumerate_all_entites(state: &StateHandle) -> anyhow::Result<Entity
 }
 
 async fn enumerate_global_entities(
-    _state: &StateHandle,
+    state: &StateHandle,
     entities: &mut EntityList,
 ) -> anyhow::Result<()> {
     entities.add(GlobalFixedDiagnostic::new("Version", govee_version()));
     entities.add(ButtonConfig::new("Purge Caches", purge_cache_topic()));
+    if state.get_platform_client().await.is_some() {
+        entities.add(PlatformQuotaSensor::new());
+    }
     Ok(())
 }
 
@@ -57,7 +62,7 @@ async fn enumerate_scenes(state: &StateHandle, entities: &mut EntityList) -> any
                     );
                     entities.add(SceneConfig {
                         base: EntityConfig {
-                            availability_topic: availability_topic(),
+                            availability: Availability::bridge(),
                             name: Some(oc.name.to_string()),
                             entity_category: None,
                             origin: Origin::default(),
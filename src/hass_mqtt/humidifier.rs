@@ -1,10 +1,10 @@
 use crate::ble::TargetHumidity;
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::hass_mqtt::work_mode::ParsedWorkMode;
 use crate::platform_api::{DeviceParameters, DeviceType, IntegerRange};
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, topic_safe_id, HassClient, IdParameter};
+use crate::service::hass::{topic_safe_id, HassClient, IdParameter};
 use crate::service::state::StateHandle;
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -121,7 +121,7 @@ impl Humidifier {
         Ok(Self {
             humidifier: HumidifierConfig {
                 base: EntityConfig {
-                    availability_topic: availability_topic(),
+                    availability: Availability::for_device(device),
                     name: if matches!(
                         device.device_type(),
                         DeviceType::Humidifier | DeviceType::Dehumidifier
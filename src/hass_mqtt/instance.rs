@@ -1,9 +1,12 @@
+use crate::cache::write_file_atomically;
 use crate::hass_mqtt::base::EntityConfig;
-use crate::service::hass::HassClient;
+use crate::service::hass::{CapturedMessage, HassClient};
 use crate::service::state::StateHandle;
 use anyhow::Context;
 use async_trait::async_trait;
 use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 #[async_trait]
@@ -19,17 +22,74 @@ pub async fn publish_entity_config<T: Serialize>(
     base: &EntityConfig,
     config: &T,
 ) -> anyhow::Result<()> {
-    // TODO: remember all published topics for future GC
-
     let disco = state.get_hass_disco_prefix().await;
     let topic = format!(
         "{disco}/{integration}/{unique_id}/config",
         unique_id = base.unique_id
     );
 
+    // Captured configs are never published, so there
+    // is nothing for us to clean up later
+    if !client.is_capture() {
+        if let Some(identifier) = base.device.identifiers.first() {
+            state.record_hass_config_topic(identifier, &topic).await;
+        }
+    }
+
     client.publish_obj(topic, config).await
 }
 
+pub fn published_topics_file_name() -> PathBuf {
+    crate::cache::cache_dir().join("govee2mqtt-hass-topics.json")
+}
+
+/// Returns the set of discovery config topics that we recorded
+/// as published, possibly by a prior run
+pub fn load_published_topics(path: &Path) -> anyhow::Result<BTreeSet<String>> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).with_context(|| format!("parsing {path:?}")),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(err) => Err(err).with_context(|| format!("reading {path:?}")),
+    }
+}
+
+pub fn save_published_topics(path: &Path, topics: &BTreeSet<String>) -> anyhow::Result<()> {
+    let data = serde_json::to_string_pretty(topics)?;
+    write_file_atomically(path, data)
+}
+
+/// Add `topics` to those recorded in `path`, keeping any that were
+/// recorded previously, as they may still need to be garbage collected
+pub fn record_published_topics(path: &Path, topics: &BTreeSet<String>) -> anyhow::Result<()> {
+    let mut recorded = load_published_topics(path)?;
+    recorded.extend(topics.iter().cloned());
+    save_published_topics(path, &recorded)
+}
+
+/// Remove `topics`, which have been cleared, from those recorded in `path`
+pub fn forget_published_topics(path: &Path, topics: &BTreeSet<String>) -> anyhow::Result<()> {
+    let mut recorded = load_published_topics(path)?;
+    recorded.retain(|topic| !topics.contains(topic));
+    save_published_topics(path, &recorded)
+}
+
+/// Clear the discovery configs that were published previously,
+/// but which are not part of the `current` set of topics, so that
+/// hass removes the corresponding orphaned entities.
+/// The current set is then recorded in `path` for next time.
+pub async fn garbage_collect_entity_configs(
+    client: &HassClient,
+    previous: &BTreeSet<String>,
+    current: &BTreeSet<String>,
+    path: &Path,
+) -> anyhow::Result<()> {
+    for topic in previous.difference(current) {
+        log::info!("Removing stale entity config {topic}");
+        client.clear_retained(topic).await?;
+    }
+    save_published_topics(path, current)
+}
+
 #[derive(Default, Clone)]
 pub struct EntityList {
     entities: Vec<Arc<dyn EntityInstance + Send + Sync + 'static>>,
@@ -48,6 +108,21 @@ impl EntityList {
         self.entities.len()
     }
 
+    /// Returns the discovery configs that would be published
+    /// for the entities, without sending them to hass
+    pub async fn capture_config(
+        &self,
+        state: &StateHandle,
+    ) -> anyhow::Result<Vec<CapturedMessage>> {
+        let client = HassClient::capture();
+        for e in &self.entities {
+            e.publish_config(state, &client)
+                .await
+                .context("EntityList::capture_config")?;
+        }
+        Ok(client.captured())
+    }
+
     pub async fn publish_config(
         &self,
         state: &StateHandle,
@@ -73,3 +148,62 @@ impl EntityList {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn garbage_collect() {
+        let topics = |names: &[&str]| -> BTreeSet<String> {
+            names.iter().map(|name| name.to_string()).collect()
+        };
+        let previous = topics(&["hass/light/a/config", "hass/light/b/config"]);
+        let current = topics(&["hass/light/b/config", "hass/light/c/config"]);
+
+        let dir = std::env::temp_dir().join(format!("govee-hass-topics-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("topics.json");
+
+        let client = HassClient::capture();
+        garbage_collect_entity_configs(&client, &previous, &current, &path)
+            .await
+            .unwrap();
+        let saved = load_published_topics(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        let cleared: Vec<_> = client
+            .captured()
+            .into_iter()
+            .map(|msg| {
+                assert!(msg.retain);
+                assert_eq!(msg.payload, serde_json::json!(""));
+                msg.topic
+            })
+            .collect();
+        assert_eq!(cleared, vec!["hass/light/a/config"]);
+        assert_eq!(saved, current);
+    }
+
+    #[test]
+    fn record_and_forget() {
+        let topics = |names: &[&str]| -> BTreeSet<String> {
+            names.iter().map(|name| name.to_string()).collect()
+        };
+        let dir = std::env::temp_dir().join(format!("govee-hass-record-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("topics.json");
+
+        save_published_topics(&path, &topics(&["hass/light/a/config"])).unwrap();
+        record_published_topics(&path, &topics(&["hass/light/b/config"])).unwrap();
+        assert_eq!(
+            load_published_topics(&path).unwrap(),
+            topics(&["hass/light/a/config", "hass/light/b/config"])
+        );
+
+        forget_published_topics(&path, &topics(&["hass/light/a/config"])).unwrap();
+        let saved = load_published_topics(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(saved, topics(&["hass/light/b/config"]));
+    }
+}
@@ -1,10 +1,9 @@
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::platform_api::DeviceType;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{
-    availability_topic, kelvin_to_mired, light_segment_state_topic, light_state_topic,
-    topic_safe_id, HassClient,
+    kelvin_to_mired, light_segment_state_topic, light_state_topic, topic_safe_id, HassClient,
 };
 use crate::service::state::StateHandle;
 use async_trait::async_trait;
@@ -151,7 +150,6 @@ impl DeviceLight {
             Some(seg) => light_segment_state_topic(device, seg),
             None => light_state_topic(device),
         };
-        let availability_topic = availability_topic();
         let unique_id = format!(
             "gv2mqtt-{id}{seg}",
             id = topic_safe_id(device),
@@ -207,7 +205,7 @@ impl DeviceLight {
         Ok(Self {
             light: LightConfig {
                 base: EntityConfig {
-                    availability_topic,
+                    availability: Availability::for_device(device),
                     name,
                     device_class: None,
                     origin: Origin::default(),
@@ -1,7 +1,7 @@
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, topic_safe_id, topic_safe_string, HassClient};
+use crate::service::hass::{topic_safe_id, topic_safe_string, HassClient};
 use crate::service::state::StateHandle;
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -76,7 +76,6 @@ impl WorkModeNumber {
             mode = topic_safe_string(mode_name)
         );
 
-        let availability_topic = availability_topic();
         let unique_id = format!(
             "gv2mqtt-{id}-{mode}-number",
             id = topic_safe_id(device),
@@ -86,7 +85,7 @@ impl WorkModeNumber {
         Self {
             number: NumberConfig {
                 base: EntityConfig {
-                    availability_topic,
+                    availability: Availability::for_device(device),
                     name: Some(label),
                     device_class: None,
                     origin: Origin::default(),
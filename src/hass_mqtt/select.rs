@@ -1,8 +1,8 @@
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::hass_mqtt::work_mode::ParsedWorkMode;
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, topic_safe_id, HassClient, IdParameter};
+use crate::service::hass::{topic_safe_id, HassClient, IdParameter};
 use crate::service::state::StateHandle;
 use anyhow::Context;
 use axum::async_trait;
@@ -36,13 +36,12 @@ impl WorkModeSelect {
     pub fn new(device: &ServiceDevice, work_modes: &ParsedWorkMode, state: &StateHandle) -> Self {
         let command_topic = format!("gv2mqtt/{id}/set-work-mode", id = topic_safe_id(device),);
         let state_topic = format!("gv2mqtt/{id}/notify-work-mode", id = topic_safe_id(device));
-        let availability_topic = availability_topic();
         let unique_id = format!("gv2mqtt-{id}-workMode", id = topic_safe_id(device),);
 
         Self {
             select: SelectConfig {
                 base: EntityConfig {
-                    availability_topic,
+                    availability: Availability::for_device(device),
                     name: Some("Mode".to_string()),
                     device_class: None,
                     origin: Origin::default(),
@@ -115,13 +114,12 @@ impl SceneModeSelect {
 
         let command_topic = format!("gv2mqtt/{id}/set-mode-scene", id = topic_safe_id(device));
         let state_topic = format!("gv2mqtt/{id}/notify-mode-scene", id = topic_safe_id(device));
-        let availability_topic = availability_topic();
         let unique_id = format!("gv2mqtt-{id}-mode-scene", id = topic_safe_id(device));
 
         Ok(Some(Self {
             select: SelectConfig {
                 base: EntityConfig {
-                    availability_topic,
+                    availability: Availability::for_device(device),
                     name: Some("Mode/Scene".to_string()),
                     device_class: None,
                     origin: Origin::default(),
@@ -1,11 +1,12 @@
 use crate::commands::serve::POLL_INTERVAL;
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::humidifier::DEVICE_CLASS_HUMIDITY;
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::platform_api::DeviceCapability;
 use crate::service::device::Device as ServiceDevice;
-use crate::service::hass::{availability_topic, topic_safe_id, topic_safe_string, HassClient};
+use crate::service::hass::{topic_safe_id, topic_safe_string, HassClient};
 use crate::service::quirks::HumidityUnits;
+use crate::service::quota::PLATFORM_QUOTA;
 use crate::service::state::StateHandle;
 use crate::temperature::{TemperatureUnits, TemperatureValue, DEVICE_CLASS_TEMPERATURE};
 use async_trait::async_trait;
@@ -73,7 +74,7 @@ impl GlobalFixedDiagnostic {
         Self {
             sensor: SensorConfig {
                 base: EntityConfig {
-                    availability_topic: availability_topic(),
+                    availability: Availability::bridge(),
                     name: Some(name),
                     entity_category: Some("diagnostic".to_string()),
                     origin: Origin::default(),
@@ -92,6 +93,65 @@ impl GlobalFixedDiagnostic {
     }
 }
 
+/// Reports how many Platform API requests remain in the daily quota
+#[derive(Clone)]
+pub struct PlatformQuotaSensor {
+    sensor: SensorConfig,
+}
+
+impl PlatformQuotaSensor {
+    pub fn new() -> Self {
+        let unique_id = "global-platform-api-quota".to_string();
+
+        Self {
+            sensor: SensorConfig {
+                base: EntityConfig {
+                    availability: Availability::bridge(),
+                    name: Some("Platform API Quota Remaining".to_string()),
+                    entity_category: Some("diagnostic".to_string()),
+                    origin: Origin::default(),
+                    device: Device::this_service(),
+                    unique_id: unique_id.clone(),
+                    device_class: None,
+                    icon: Some("mdi:counter".to_string()),
+                },
+                state_topic: format!("gv2mqtt/sensor/{unique_id}/state"),
+                state_class: Some(StateClass::Measurement),
+                unit_of_measurement: Some("requests"),
+                json_attributes_topic: Some(format!("gv2mqtt/sensor/{unique_id}/attributes")),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl EntityInstance for PlatformQuotaSensor {
+    async fn publish_config(&self, state: &StateHandle, client: &HassClient) -> anyhow::Result<()> {
+        self.sensor.publish(state, client).await
+    }
+
+    async fn notify_state(&self, client: &HassClient) -> anyhow::Result<()> {
+        let quota = PLATFORM_QUOTA.snapshot();
+        self.sensor
+            .notify_state(client, &quota.remaining.to_string())
+            .await?;
+
+        if let Some(topic) = &self.sensor.json_attributes_topic {
+            client
+                .publish_obj(
+                    topic,
+                    &json!({
+                        "limit": quota.limit,
+                        "reset": quota.reset,
+                        "estimated": !quota.reported,
+                    }),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct CapabilitySensor {
     sensor: SensorConfig,
@@ -140,7 +200,7 @@ impl CapabilitySensor {
         Ok(Self {
             sensor: SensorConfig {
                 base: EntityConfig {
-                    availability_topic: availability_topic(),
+                    availability: Availability::for_device(device),
                     name: Some(name),
                     entity_category: Some("diagnostic".to_string()),
                     origin: Origin::default(),
@@ -238,7 +298,7 @@ impl DeviceStatusDiagnostic {
         Self {
             sensor: SensorConfig {
                 base: EntityConfig {
-                    availability_topic: availability_topic(),
+                    availability: Availability::bridge(),
                     name: Some("Status".to_string()),
                     entity_category: Some("diagnostic".to_string()),
                     origin: Origin::default(),
@@ -277,10 +337,14 @@ impl EntityInstance for DeviceStatusDiagnostic {
         let platform_metadata = &device.http_device_info;
         let platform_state = &device.http_device_state;
         let device_state = device.device_state();
+        let transports = self
+            .state
+            .transport_health_for_device(&self.device_id)
+            .await;
 
         let now = Utc::now();
 
-        let threshold = *POLL_INTERVAL + chrono::Duration::seconds(30);
+        let threshold = POLL_INTERVAL + chrono::Duration::seconds(30);
 
         let summary = match &device_state {
             Some(state) => {
@@ -300,6 +364,7 @@ impl EntityInstance for DeviceStatusDiagnostic {
             "platform_metadata": platform_metadata,
             "platform_state": platform_state,
             "overall": device_state,
+            "transports": transports,
         });
 
         self.sensor.notify_state(&client, &summary).await?;
@@ -1,10 +1,9 @@
-use crate::hass_mqtt::base::{Device, EntityConfig, Origin};
+use crate::hass_mqtt::base::{Availability, Device, EntityConfig, Origin};
 use crate::hass_mqtt::instance::{publish_entity_config, EntityInstance};
 use crate::platform_api::DeviceCapability;
 use crate::service::device::Device as ServiceDevice;
 use crate::service::hass::{
-    availability_topic, camel_case_to_space_separated, switch_instance_state_topic, topic_safe_id,
-    HassClient,
+    camel_case_to_space_separated, switch_instance_state_topic, topic_safe_id, HassClient,
 };
 use crate::service::state::StateHandle;
 use async_trait::async_trait;
@@ -30,7 +29,6 @@ impl SwitchConfig {
             inst = instance.instance
         );
         let state_topic = switch_instance_state_topic(device, &instance.instance);
-        let availability_topic = availability_topic();
         let unique_id = format!(
             "gv2mqtt-{id}-{inst}",
             id = topic_safe_id(device),
@@ -39,7 +37,7 @@ impl SwitchConfig {
 
         Ok(Self {
             base: EntityConfig {
-                availability_topic,
+                availability: Availability::for_device(device),
                 name: Some(camel_case_to_space_separated(&instance.instance)),
                 device_class: None,
                 origin: Origin::default(),
@@ -1,12 +1,12 @@
 use crate::platform_api::{DeviceCapability, DeviceParameters, EnumOption};
 use crate::service::device::Device as ServiceDevice;
 use anyhow::anyhow;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 use std::collections::BTreeMap;
 use std::ops::Range;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
 pub struct ParsedWorkMode {
     pub modes: BTreeMap<String, WorkMode>,
 }
@@ -159,7 +159,7 @@ impl ParsedWorkMode {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
 pub struct WorkMode {
     pub name: String,
     pub value: JsonValue,
@@ -169,7 +169,7 @@ pub struct WorkMode {
     pub value_range: Option<Range<i64>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct WorkModeValue {
     pub value: JsonValue,
     pub name: Option<String>,
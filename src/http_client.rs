@@ -0,0 +1,209 @@
+use crate::opt_env_var;
+use crate::platform_api::fail_if_transient;
+use crate::retry::{retry, retryable, RetryPolicy};
+use crate::service::metrics::{HTTP_REQUESTS, HTTP_REQUEST_DURATION};
+use anyhow::Context;
+use parking_lot::{const_mutex, Mutex};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The client shared by all requests to Govee's cloud services,
+/// so that connections and TLS sessions are reused
+static CLIENT: Mutex<Option<reqwest::Client>> = const_mutex(None);
+
+#[derive(clap::Parser, Debug)]
+pub struct HttpClientArguments {
+    /// How long to wait for a request to Govee's cloud services
+    /// to complete, in seconds.
+    /// You may also set GOVEE_HTTP_TIMEOUT via the environment.
+    #[arg(long, global = true)]
+    http_timeout: Option<u64>,
+
+    /// How long to wait to connect to Govee's cloud services,
+    /// in seconds.
+    /// You may also set GOVEE_HTTP_CONNECT_TIMEOUT via the environment.
+    #[arg(long, global = true)]
+    http_connect_timeout: Option<u64>,
+
+    /// The URL of an HTTP(S) proxy through which to make requests
+    /// to Govee's cloud services, eg: http://proxy.lan:3128.
+    /// You may also set GOVEE_HTTP_PROXY via the environment.
+    /// If not set, the standard HTTPS_PROXY and NO_PROXY environment
+    /// variables are respected.
+    #[arg(long, global = true)]
+    http_proxy: Option<String>,
+
+    /// The path to a PEM file containing additional CA certificates
+    /// to trust, eg: for a TLS intercepting proxy.
+    /// You may also set GOVEE_HTTP_CA_BUNDLE via the environment.
+    #[arg(long, global = true)]
+    http_ca_bundle: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpClientOptions {
+    pub timeout: Duration,
+    pub connect_timeout: Duration,
+    pub proxy: Option<String>,
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl Default for HttpClientOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            proxy: None,
+            ca_bundle: None,
+        }
+    }
+}
+
+impl HttpClientArguments {
+    pub fn to_options(&self) -> anyhow::Result<HttpClientOptions> {
+        let mut options = HttpClientOptions::default();
+
+        if let Some(secs) = self.http_timeout {
+            options.timeout = Duration::from_secs(secs);
+        } else if let Some(secs) = opt_env_var("GOVEE_HTTP_TIMEOUT")? {
+            options.timeout = Duration::from_secs(secs);
+        }
+
+        if let Some(secs) = self.http_connect_timeout {
+            options.connect_timeout = Duration::from_secs(secs);
+        } else if let Some(secs) = opt_env_var("GOVEE_HTTP_CONNECT_TIMEOUT")? {
+            options.connect_timeout = Duration::from_secs(secs);
+        }
+
+        options.proxy = match &self.http_proxy {
+            Some(proxy) => Some(proxy.clone()),
+            None => opt_env_var("GOVEE_HTTP_PROXY")?,
+        };
+
+        options.ca_bundle = match &self.http_ca_bundle {
+            Some(path) => Some(path.clone()),
+            None => opt_env_var("GOVEE_HTTP_CA_BUNDLE")?,
+        };
+
+        Ok(options)
+    }
+
+    /// Configure the shared client.  This should be called once,
+    /// before any requests are made.
+    pub fn configure(&self) -> anyhow::Result<()> {
+        let client = self.to_options()?.build_client()?;
+        CLIENT.lock().replace(client);
+        Ok(())
+    }
+}
+
+impl HttpClientOptions {
+    pub fn build_client(&self) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder()
+            .timeout(self.timeout)
+            .connect_timeout(self.connect_timeout)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .user_agent(format!(
+                "govee2mqtt/{}",
+                crate::version_info::govee_version()
+            ));
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy).with_context(|| format!("invalid proxy {proxy}"))?,
+            );
+        }
+
+        if let Some(path) = &self.ca_bundle {
+            let pem = std::fs::read(path).with_context(|| format!("reading {path:?}"))?;
+            let certs = reqwest::Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("parsing CA certificates from {path:?}"))?;
+            anyhow::ensure!(!certs.is_empty(), "no CA certificates found in {path:?}");
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        builder.build().context("building HTTP client")
+    }
+}
+
+/// Returns the shared client, building one with the default
+/// options if it has not been configured
+pub fn http_client() -> reqwest::Client {
+    CLIENT
+        .lock()
+        .get_or_insert_with(|| {
+            HttpClientOptions::default()
+                .build_client()
+                .expect("default HTTP client to build")
+        })
+        .clone()
+}
+
+/// Send `request` using the shared client, recording the outcome
+/// in the metrics for `service`
+pub async fn execute(
+    service: &str,
+    request: reqwest::Request,
+) -> reqwest::Result<reqwest::Response> {
+    let started = Instant::now();
+    let response = http_client().execute(request).await;
+    let status = match &response {
+        Ok(response) => response.status().as_u16().to_string(),
+        Err(err) if err.is_timeout() => "timeout".to_string(),
+        Err(_) => "error".to_string(),
+    };
+    HTTP_REQUESTS.inc(&[service, &status]);
+    HTTP_REQUEST_DURATION.observe(&[service], started.elapsed().as_secs_f64());
+    response
+}
+
+/// Send the request produced by `build`, retrying transient failures.
+/// The request is rebuilt for each attempt, so that any timestamp
+/// that it carries is fresh.
+/// Only use this for requests that are safe to repeat.
+pub async fn send_with_retry(
+    service: &str,
+    label: &str,
+    build: impl Fn() -> anyhow::Result<reqwest::RequestBuilder>,
+) -> anyhow::Result<reqwest::Response> {
+    retry(&RetryPolicy::CLOUD, label, retryable, || async {
+        let response = execute(service, build()?.build()?).await?;
+        fail_if_transient(response).await
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(clap::Parser, Debug)]
+    struct TestArgs {
+        #[command(flatten)]
+        http: HttpClientArguments,
+    }
+
+    #[test]
+    fn options() {
+        let args = TestArgs::parse_from([
+            "test",
+            "--http-timeout",
+            "5",
+            "--http-proxy",
+            "http://proxy.lan:3128",
+        ]);
+        let options = args.http.to_options().unwrap();
+        assert_eq!(options.timeout, Duration::from_secs(5));
+        assert_eq!(options.proxy.as_deref(), Some("http://proxy.lan:3128"));
+        options.build_client().unwrap();
+
+        let bad_proxy = HttpClientOptions {
+            proxy: Some("not a url".to_string()),
+            ..HttpClientOptions::default()
+        };
+        assert!(bad_proxy.build_client().is_err());
+    }
+}
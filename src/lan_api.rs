@@ -1,31 +1,80 @@
 use crate::ble::{Base64HexBytes, SetSceneCode};
+use crate::error::{Classify, ErrorKind};
 use crate::opt_env_var;
 use crate::platform_api::from_json;
+use crate::service::diagnostics::{record_packet, PacketDirection, PacketTransport};
+use crate::service::health::{Subsystem, HEALTH};
+use crate::service::metrics::LAN_DISCOVERY_RESPONSES;
 use crate::undoc_api::GoveeUndocumentedApi;
 use anyhow::Context;
 use if_addrs::IfAddr;
+use parking_lot::Mutex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use std::sync::Arc;
 use std::time::Duration;
+use thiserror::Error;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tokio::sync::Mutex;
-use tokio::time::Instant;
+use tokio::sync::{watch, Notify, OnceCell};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, MissedTickBehavior};
 
 // <https://app-h5.govee.com/user-manual/wlan-guide>
 
-/// The port on which govee devices listen for scan requests
-const SCAN_PORT: u16 = 4001;
-/// The port on which a client needs to listen to receive responses
-/// from govee devices
-const LISTEN_PORT: u16 = 4002;
-/// The port on which govee devices listen for control requests
-const CMD_PORT: u16 = 4003;
+/// The default port on which govee devices listen for scan requests
+pub const SCAN_PORT: u16 = 4001;
+/// The default port on which a client needs to listen to receive
+/// responses from govee devices
+pub const LISTEN_PORT: u16 = 4002;
+/// The default port on which govee devices listen for control requests
+pub const CMD_PORT: u16 = 4003;
 /// The multicast group of which govee LAN-API enabled devices are members
 const MULTICAST: IpAddr = IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250));
 
+/// The command byte of a razer packet that enables or disables
+/// real-time mode
+pub const RAZER_ENABLE: u8 = 0xb1;
+/// The command byte of a razer packet that carries a frame of colors
+pub const RAZER_FRAME: u8 = 0xb0;
+/// How often to repeat the current frame while streaming, even if
+/// it hasn't changed, so that the device stays in real-time mode
+const RAZER_KEEPALIVE: Duration = Duration::from_secs(1);
+
+/// The ways in which talking to a device via the LAN API can fail,
+/// other than an error from the network itself
+#[derive(Error, Debug)]
+pub enum LanError {
+    #[error("timed out waiting for a response from {0}")]
+    Timeout(IpAddr),
+    #[error("listener thread terminated")]
+    ListenerTerminated,
+    #[error("device {0} did not report the expected status via the LAN API")]
+    UnexpectedStatus(String),
+    #[error("{0} is not supported by the LAN API")]
+    Unsupported(String),
+    #[error("unable to set scene {scene} for {device}")]
+    UnknownScene { scene: String, device: String },
+    #[error("no lan client")]
+    NoClient,
+}
+
+impl Classify for LanError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Timeout(_) | Self::ListenerTerminated | Self::UnexpectedStatus(_) => {
+                ErrorKind::Transient
+            }
+            Self::Unsupported(_) | Self::UnknownScene { .. } | Self::NoClient => {
+                ErrorKind::Unsupported
+            }
+        }
+    }
+}
+
 #[derive(clap::Parser, Debug)]
 pub struct LanDiscoArguments {
     /// Prevent the use of the default multicast broadcast address.
@@ -54,6 +103,43 @@ pub struct LanDiscoArguments {
     /// You may also set GOVEE_LAN_DISCO_TIMEOUT via the environment.
     #[arg(long, default_value_t = 3, global = true)]
     disco_timeout: u64,
+
+    /// The address on which to listen for responses from devices.
+    /// Use `::` to listen for both IPv4 and IPv6 responses.
+    /// Defaults to `0.0.0.0`, or `::` if any of the addresses
+    /// to scan are IPv6 addresses.
+    /// You may also set GOVEE_LAN_LISTEN_ADDR via the environment.
+    #[arg(long, global = true)]
+    pub lan_listen_addr: Option<IpAddr>,
+
+    /// The name of the network interface, eg: eth0, from which to
+    /// send discovery and control packets. Use this on hosts that
+    /// are connected to more than one network.
+    /// You may also set GOVEE_LAN_INTERFACE via the environment.
+    #[arg(long, global = true)]
+    pub lan_interface: Option<String>,
+
+    /// The port on which devices listen for discovery packets.
+    /// You may also set GOVEE_LAN_SCAN_PORT via the environment.
+    #[arg(long, global = true)]
+    pub lan_scan_port: Option<u16>,
+
+    /// The port on which to listen for responses from devices.
+    /// You may also set GOVEE_LAN_LISTEN_PORT via the environment.
+    #[arg(long, global = true)]
+    pub lan_listen_port: Option<u16>,
+
+    /// The port on which devices listen for control packets.
+    /// You may also set GOVEE_LAN_CMD_PORT via the environment.
+    #[arg(long, global = true)]
+    pub lan_cmd_port: Option<u16>,
+
+    /// The minimum interval, in milliseconds, between requests sent
+    /// to the same device via the LAN API, so that rapid updates do
+    /// not overwhelm it. Defaults to 100.
+    /// You may also set GOVEE_LAN_COMMAND_INTERVAL via the environment.
+    #[arg(long, global = true)]
+    pub lan_command_interval: Option<u64>,
 }
 
 pub fn truthy(s: &str) -> anyhow::Result<bool> {
@@ -81,6 +167,7 @@ impl LanDiscoArguments {
             additional_addresses: self.scan.clone(),
             broadcast_all_interfaces: self.broadcast_all,
             global_broadcast: self.global_broadcast,
+            ..DiscoOptions::default()
         };
 
         if let Some(v) = opt_env_var::<String>("GOVEE_LAN_NO_MULTICAST")? {
@@ -105,6 +192,47 @@ impl LanDiscoArguments {
             }
         }
 
+        let listen_addr = match self.lan_listen_addr {
+            Some(addr) => Some(addr),
+            None => opt_env_var("GOVEE_LAN_LISTEN_ADDR")?,
+        };
+        options.listen_addr = listen_addr.unwrap_or_else(|| {
+            if options.additional_addresses.iter().any(IpAddr::is_ipv6) {
+                Ipv6Addr::UNSPECIFIED.into()
+            } else {
+                Ipv4Addr::UNSPECIFIED.into()
+            }
+        });
+
+        options.interface = match &self.lan_interface {
+            Some(name) => Some(name.clone()),
+            None => opt_env_var("GOVEE_LAN_INTERFACE")?,
+        };
+
+        if let Some(port) = self.lan_scan_port {
+            options.scan_port = port;
+        } else if let Some(port) = opt_env_var("GOVEE_LAN_SCAN_PORT")? {
+            options.scan_port = port;
+        }
+
+        if let Some(port) = self.lan_listen_port {
+            options.listen_port = port;
+        } else if let Some(port) = opt_env_var("GOVEE_LAN_LISTEN_PORT")? {
+            options.listen_port = port;
+        }
+
+        if let Some(port) = self.lan_cmd_port {
+            options.cmd_port = port;
+        } else if let Some(port) = opt_env_var("GOVEE_LAN_CMD_PORT")? {
+            options.cmd_port = port;
+        }
+
+        if let Some(millis) = self.lan_command_interval {
+            options.command_interval = Duration::from_millis(millis);
+        } else if let Some(millis) = opt_env_var("GOVEE_LAN_COMMAND_INTERVAL")? {
+            options.command_interval = Duration::from_millis(millis);
+        }
+
         Ok(options)
     }
 
@@ -117,6 +245,7 @@ impl LanDiscoArguments {
     }
 }
 
+#[derive(Debug, Clone)]
 pub struct DiscoOptions {
     /// Use the MULTICAST address defined in the LAN protocol
     pub enable_multicast: bool,
@@ -128,6 +257,16 @@ pub struct DiscoOptions {
     pub broadcast_all_interfaces: bool,
     /// Broadcast to the global broadcast address
     pub global_broadcast: bool,
+    /// The address on which to listen for responses
+    pub listen_addr: IpAddr,
+    /// The name of the network interface from which to send packets.
+    /// If None, the interface is chosen by the routing table.
+    pub interface: Option<String>,
+    pub scan_port: u16,
+    pub listen_port: u16,
+    pub cmd_port: u16,
+    /// The minimum interval between requests sent to the same device
+    pub command_interval: Duration,
 }
 
 impl DiscoOptions {
@@ -146,6 +285,12 @@ impl Default for DiscoOptions {
             additional_addresses: vec![],
             broadcast_all_interfaces: false,
             global_broadcast: false,
+            listen_addr: Ipv4Addr::UNSPECIFIED.into(),
+            interface: None,
+            scan_port: SCAN_PORT,
+            listen_port: LISTEN_PORT,
+            cmd_port: CMD_PORT,
+            command_interval: Duration::from_millis(100),
         }
     }
 }
@@ -169,11 +314,39 @@ pub enum Request {
     },
     #[serde(rename = "ptReal")]
     PtReal { command: Vec<String> },
+    /// Real-time streaming, as used by the screen sync feature of
+    /// Govee's desktop apps. `pt` is a base64 encoded packet produced
+    /// by `razer_packet`.
+    #[serde(rename = "razer")]
+    Razer { pt: String },
+}
+
+/// Encodes a razer packet: a 0xbb marker, the big-endian length of
+/// the payload, the command byte, the payload and an xor checksum
+fn razer_packet(command: u8, payload: &[u8]) -> anyhow::Result<String> {
+    let len = u16::try_from(payload.len()).context("razer payload is too long")?;
+    let mut packet = vec![0xbb];
+    packet.extend_from_slice(&len.to_be_bytes());
+    packet.push(command);
+    packet.extend_from_slice(payload);
+    packet.push(packet.iter().fold(0, |checksum, b| checksum ^ b));
+    Ok(data_encoding::BASE64.encode(&packet))
+}
+
+/// Encodes a frame with one color per segment
+fn razer_frame(colors: &[DeviceColor]) -> anyhow::Result<String> {
+    let count = u8::try_from(colors.len()).context("too many segments for a razer frame")?;
+    // No gradient between segments, followed by the colors
+    let mut payload = vec![0, count];
+    for color in colors {
+        payload.extend_from_slice(&[color.r, color.g, color.b]);
+    }
+    razer_packet(RAZER_FRAME, &payload)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct RequestMessage {
-    msg: Request,
+pub(crate) struct RequestMessage {
+    pub msg: Request,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, Eq, PartialEq)]
@@ -192,52 +365,58 @@ pub struct LanDevice {
 }
 
 impl LanDevice {
-    pub async fn send_request(&self, msg: Request) -> anyhow::Result<()> {
-        log::trace!("LanDevice::send_request to {:?} {msg:?}", self.ip);
-        let client = udp_socket_for_target(self.ip).await?;
-        let data = serde_json::to_string(&RequestMessage { msg })?;
-        client.send_to(data.as_bytes(), (self.ip, CMD_PORT)).await?;
-
-        Ok(())
-    }
-
-    pub async fn send_turn(&self, on: bool) -> anyhow::Result<()> {
-        self.send_request(Request::Turn {
-            value: if on { 1 } else { 0 },
-        })
-        .await
+    pub async fn send_turn(&self, client: &Client, on: bool) -> anyhow::Result<()> {
+        client
+            .send_request(
+                self,
+                Request::Turn {
+                    value: if on { 1 } else { 0 },
+                },
+            )
+            .await
     }
 
-    pub async fn send_brightness(&self, percent: u8) -> anyhow::Result<()> {
-        self.send_request(Request::Brightness { value: percent })
+    pub async fn send_brightness(&self, client: &Client, percent: u8) -> anyhow::Result<()> {
+        client
+            .send_request(self, Request::Brightness { value: percent })
             .await
     }
 
-    pub async fn send_color_rgb(&self, color: DeviceColor) -> anyhow::Result<()> {
-        self.send_request(Request::Color {
-            color,
-            color_temperature_kelvin: 0,
-        })
-        .await
+    pub async fn send_color_rgb(&self, client: &Client, color: DeviceColor) -> anyhow::Result<()> {
+        client
+            .send_request(
+                self,
+                Request::Color {
+                    color,
+                    color_temperature_kelvin: 0,
+                },
+            )
+            .await
     }
 
-    pub async fn send_real(&self, commands: Vec<String>) -> anyhow::Result<()> {
-        self.send_request(Request::PtReal { command: commands })
+    pub async fn send_real(&self, client: &Client, commands: Vec<String>) -> anyhow::Result<()> {
+        client
+            .send_request(self, Request::PtReal { command: commands })
             .await
     }
 
     pub async fn send_color_temperature_kelvin(
         &self,
+        client: &Client,
         color_temperature_kelvin: u32,
     ) -> anyhow::Result<()> {
-        self.send_request(Request::Color {
-            color: DeviceColor { r: 0, g: 0, b: 0 },
-            color_temperature_kelvin,
-        })
-        .await
+        client
+            .send_request(
+                self,
+                Request::Color {
+                    color: DeviceColor { r: 0, g: 0, b: 0 },
+                    color_temperature_kelvin,
+                },
+            )
+            .await
     }
 
-    pub async fn set_scene_by_name(&self, scene_name: &str) -> anyhow::Result<()> {
+    pub async fn set_scene_by_name(&self, client: &Client, scene_name: &str) -> anyhow::Result<()> {
         for category in GoveeUndocumentedApi::get_scenes_for_device(&self.sku).await? {
             for scene in category.scenes {
                 for effect in scene.light_effects {
@@ -251,14 +430,116 @@ impl LanDevice {
                             "sending scene packet {encoded:x?} for {scene_name}, code {}",
                             effect.scene_code
                         );
-                        return self.send_real(encoded).await;
+                        return self.send_real(client, encoded).await;
                     }
                 }
             }
         }
 
-        anyhow::bail!("unable to set scene {scene_name} for {}", self.device);
+        Err(LanError::UnknownScene {
+            scene: scene_name.to_string(),
+            device: self.device.clone(),
+        }
+        .into())
+    }
+
+    /// Enable or disable real-time mode, in which the device shows
+    /// the frames sent via razer requests rather than its own state
+    pub async fn send_razer_enable(&self, client: &Client, enable: bool) -> anyhow::Result<()> {
+        client
+            .send_request(
+                self,
+                Request::Razer {
+                    pt: razer_packet(RAZER_ENABLE, &[if enable { 1 } else { 0 }])?,
+                },
+            )
+            .await
+    }
+
+    /// Put the device into real-time mode and stream frames of
+    /// per-segment colors to it, at no more than `fps` frames per second.
+    /// Frames are sent without the usual pacing between requests,
+    /// so that the target rate can be met.
+    pub async fn start_razer_stream(
+        &self,
+        client: &Client,
+        fps: u32,
+    ) -> anyhow::Result<RazerStream> {
+        anyhow::ensure!(fps > 0, "fps must be greater than 0");
+        self.send_razer_enable(client, true).await?;
+        let (frames, rx) = watch::channel(vec![]);
+        let task = tokio::spawn(run_razer_stream(client.clone(), self.clone(), fps, rx));
+        Ok(RazerStream { frames, task })
+    }
+}
+
+/// Streams frames to a device in real-time mode.
+/// Real-time mode is disabled when the stream is stopped or dropped.
+pub struct RazerStream {
+    frames: watch::Sender<Vec<DeviceColor>>,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+
+impl RazerStream {
+    /// Show `colors`, one per segment, in the next frame.
+    /// If frames are submitted faster than the target rate,
+    /// only the most recent is sent.
+    pub fn send_frame(&self, colors: Vec<DeviceColor>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            colors.len() <= u8::MAX as usize,
+            "a frame can have at most {} segments",
+            u8::MAX
+        );
+        self.frames
+            .send(colors)
+            .map_err(|_| LanError::ListenerTerminated)?;
+        Ok(())
+    }
+
+    /// Stop streaming and take the device out of real-time mode
+    pub async fn stop(self) -> anyhow::Result<()> {
+        let Self { frames, task } = self;
+        drop(frames);
+        task.await?
+    }
+}
+
+async fn run_razer_stream(
+    client: Client,
+    device: LanDevice,
+    fps: u32,
+    mut frames: watch::Receiver<Vec<DeviceColor>>,
+) -> anyhow::Result<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / fps);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        interval.tick().await;
+        match frames.has_changed() {
+            // The stream was stopped
+            Err(_) => break,
+            Ok(true) => {}
+            Ok(false) => {
+                if last_sent.is_none_or(|last| last.elapsed() < RAZER_KEEPALIVE) {
+                    continue;
+                }
+            }
+        }
+        let frame = razer_frame(&frames.borrow_and_update())?;
+        if let Err(err) = client.send_now(&device, Request::Razer { pt: frame }).await {
+            log::warn!("razer frame to {}: {err:#}", device.ip);
+        }
+        last_sent = Some(Instant::now());
     }
+
+    client
+        .send_now(
+            &device,
+            Request::Razer {
+                pt: razer_packet(RAZER_ENABLE, &[0])?,
+            },
+        )
+        .await
 }
 
 pub fn boolean_int<'de, D: serde::de::Deserializer<'de>>(
@@ -286,7 +567,7 @@ pub struct DeviceStatus {
     pub color_temperature_kelvin: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct DeviceColor {
     pub r: u8,
     pub g: u8,
@@ -303,8 +584,8 @@ pub enum Response {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct ResponseWrapper {
-    msg: Response,
+pub(crate) struct ResponseWrapper {
+    pub msg: Response,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -313,14 +594,52 @@ pub enum AccountTopic {
     Reserve,
 }
 
-struct ClientListener {
-    addr: IpAddr,
-    tx: Sender<Response>,
+/// The addresses of the network interface selected
+/// via DiscoOptions::interface
+#[derive(Debug, Default)]
+struct InterfaceAddrs {
+    index: u32,
+    v4: Option<Ipv4Addr>,
+    v4_broadcast: Option<Ipv4Addr>,
+}
+
+impl InterfaceAddrs {
+    fn resolve(name: &str) -> anyhow::Result<Self> {
+        let mut result = None;
+        for iface in if_addrs::get_if_addrs().context("get_if_addrs")? {
+            if iface.name != name {
+                continue;
+            }
+            let result = result.get_or_insert_with(Self::default);
+            result.index = iface.index.unwrap_or(0);
+            if let IfAddr::V4(v4) = iface.addr {
+                result.v4.get_or_insert(v4.ip);
+                if result.v4_broadcast.is_none() {
+                    result.v4_broadcast = v4.broadcast;
+                }
+            }
+        }
+        let result = result.ok_or_else(|| anyhow::anyhow!("network interface {name} not found"))?;
+        log::debug!("Using network interface {name}: {result:?}");
+        Ok(result)
+    }
 }
 
-#[derive(Default)]
 struct ClientInner {
-    mux: Mutex<Vec<ClientListener>>,
+    /// Those waiting for responses, keyed by the address of the
+    /// device from which they are expecting them
+    listeners: Mutex<HashMap<IpAddr, Vec<Sender<Response>>>>,
+    /// The sockets from which requests are sent to devices,
+    /// created on first use for each address family
+    cmd_v4: OnceCell<UdpSocket>,
+    cmd_v6: OnceCell<UdpSocket>,
+    /// The earliest time at which the next request may be sent
+    /// to each device
+    next_send: Mutex<HashMap<IpAddr, Instant>>,
+    options: DiscoOptions,
+    interface: Option<InterfaceAddrs>,
+    /// Signalled to request an immediate round of discovery
+    rescan: Notify,
 }
 
 #[derive(Clone)]
@@ -328,31 +647,109 @@ pub struct Client {
     inner: Arc<ClientInner>,
 }
 
+impl ClientInner {
+    /// Bind a socket from which to send to `addr`.
+    /// IPv4 sockets are bound to the address of the selected interface,
+    /// so that packets, including broadcast and multicast packets,
+    /// leave via that interface.  IPv6 sockets rely on the scope id
+    /// applied by `target_addr` instead.
+    async fn udp_socket_for_target(&self, addr: IpAddr) -> std::io::Result<UdpSocket> {
+        match addr {
+            IpAddr::V4(_) => {
+                let source = self
+                    .interface
+                    .as_ref()
+                    .and_then(|iface| iface.v4)
+                    .unwrap_or(Ipv4Addr::UNSPECIFIED);
+                UdpSocket::bind((source, 0)).await
+            }
+            IpAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await,
+        }
+    }
+
+    /// Returns the socket address for `port` on `addr`.
+    /// IPv6 addresses are scoped to the selected interface, which is
+    /// required for link-local and multicast addresses.
+    fn target_addr(&self, addr: IpAddr, port: u16) -> SocketAddr {
+        match addr {
+            IpAddr::V4(_) => SocketAddr::new(addr, port),
+            IpAddr::V6(v6) => {
+                let scope = self
+                    .interface
+                    .as_ref()
+                    .map(|iface| iface.index)
+                    .unwrap_or(0);
+                SocketAddrV6::new(v6, port, 0, scope).into()
+            }
+        }
+    }
+
+    /// Returns the long-lived socket from which to send requests to `addr`
+    async fn cmd_socket(&self, addr: IpAddr) -> std::io::Result<&UdpSocket> {
+        let cell = match addr {
+            IpAddr::V4(_) => &self.cmd_v4,
+            IpAddr::V6(_) => &self.cmd_v6,
+        };
+        cell.get_or_try_init(|| self.udp_socket_for_target(addr))
+            .await
+    }
+
+    /// Reserve the next opportunity to send a request to `addr`,
+    /// returning when that is. Requests to the same device are spaced
+    /// apart by at least the command interval, so that a burst of
+    /// updates doesn't overwhelm it.
+    fn reserve_send_slot(&self, addr: IpAddr) -> Instant {
+        let now = Instant::now();
+        let mut next_send = self.next_send.lock();
+        let slot = match next_send.get(&addr) {
+            Some(next) if *next > now => *next,
+            _ => now,
+        };
+        next_send.insert(addr, slot + self.options.command_interval);
+        slot
+    }
+
+    /// Route `response` from `addr` to those waiting for it
+    fn dispatch(&self, addr: IpAddr, response: &Response) {
+        let mut listeners = self.listeners.lock();
+        if let Some(senders) = listeners.get_mut(&addr) {
+            senders.retain(|tx| !tx.is_closed());
+            for tx in senders.iter() {
+                // If the listener already has a response pending,
+                // it doesn't need another
+                tx.try_send(response.clone()).ok();
+            }
+            if senders.is_empty() {
+                listeners.remove(&addr);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Broadcaster {
-    addr: IpAddr,
+    addr: SocketAddr,
     socket: UdpSocket,
 }
 
-async fn udp_socket_for_target(addr: IpAddr) -> std::io::Result<UdpSocket> {
-    match addr {
-        IpAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await,
-        IpAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0)).await,
-    }
-}
-
 impl Broadcaster {
-    pub async fn new(addr: IpAddr) -> std::io::Result<Self> {
-        let socket = udp_socket_for_target(addr).await?;
+    pub async fn new(addr: IpAddr, inner: &ClientInner) -> std::io::Result<Self> {
+        let socket = inner.udp_socket_for_target(addr).await?;
 
         if addr.is_multicast() {
             match addr {
                 IpAddr::V4(v4) => {
-                    socket.join_multicast_v4(v4, Ipv4Addr::UNSPECIFIED)?;
+                    let iface = inner
+                        .interface
+                        .as_ref()
+                        .and_then(|iface| iface.v4)
+                        .unwrap_or(Ipv4Addr::UNSPECIFIED);
+                    socket.join_multicast_v4(v4, iface)?;
                     socket.set_multicast_loop_v4(false)?;
                 }
                 IpAddr::V6(v6) => {
-                    socket.join_multicast_v6(&v6, 0)?;
+                    let iface = inner.interface.as_ref().map(|iface| iface.index);
+                    socket.join_multicast_v6(&v6, iface.unwrap_or(0))?;
                     socket.set_multicast_loop_v6(false)?;
                 }
             }
@@ -360,18 +757,20 @@ impl Broadcaster {
             socket.set_broadcast(true)?;
         }
 
-        Ok(Self { addr, socket })
+        Ok(Self {
+            addr: inner.target_addr(addr, inner.options.scan_port),
+            socket,
+        })
     }
 
     pub async fn broadcast<B: AsRef<[u8]>>(&self, bytes: B) -> std::io::Result<()> {
-        self.socket
-            .send_to(bytes.as_ref(), (self.addr, SCAN_PORT))
-            .await?;
+        self.socket.send_to(bytes.as_ref(), self.addr).await?;
         Ok(())
     }
 }
 
-async fn send_scan(options: &DiscoOptions) -> anyhow::Result<()> {
+async fn send_scan(inner: &ClientInner) -> anyhow::Result<()> {
+    let options = &inner.options;
     let mut addresses = options.additional_addresses.clone();
     if options.enable_multicast {
         addresses.push(MULTICAST);
@@ -380,31 +779,37 @@ async fn send_scan(options: &DiscoOptions) -> anyhow::Result<()> {
         addresses.push(Ipv4Addr::BROADCAST.into());
     }
     if options.broadcast_all_interfaces {
-        match if_addrs::get_if_addrs() {
-            Ok(ifaces) => {
-                for iface in ifaces {
-                    if iface.is_loopback() {
-                        continue;
-                    }
-                    let bcast = match iface.addr {
-                        IfAddr::V4(v4) => v4.broadcast.map(IpAddr::V4),
-                        IfAddr::V6(v6) => v6.broadcast.map(IpAddr::V6),
-                    };
-                    if let Some(addr) = bcast {
-                        log::debug!("Adding bcast {addr} from if {}", iface.name);
-                        addresses.push(addr);
+        if let Some(iface) = &inner.interface {
+            if let Some(addr) = iface.v4_broadcast {
+                addresses.push(addr.into());
+            }
+        } else {
+            match if_addrs::get_if_addrs() {
+                Ok(ifaces) => {
+                    for iface in ifaces {
+                        if iface.is_loopback() {
+                            continue;
+                        }
+                        let bcast = match iface.addr {
+                            IfAddr::V4(v4) => v4.broadcast.map(IpAddr::V4),
+                            IfAddr::V6(v6) => v6.broadcast.map(IpAddr::V6),
+                        };
+                        if let Some(addr) = bcast {
+                            log::debug!("Adding bcast {addr} from if {}", iface.name);
+                            addresses.push(addr);
+                        }
                     }
                 }
-            }
-            Err(err) => {
-                log::error!("get_if_addrs: {err:#}");
+                Err(err) => {
+                    log::error!("get_if_addrs: {err:#}");
+                }
             }
         }
     }
 
     let mut broadcasters = vec![];
     for addr in addresses {
-        match Broadcaster::new(addr).await {
+        match Broadcaster::new(addr, inner).await {
             Ok(b) => broadcasters.push(b),
             Err(err) => {
                 log::error!("{addr}: {err:#}");
@@ -427,19 +832,23 @@ async fn send_scan(options: &DiscoOptions) -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn lan_disco(
-    options: DiscoOptions,
-    inner: Arc<ClientInner>,
-) -> anyhow::Result<Receiver<LanDevice>> {
-    let listen = UdpSocket::bind(("0.0.0.0", LISTEN_PORT)).await.context(
-        "Cannot bind to UDP Port 4002, which is required \
-        for the Govee LAN API to function. Most likely cause is that you \
-        are running another integration (perhaps `Govee LAN Control`, or \
-        `homebridge-govee`) that is already bound to that port. \
-        Both cannot run on the same machine at the same time. \
-        Consider disabling `Govee LAN Control` or setting `lanDisable` in \
-        `homebridge-govee`.",
-    )?;
+async fn lan_disco(inner: Arc<ClientInner>) -> anyhow::Result<Receiver<LanDevice>> {
+    let listen_addr = inner.options.listen_addr;
+    let port = inner.options.listen_port;
+    let listen = UdpSocket::bind((listen_addr, port))
+        .await
+        .with_context(|| {
+            format!(
+                "Cannot bind to UDP Port {port} on {listen_addr}, which is required \
+                for the Govee LAN API to function. Most likely cause is that you \
+                are running another integration (perhaps `Govee LAN Control`, or \
+                `homebridge-govee`) that is already bound to that port. \
+                Both cannot run on the same machine at the same time. \
+                Consider disabling `Govee LAN Control` or setting `lanDisable` in \
+                `homebridge-govee`, or use a different listen address."
+            )
+        })?;
+    HEALTH.set_ok(Subsystem::LanDiscovery);
     let (tx, rx) = channel(8);
 
     async fn process_packet(
@@ -448,23 +857,22 @@ async fn lan_disco(
         inner: &Arc<ClientInner>,
         tx: &Sender<LanDevice>,
     ) -> anyhow::Result<()> {
+        // IPv4 responses received via a dual-stack socket
+        // have IPv4-mapped IPv6 addresses
+        let ip = addr.ip().to_canonical();
         log::trace!(
             "process_packet: addr={addr:?} data={}",
             String::from_utf8_lossy(data)
         );
+        record_packet(PacketTransport::Lan, PacketDirection::Received, ip, data);
 
         let response: ResponseWrapper = from_json(data)
             .with_context(|| format!("Parsing: {}", String::from_utf8_lossy(data)))?;
 
-        let mut mux = inner.mux.lock().await;
-        mux.retain(|l| !l.tx.is_closed());
-        for l in mux.iter() {
-            if l.addr == addr.ip() {
-                l.tx.send(response.msg.clone()).await.ok();
-            }
-        }
+        inner.dispatch(ip, &response.msg);
 
         if let Response::Scan(info) = response.msg {
+            LAN_DISCOVERY_RESPONSES.inc(&[&info.sku]);
             tx.send(info).await?;
         }
 
@@ -472,12 +880,11 @@ async fn lan_disco(
     }
 
     async fn run_disco(
-        options: &DiscoOptions,
         listen: UdpSocket,
         tx: Sender<LanDevice>,
         inner: Arc<ClientInner>,
     ) -> anyhow::Result<()> {
-        send_scan(options).await?;
+        send_scan(&inner).await?;
 
         let mut retry_interval = Duration::from_secs(2);
         let max_retry = Duration::from_secs(60);
@@ -486,27 +893,40 @@ async fn lan_disco(
             let mut buf = [0u8; 4096];
 
             let deadline = last_send + retry_interval;
-            match tokio::time::timeout_at(deadline, listen.recv_from(&mut buf)).await {
-                Ok(Ok((len, addr))) => {
-                    if let Err(err) = process_packet(addr, &buf[0..len], &inner, &tx).await {
-                        log::error!("process_packet: {err:#}");
+            tokio::select! {
+                result = tokio::time::timeout_at(deadline, listen.recv_from(&mut buf)) => {
+                    match result {
+                        Ok(Ok((len, addr))) => {
+                            if let Err(err) = process_packet(addr, &buf[0..len], &inner, &tx).await
+                            {
+                                log::error!("process_packet: {err:#}");
+                            }
+                        }
+                        Ok(Err(err)) => {
+                            log::error!("recv_from: {err:#}");
+                        }
+                        Err(_) => {
+                            send_scan(&inner).await?;
+                            last_send = Instant::now();
+                            retry_interval = (retry_interval * 2).min(max_retry);
+                        }
                     }
                 }
-                Ok(Err(err)) => {
-                    log::error!("recv_from: {err:#}");
-                }
-                Err(_) => {
-                    send_scan(options).await?;
+                _ = inner.rescan.notified() => {
+                    // Leave the backoff alone, so that repeated requests
+                    // don't keep us scanning at the fastest rate
+                    log::debug!("Rescanning on request");
+                    send_scan(&inner).await?;
                     last_send = Instant::now();
-                    retry_interval = (retry_interval * 2).min(max_retry);
                 }
             }
         }
     }
 
     tokio::spawn(async move {
-        if let Err(err) = run_disco(&options, listen, tx, inner).await {
+        if let Err(err) = run_disco(listen, tx, inner).await {
             log::error!("Error at the disco: {err:#}");
+            HEALTH.set_error(Subsystem::LanDiscovery, format!("{err:#}"));
         }
     });
 
@@ -515,17 +935,57 @@ async fn lan_disco(
 
 impl Client {
     pub async fn new(options: DiscoOptions) -> anyhow::Result<(Self, Receiver<LanDevice>)> {
-        let inner = Arc::new(ClientInner::default());
-        let rx = lan_disco(options, Arc::clone(&inner)).await?;
+        let interface = options
+            .interface
+            .as_deref()
+            .map(InterfaceAddrs::resolve)
+            .transpose()?;
+        let inner = Arc::new(ClientInner {
+            listeners: Mutex::default(),
+            cmd_v4: OnceCell::new(),
+            cmd_v6: OnceCell::new(),
+            next_send: Mutex::default(),
+            options,
+            interface,
+            rescan: Notify::new(),
+        });
+        let rx = lan_disco(Arc::clone(&inner)).await?;
 
         Ok((Self { inner }, rx))
     }
 
-    async fn add_listener(&self, addr: IpAddr) -> anyhow::Result<Receiver<Response>> {
+    fn add_listener(&self, addr: IpAddr) -> Receiver<Response> {
         let (tx, rx) = channel(1);
-        let mut mux = self.inner.mux.lock().await;
-        mux.push(ClientListener { addr, tx });
-        Ok(rx)
+        let mut listeners = self.inner.listeners.lock();
+        let senders = listeners.entry(addr).or_default();
+        senders.retain(|tx| !tx.is_closed());
+        senders.push(tx);
+        rx
+    }
+
+    pub async fn send_request(&self, device: &LanDevice, msg: Request) -> anyhow::Result<()> {
+        tokio::time::sleep_until(self.inner.reserve_send_slot(device.ip)).await;
+        self.send_now(device, msg).await
+    }
+
+    /// Send `msg` to `device` immediately, bypassing the pacing
+    /// that `send_request` applies
+    async fn send_now(&self, device: &LanDevice, msg: Request) -> anyhow::Result<()> {
+        log::trace!("Client::send_now to {:?} {msg:?}", device.ip);
+        let data = serde_json::to_string(&RequestMessage { msg })?;
+        let socket = self.inner.cmd_socket(device.ip).await?;
+        record_packet(
+            PacketTransport::Lan,
+            PacketDirection::Sent,
+            device.ip,
+            data.as_bytes(),
+        );
+        let target = self
+            .inner
+            .target_addr(device.ip, self.inner.options.cmd_port);
+        socket.send_to(data.as_bytes(), target).await?;
+
+        Ok(())
     }
 
     /// Interrogate `addr` by sending a scan request to it.
@@ -534,9 +994,9 @@ impl Client {
     /// In addition, its details will be routed via the discovery
     /// receiver.
     pub async fn scan_ip(&self, addr: IpAddr) -> anyhow::Result<LanDevice> {
-        let mut rx = self.add_listener(addr).await?;
+        let mut rx = self.add_listener(addr);
 
-        let bcast = Broadcaster::new(addr).await?;
+        let bcast = Broadcaster::new(addr, &self.inner).await?;
         let scan = serde_json::to_string(&RequestMessage {
             msg: Request::Scan {
                 account_topic: AccountTopic::Reserve,
@@ -551,28 +1011,152 @@ impl Client {
                     return Ok(info);
                 }
                 Ok(Some(_)) => {}
-                Ok(None) => anyhow::bail!("listener thread terminated"),
-                Err(_) => anyhow::bail!("timeout waiting for response"),
+                Ok(None) => return Err(LanError::ListenerTerminated.into()),
+                Err(_) => return Err(LanError::Timeout(addr).into()),
             }
         }
     }
 
+    /// Request an immediate round of discovery, for example because a
+    /// device has stopped responding and may have a new IP address.
+    /// Responses are routed via the discovery receiver.
+    pub fn rediscover(&self) {
+        self.inner.rescan.notify_one();
+    }
+
     pub async fn query_status(&self, device: &LanDevice) -> anyhow::Result<DeviceStatus> {
-        let mut rx = self.add_listener(device.ip).await?;
-        let deadline = Instant::now() + Duration::from_secs(10);
+        self.query_status_within(device, Duration::from_secs(10))
+            .await
+    }
+
+    /// Query the status of `device`, giving up if it doesn't respond
+    /// within `timeout`
+    pub async fn query_status_within(
+        &self,
+        device: &LanDevice,
+        timeout: Duration,
+    ) -> anyhow::Result<DeviceStatus> {
+        let mut rx = self.add_listener(device.ip);
+        let deadline = Instant::now() + timeout;
         while Instant::now() <= deadline {
             log::trace!("query status of {}", device.ip);
-            device.send_request(Request::DevStatus {}).await?;
+            self.send_request(device, Request::DevStatus {}).await?;
             match tokio::time::timeout(Duration::from_millis(350), rx.recv()).await {
                 Ok(Some(Response::DevStatus(status))) => {
                     return Ok(status);
                 }
                 Ok(Some(_)) => {}
-                Ok(None) => anyhow::bail!("listener thread terminated"),
+                Ok(None) => return Err(LanError::ListenerTerminated.into()),
                 Err(_) => {}
             }
         }
 
-        anyhow::bail!("timed out waiting for status");
+        Err(LanError::Timeout(device.ip).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lan_sim::{LanSimulator, SimOptions};
+    use clap::Parser;
+
+    #[derive(clap::Parser, Debug)]
+    struct TestArgs {
+        #[command(flatten)]
+        lan: LanDiscoArguments,
+    }
+
+    #[test]
+    fn options() {
+        let args = TestArgs::parse_from(["test", "--scan", "fd00::1", "--lan-cmd-port", "14003"]);
+        let options = args.lan.to_disco_options().unwrap();
+        assert_eq!(options.listen_addr, IpAddr::from(Ipv6Addr::UNSPECIFIED));
+        assert_eq!(options.scan_port, SCAN_PORT);
+        assert_eq!(options.cmd_port, 14003);
+
+        let args = TestArgs::parse_from(["test", "--lan-listen-addr", "127.0.0.1"]);
+        let options = args.lan.to_disco_options().unwrap();
+        assert_eq!(options.listen_addr, IpAddr::from(Ipv4Addr::LOCALHOST));
+        assert_eq!(options.listen_port, LISTEN_PORT);
+    }
+
+    #[tokio::test]
+    async fn simulated_device() {
+        let (sim, client, mut scan) = LanSimulator::start_with_client(SimOptions::default())
+            .await
+            .unwrap();
+        let sim_device = &sim.devices()[0];
+
+        let device = tokio::time::timeout(Duration::from_secs(5), scan.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&device, sim_device.info());
+
+        device.send_turn(&client, false).await.unwrap();
+        device.send_brightness(&client, 25).await.unwrap();
+        let status = client.query_status(&device).await.unwrap();
+        assert!(!status.on);
+        assert_eq!(status.brightness, 25);
+
+        // Concurrent queries of the same device are each answered
+        let (a, b) = tokio::join!(client.query_status(&device), client.query_status(&device));
+        assert_eq!(a.unwrap(), b.unwrap());
+    }
+
+    #[tokio::test]
+    async fn pacing() {
+        let (sim, client, mut scan) = LanSimulator::start_with_client(SimOptions::default())
+            .await
+            .unwrap();
+        let device = tokio::time::timeout(Duration::from_secs(5), scan.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let interval = DiscoOptions::default().command_interval;
+        let started = Instant::now();
+        for percent in 1..=5 {
+            device.send_brightness(&client, percent).await.unwrap();
+        }
+        assert!(started.elapsed() >= interval * 4);
+
+        let status = client.query_status(&device).await.unwrap();
+        assert_eq!(status.brightness, 5);
+        assert_eq!(sim.devices()[0].status().brightness, 5);
+    }
+
+    #[test]
+    fn razer_packets() {
+        let packet = razer_packet(RAZER_ENABLE, &[1]).unwrap();
+        assert_eq!(
+            data_encoding::BASE64.decode(packet.as_bytes()).unwrap(),
+            [0xbb, 0x00, 0x01, 0xb1, 0x01, 0x0a]
+        );
+    }
+
+    #[tokio::test]
+    async fn razer_stream() {
+        let (sim, client, mut scan) = LanSimulator::start_with_client(SimOptions::default())
+            .await
+            .unwrap();
+        let device = tokio::time::timeout(Duration::from_secs(5), scan.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let stream = device.start_razer_stream(&client, 20).await.unwrap();
+        let frame = vec![
+            DeviceColor { r: 255, g: 0, b: 0 },
+            DeviceColor { r: 0, g: 255, b: 0 },
+        ];
+        stream.send_frame(frame.clone()).unwrap();
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(sim.devices()[0].razer_frame(), Some(frame));
+
+        stream.stop().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(sim.devices()[0].razer_frame(), None);
     }
 }
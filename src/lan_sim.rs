@@ -0,0 +1,507 @@
+use crate::lan_api::{
+    DeviceColor, DeviceStatus, LanDevice, Request, RequestMessage, Response, ResponseWrapper,
+    CMD_PORT, LISTEN_PORT, RAZER_ENABLE, RAZER_FRAME, SCAN_PORT,
+};
+use crate::platform_api::from_json;
+use anyhow::Context;
+use parking_lot::Mutex;
+use rand::Rng;
+use serde_json::json;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+/// Ways in which the firmware of real devices has been seen
+/// to deviate from the documented LAN protocol
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quirk {
+    /// Report `onOff` as a boolean rather than as 0 or 1
+    BoolOnOff,
+    /// Never answer `devStatus` requests
+    NoStatus,
+    /// Reply to the port that the request came from, rather
+    /// than to the LAN API listen port
+    ReplyToSourcePort,
+}
+
+#[derive(Debug, Clone)]
+pub struct SimOptions {
+    /// The address of the first device. Each additional device
+    /// uses the next address, eg: 127.0.0.2, so that the devices
+    /// can be told apart in the same way as on a real network
+    pub ip: IpAddr,
+    pub count: usize,
+    pub sku: String,
+    /// The port on which devices listen for scan requests.
+    /// 0 picks a free port for each device.
+    pub scan_port: u16,
+    /// The port on which devices listen for control requests.
+    /// 0 picks a free port for each device.
+    pub cmd_port: u16,
+    /// The port to which devices send their replies
+    pub reply_port: u16,
+    /// How long a device takes to act on each request
+    pub latency: Duration,
+    /// The probability, from 0.0 to 1.0, that a request is lost
+    pub loss: f64,
+    pub quirks: Vec<Quirk>,
+}
+
+impl Default for SimOptions {
+    fn default() -> Self {
+        Self {
+            ip: Ipv4Addr::LOCALHOST.into(),
+            count: 1,
+            sku: "H6072".to_string(),
+            scan_port: SCAN_PORT,
+            cmd_port: CMD_PORT,
+            reply_port: LISTEN_PORT,
+            latency: Duration::ZERO,
+            loss: 0.0,
+            quirks: vec![],
+        }
+    }
+}
+
+/// Returns the address `offset` places after `ip`
+fn nth_addr(ip: IpAddr, offset: usize) -> anyhow::Result<IpAddr> {
+    Ok(match ip {
+        IpAddr::V4(v4) => Ipv4Addr::from(
+            u32::from(v4)
+                .checked_add(offset.try_into()?)
+                .context("address out of range")?,
+        )
+        .into(),
+        IpAddr::V6(v6) => Ipv6Addr::from(
+            u128::from(v6)
+                .checked_add(offset.try_into()?)
+                .context("address out of range")?,
+        )
+        .into(),
+    })
+}
+
+#[derive(Default)]
+struct SimState {
+    status: DeviceStatus,
+    /// The packets received via ptReal requests
+    real: Vec<Vec<u8>>,
+    /// The most recent frame received while in real-time mode,
+    /// or None if real-time mode is not enabled
+    razer: Option<Vec<DeviceColor>>,
+}
+
+/// A simulated device
+pub struct SimDevice {
+    info: LanDevice,
+    scan_addr: SocketAddr,
+    cmd_addr: SocketAddr,
+    options: Arc<SimOptions>,
+    state: Mutex<SimState>,
+}
+
+impl SimDevice {
+    pub fn info(&self) -> &LanDevice {
+        &self.info
+    }
+
+    /// The address to which scan requests should be sent
+    pub fn scan_addr(&self) -> SocketAddr {
+        self.scan_addr
+    }
+
+    /// The address to which control requests should be sent
+    pub fn cmd_addr(&self) -> SocketAddr {
+        self.cmd_addr
+    }
+
+    pub fn status(&self) -> DeviceStatus {
+        self.state.lock().status.clone()
+    }
+
+    /// Returns the packets that have been received via ptReal
+    pub fn real_packets(&self) -> Vec<Vec<u8>> {
+        self.state.lock().real.clone()
+    }
+
+    /// Returns the most recent real-time frame, or None if
+    /// real-time mode is not enabled
+    pub fn razer_frame(&self) -> Option<Vec<DeviceColor>> {
+        self.state.lock().razer.clone()
+    }
+
+    fn has_quirk(&self, quirk: Quirk) -> bool {
+        self.options.quirks.contains(&quirk)
+    }
+
+    /// Applies `request` to the state of the device,
+    /// returning the reply to send, if any
+    fn handle(&self, request: Request) -> anyhow::Result<Option<String>> {
+        let mut state = self.state.lock();
+        match request {
+            Request::Scan { .. } => {
+                return Ok(Some(serde_json::to_string(&ResponseWrapper {
+                    msg: Response::Scan(self.info.clone()),
+                })?));
+            }
+            Request::DevStatus {} => {
+                if self.has_quirk(Quirk::NoStatus) {
+                    return Ok(None);
+                }
+                let status = &state.status;
+                let on = if self.has_quirk(Quirk::BoolOnOff) {
+                    json!(status.on)
+                } else {
+                    json!(if status.on { 1 } else { 0 })
+                };
+                return Ok(Some(serde_json::to_string(&json!({
+                    "msg": {
+                        "cmd": "devStatus",
+                        "data": {
+                            "onOff": on,
+                            "brightness": status.brightness,
+                            "color": status.color,
+                            "colorTemInKelvin": status.color_temperature_kelvin,
+                        }
+                    }
+                }))?));
+            }
+            Request::Turn { value } => {
+                state.status.on = value != 0;
+            }
+            Request::Brightness { value } => {
+                state.status.brightness = value.min(100);
+            }
+            Request::Color {
+                color,
+                color_temperature_kelvin,
+            } => {
+                state.status.color = color;
+                state.status.color_temperature_kelvin = color_temperature_kelvin;
+            }
+            Request::PtReal { command } => {
+                for line in command {
+                    let packet = data_encoding::BASE64
+                        .decode(line.as_bytes())
+                        .with_context(|| format!("decoding ptReal line {line}"))?;
+                    // Power is the only packet whose effect we model
+                    if let [0x33, 0x01, on, ..] = packet.as_slice() {
+                        state.status.on = *on != 0;
+                    }
+                    state.real.push(packet);
+                }
+            }
+            Request::Razer { pt } => {
+                let packet = data_encoding::BASE64
+                    .decode(pt.as_bytes())
+                    .with_context(|| format!("decoding razer packet {pt}"))?;
+                let checksum = packet.iter().fold(0, |checksum, b| checksum ^ b);
+                anyhow::ensure!(checksum == 0, "bad checksum in razer packet {packet:02x?}");
+                match packet.as_slice() {
+                    [0xbb, _, _, RAZER_ENABLE, enable, _] => {
+                        state.razer = if *enable != 0 { Some(vec![]) } else { None };
+                    }
+                    [0xbb, _, _, RAZER_FRAME, _gradient, count, colors @ .., _] => {
+                        anyhow::ensure!(
+                            colors.len() == *count as usize * 3,
+                            "razer frame {packet:02x?} has the wrong length"
+                        );
+                        // Frames are ignored unless real-time mode is enabled
+                        if let Some(frame) = &mut state.razer {
+                            *frame = colors
+                                .chunks(3)
+                                .map(|rgb| DeviceColor {
+                                    r: rgb[0],
+                                    g: rgb[1],
+                                    b: rgb[2],
+                                })
+                                .collect();
+                        }
+                    }
+                    _ => anyhow::bail!("unexpected razer packet {packet:02x?}"),
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    async fn serve(self: Arc<Self>, socket: UdpSocket) {
+        let mut buf = [0u8; 4096];
+        loop {
+            let (len, addr) = match socket.recv_from(&mut buf).await {
+                Ok(result) => result,
+                Err(err) => {
+                    log::error!("{}: recv_from: {err:#}", self.info.ip);
+                    continue;
+                }
+            };
+
+            let request: RequestMessage = match from_json(&buf[0..len]) {
+                Ok(request) => request,
+                Err(err) => {
+                    log::warn!("{}: ignoring request from {addr}: {err:#}", self.info.ip);
+                    continue;
+                }
+            };
+
+            if self.options.loss > 0.0 && rand::thread_rng().gen_bool(self.options.loss.min(1.0)) {
+                log::debug!("{}: dropping {:?} from {addr}", self.info.ip, request.msg);
+                continue;
+            }
+            log::debug!("{}: {:?} from {addr}", self.info.ip, request.msg);
+
+            tokio::time::sleep(self.options.latency).await;
+
+            let reply = match self.handle(request.msg) {
+                Ok(Some(reply)) => reply,
+                Ok(None) => continue,
+                Err(err) => {
+                    log::warn!("{}: {err:#}", self.info.ip);
+                    continue;
+                }
+            };
+            let port = if self.has_quirk(Quirk::ReplyToSourcePort) {
+                addr.port()
+            } else {
+                self.options.reply_port
+            };
+            if let Err(err) = socket.send_to(reply.as_bytes(), (addr.ip(), port)).await {
+                log::error!("{}: send_to {}:{port}: {err:#}", self.info.ip, addr.ip());
+            }
+        }
+    }
+}
+
+/// Emulates one or more devices that speak the LAN protocol,
+/// so that the LAN client can be exercised without real hardware.
+/// The devices stop when this is dropped.
+pub struct LanSimulator {
+    devices: Vec<Arc<SimDevice>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl LanSimulator {
+    pub async fn start(options: SimOptions) -> anyhow::Result<Self> {
+        let options = Arc::new(options);
+        let mut devices = vec![];
+        let mut tasks = vec![];
+
+        for n in 0..options.count {
+            let ip = nth_addr(options.ip, n)?;
+            let scan = UdpSocket::bind((ip, options.scan_port))
+                .await
+                .with_context(|| format!("binding {ip}:{}", options.scan_port))?;
+            let cmd = UdpSocket::bind((ip, options.cmd_port))
+                .await
+                .with_context(|| format!("binding {ip}:{}", options.cmd_port))?;
+
+            let device = Arc::new(SimDevice {
+                info: LanDevice {
+                    ip,
+                    device: format!("5A:1D:C0:FF:EE:00:{:02X}:{:02X}", n >> 8, n & 0xff),
+                    sku: options.sku.clone(),
+                    ble_version_hard: "3.01.01".to_string(),
+                    ble_version_soft: "1.03.01".to_string(),
+                    wifi_version_hard: "1.00.10".to_string(),
+                    wifi_version_soft: "1.02.03".to_string(),
+                },
+                scan_addr: scan.local_addr()?,
+                cmd_addr: cmd.local_addr()?,
+                options: Arc::clone(&options),
+                state: Mutex::new(SimState {
+                    status: DeviceStatus {
+                        on: true,
+                        brightness: 100,
+                        color: DeviceColor {
+                            r: 255,
+                            g: 255,
+                            b: 255,
+                        },
+                        color_temperature_kelvin: 0,
+                    },
+                    real: vec![],
+                    razer: None,
+                }),
+            });
+
+            tasks.push(tokio::spawn(Arc::clone(&device).serve(scan)));
+            tasks.push(tokio::spawn(Arc::clone(&device).serve(cmd)));
+            devices.push(device);
+        }
+
+        Ok(Self { devices, tasks })
+    }
+
+    pub fn devices(&self) -> &[Arc<SimDevice>] {
+        &self.devices
+    }
+
+    /// Start a simulated device on free ports, along with a client
+    /// that is configured to discover and talk to it
+    #[cfg(test)]
+    pub async fn start_with_client(
+        options: SimOptions,
+    ) -> anyhow::Result<(
+        Self,
+        crate::lan_api::Client,
+        tokio::sync::mpsc::Receiver<LanDevice>,
+    )> {
+        use crate::lan_api::{Client, DiscoOptions};
+
+        // Find a free port on which the client can listen
+        let listen_port = std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+        let sim = Self::start(SimOptions {
+            count: 1,
+            scan_port: 0,
+            cmd_port: 0,
+            reply_port: listen_port,
+            ..options
+        })
+        .await?;
+        let device = &sim.devices()[0];
+
+        let (client, scan) = Client::new(DiscoOptions {
+            enable_multicast: false,
+            additional_addresses: vec![device.info().ip],
+            listen_addr: Ipv4Addr::LOCALHOST.into(),
+            scan_port: device.scan_addr().port(),
+            listen_port,
+            cmd_port: device.cmd_addr().port(),
+            ..DiscoOptions::default()
+        })
+        .await?;
+
+        Ok((sim, client, scan))
+    }
+}
+
+impl Drop for LanSimulator {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lan_api::AccountTopic;
+
+    /// Send `msg` to `addr` and wait briefly for a reply
+    async fn exchange(socket: &UdpSocket, addr: SocketAddr, msg: Request) -> Option<Response> {
+        let data = serde_json::to_string(&RequestMessage { msg }).unwrap();
+        socket.send_to(data.as_bytes(), addr).await.unwrap();
+        let mut buf = [0u8; 4096];
+        let (len, _) = tokio::time::timeout(Duration::from_millis(500), socket.recv_from(&mut buf))
+            .await
+            .ok()?
+            .unwrap();
+        let reply: ResponseWrapper = from_json(&buf[0..len]).unwrap();
+        Some(reply.msg)
+    }
+
+    async fn start(quirks: Vec<Quirk>) -> (LanSimulator, UdpSocket) {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let sim = LanSimulator::start(SimOptions {
+            scan_port: 0,
+            cmd_port: 0,
+            reply_port: socket.local_addr().unwrap().port(),
+            quirks,
+            ..SimOptions::default()
+        })
+        .await
+        .unwrap();
+        (sim, socket)
+    }
+
+    #[tokio::test]
+    async fn simulated_device() {
+        let (sim, socket) = start(vec![]).await;
+        let device = &sim.devices()[0];
+
+        let scan = Request::Scan {
+            account_topic: AccountTopic::Reserve,
+        };
+        match exchange(&socket, device.scan_addr(), scan).await {
+            Some(Response::Scan(info)) => assert_eq!(&info, device.info()),
+            reply => panic!("unexpected {reply:?}"),
+        }
+
+        let cmd = device.cmd_addr();
+        assert!(exchange(&socket, cmd, Request::Brightness { value: 42 })
+            .await
+            .is_none());
+        let color = DeviceColor { r: 1, g: 2, b: 3 };
+        exchange(
+            &socket,
+            cmd,
+            Request::Color {
+                color,
+                color_temperature_kelvin: 0,
+            },
+        )
+        .await;
+        // Power off, encoded as a ptReal command
+        exchange(
+            &socket,
+            cmd,
+            Request::PtReal {
+                command: vec![data_encoding::BASE64.encode(&[0x33, 0x01, 0x00])],
+            },
+        )
+        .await;
+
+        let expect = DeviceStatus {
+            on: false,
+            brightness: 42,
+            color,
+            color_temperature_kelvin: 0,
+        };
+        match exchange(&socket, cmd, Request::DevStatus {}).await {
+            Some(Response::DevStatus(status)) => assert_eq!(status, expect),
+            reply => panic!("unexpected {reply:?}"),
+        }
+        assert_eq!(device.status(), expect);
+        assert_eq!(device.real_packets(), vec![vec![0x33, 0x01, 0x00]]);
+    }
+
+    #[tokio::test]
+    async fn quirks() {
+        let (sim, socket) = start(vec![Quirk::NoStatus]).await;
+        let cmd = sim.devices()[0].cmd_addr();
+        assert!(exchange(&socket, cmd, Request::DevStatus {})
+            .await
+            .is_none());
+
+        // Replies go to our socket even though the reply port is wrong
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let sim = LanSimulator::start(SimOptions {
+            scan_port: 0,
+            cmd_port: 0,
+            reply_port: 1,
+            quirks: vec![Quirk::BoolOnOff, Quirk::ReplyToSourcePort],
+            ..SimOptions::default()
+        })
+        .await
+        .unwrap();
+        let cmd = sim.devices()[0].cmd_addr();
+        match exchange(&socket, cmd, Request::DevStatus {}).await {
+            Some(Response::DevStatus(status)) => assert!(status.on),
+            reply => panic!("unexpected {reply:?}"),
+        }
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            nth_addr("127.0.0.1".parse().unwrap(), 2).unwrap(),
+            "127.0.0.3".parse::<IpAddr>().unwrap()
+        );
+        assert!(nth_addr("255.255.255.255".parse().unwrap(), 1).is_err());
+    }
+}
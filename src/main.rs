@@ -1,3 +1,4 @@
+use crate::http_client::HttpClientArguments;
 use crate::lan_api::LanDiscoArguments;
 use crate::platform_api::GoveeApiArguments;
 use crate::service::hass::HassArguments;
@@ -8,11 +9,15 @@ use std::str::FromStr;
 mod ble;
 mod cache;
 mod commands;
+mod error;
 mod hass_mqtt;
+mod http_client;
 mod lan_api;
+mod lan_sim;
 #[macro_use]
 mod platform_api;
 mod rest_api;
+mod retry;
 mod service;
 mod temperature;
 mod undoc_api;
@@ -29,6 +34,8 @@ pub struct Args {
     undoc_args: UndocApiArguments,
     #[command(flatten)]
     hass_args: HassArguments,
+    #[command(flatten)]
+    http_args: HttpClientArguments,
 
     #[command(subcommand)]
     cmd: SubCommand,
@@ -36,8 +43,11 @@ pub struct Args {
 
 #[derive(clap::Parser, Debug)]
 pub enum SubCommand {
+    Diagnostics(commands::diagnostics::DiagnosticsCommand),
+    HealthCheck(commands::healthcheck::HealthCheckCommand),
     LanControl(commands::lan_control::LanControlCommand),
     LanDisco(commands::lan_disco::LanDiscoCommand),
+    LanSim(commands::lan_sim::LanSimCommand),
     ListHttp(commands::list_http::ListHttpCommand),
     List(commands::list::ListCommand),
     HttpControl(commands::http_control::HttpControlCommand),
@@ -47,9 +57,13 @@ pub enum SubCommand {
 
 impl Args {
     pub async fn run(&self) -> anyhow::Result<()> {
+        self.http_args.configure()?;
         match &self.cmd {
+            SubCommand::Diagnostics(cmd) => cmd.run(self).await,
+            SubCommand::HealthCheck(cmd) => cmd.run(self).await,
             SubCommand::LanControl(cmd) => cmd.run(self).await,
             SubCommand::LanDisco(cmd) => cmd.run(self).await,
+            SubCommand::LanSim(cmd) => cmd.run(self).await,
             SubCommand::ListHttp(cmd) => cmd.run(self).await,
             SubCommand::HttpControl(cmd) => cmd.run(self).await,
             SubCommand::List(cmd) => cmd.run(self).await,
@@ -93,17 +107,23 @@ fn setup_logger() {
             use chrono::Utc;
             use std::io::Write;
 
+            let timestamp = Utc::now().with_timezone(&tz).format("%Y-%m-%dT%H:%M:%S");
+            let path = record
+                .module_path()
+                .map(|path| format!(" {path}"))
+                .unwrap_or_default();
+
+            // Retain an unstyled copy for the diagnostics bundle
+            service::diagnostics::record_log_line(format!(
+                "[{timestamp}{utc_suffix} {:<5}{path}] {}",
+                record.level(),
+                record.args()
+            ));
+
             let level_style = buf.default_level_style(record.level());
-            write!(
-                buf,
-                "[{}{utc_suffix} ",
-                Utc::now().with_timezone(&tz).format("%Y-%m-%dT%H:%M:%S")
-            )?;
+            write!(buf, "[{timestamp}{utc_suffix} ")?;
             write!(buf, "{level_style}{:<5}{level_style:#}", record.level())?;
-            if let Some(path) = record.module_path() {
-                write!(buf, " {}", path)?;
-            }
-            writeln!(buf, "] {}", record.args())
+            writeln!(buf, "{path}] {}", record.args())
         })
         .filter_level(log::LevelFilter::Info)
         .parse_env("RUST_LOG")
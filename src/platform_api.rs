@@ -1,11 +1,17 @@
 use crate::cache::{cache_get, CacheComputeResult, CacheGetOptions};
+use crate::error::{Classify, ErrorKind};
 use crate::hass_mqtt::climate::parse_temperature_constraints;
+use crate::http_client::{execute, http_client};
 use crate::opt_env_var;
+use crate::retry::{is_transient_status, parse_retry_after, retry, retryable, RetryPolicy};
+use crate::service::health::{Subsystem, HEALTH};
+use crate::service::quota::{PlatformQuota, PLATFORM_QUOTA};
 use crate::service::state::sort_and_dedup_scenes;
 use crate::temperature::{TemperatureUnits, TemperatureValue};
 use crate::undoc_api::GoveeUndocumentedApi;
 use anyhow::Context;
 use reqwest::Method;
+use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::{json, Value as JsonValue};
 use std::collections::HashMap;
@@ -361,7 +367,7 @@ impl GoveeApiClient {
                 _ => anyhow::bail!("set_scene_by_name: unexpected type {cap:#?}"),
             }
         }
-        anyhow::bail!("Scene '{scene}' is not available for this device");
+        Err(PlatformApiError::UnknownScene(scene.to_string()).into())
     }
 
     pub async fn set_target_temperature(
@@ -372,7 +378,7 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance(instance_name)
-            .ok_or_else(|| anyhow::anyhow!("device has no {instance_name}"))?;
+            .ok_or_else(|| PlatformApiError::MissingCapability(instance_name.to_string()))?;
 
         let constraints = parse_temperature_constraints(cap)?.as_unit(TemperatureUnits::Celsius);
 
@@ -403,7 +409,7 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance("workMode")
-            .ok_or_else(|| anyhow::anyhow!("device has no workMode"))?;
+            .ok_or_else(|| PlatformApiError::MissingCapability("workMode".to_string()))?;
 
         let value = json!({
             "workMode": work_mode,
@@ -421,7 +427,7 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance(instance)
-            .ok_or_else(|| anyhow::anyhow!("device has no {instance}"))?;
+            .ok_or_else(|| PlatformApiError::MissingCapability(instance.to_string()))?;
 
         let value = cap
             .enum_parameter_by_name(if on { "on" } else { "off" })
@@ -445,7 +451,7 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance("brightness")
-            .ok_or_else(|| anyhow::anyhow!("device has no brightness"))?;
+            .ok_or_else(|| PlatformApiError::MissingCapability("brightness".to_string()))?;
         let value = match &cap.parameters {
             Some(DeviceParameters::Integer {
                 range: IntegerRange { min, max, .. },
@@ -463,7 +469,7 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance("colorTemperatureK")
-            .ok_or_else(|| anyhow::anyhow!("device has no colorTemperatureK"))?;
+            .ok_or_else(|| PlatformApiError::MissingCapability("colorTemperatureK".to_string()))?;
         let value = match &cap.parameters {
             Some(DeviceParameters::Integer {
                 range: IntegerRange { min, max, .. },
@@ -483,7 +489,7 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance("colorRgb")
-            .ok_or_else(|| anyhow::anyhow!("device has no colorRgb"))?;
+            .ok_or_else(|| PlatformApiError::MissingCapability("colorRgb".to_string()))?;
         let value = ((r as u32) << 16) | ((g as u32) << 8) | (b as u32);
         self.control_device(&device, &cap, value).await
     }
@@ -498,7 +504,7 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance("segmentedColorRgb")
-            .ok_or_else(|| anyhow::anyhow!("device has no segmentedColorRgb"))?;
+            .ok_or_else(|| PlatformApiError::MissingCapability("segmentedColorRgb".to_string()))?;
         let value = ((r as u32) << 16) | ((g as u32) << 8) | (b as u32);
         self.control_device(
             &device,
@@ -519,11 +525,13 @@ impl GoveeApiClient {
     ) -> anyhow::Result<ControlDeviceResponseCapability> {
         let cap = device
             .capability_by_instance("segmentedBrightness")
-            .ok_or_else(|| anyhow::anyhow!("device has no segmentedBrightness"))?;
+            .ok_or_else(|| {
+                PlatformApiError::MissingCapability("segmentedBrightness".to_string())
+            })?;
 
-        let (min, max) = device
-            .supports_segmented_brightness()
-            .ok_or_else(|| anyhow::anyhow!("device doesnt support segmented brightness"))?;
+        let (min, max) = device.supports_segmented_brightness().ok_or_else(|| {
+            PlatformApiError::MissingCapability("segmentedBrightness".to_string())
+        })?;
 
         let value = (percent as u32).max(min).min(max);
 
@@ -812,6 +820,22 @@ impl Serialize for $name {
     }
 }
 
+// Values that we don't know about are passed through as-is,
+// so any string is acceptable
+impl schemars::JsonSchema for $name {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        stringify!($name).to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        <String as schemars::JsonSchema>::json_schema(gen)
+    }
+}
+
     }
 }
 
@@ -851,12 +875,15 @@ pub enum DeviceCapabilityKind {
 }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, JsonSchema, Debug, Clone)]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct DeviceCapability {
     #[serde(rename = "type")]
     pub kind: DeviceCapabilityKind,
     pub instance: String,
+    /// Describes the accepted value, using the format of the
+    /// Platform API; its dataType is one of ENUM, INTEGER, STRUCT or Array
+    #[schemars(with = "Option<serde_json::Map<String, JsonValue>>")]
     pub parameters: Option<DeviceParameters>,
     #[serde(rename = "alarmType")]
     pub alarm_type: Option<u32>,
@@ -916,6 +943,158 @@ impl DeviceParameters {
             _ => None,
         }
     }
+
+    /// Checks that `value` is acceptable for these parameters and
+    /// returns the value that should be sent to the device.
+    /// Enum option names are accepted in place of their values, and
+    /// omitted struct fields take their default value, if any.
+    pub fn validate_value(&self, value: &JsonValue) -> anyhow::Result<JsonValue> {
+        self.validate_value_impl(value, &[])
+    }
+
+    /// `selected` holds the names of the enum options that were chosen
+    /// by sibling struct fields; some enums, such as the `modeValue`
+    /// of a `workMode`, have options that only apply to the
+    /// correspondingly named sibling option.
+    fn validate_value_impl(
+        &self,
+        value: &JsonValue,
+        selected: &[&str],
+    ) -> anyhow::Result<JsonValue> {
+        match self {
+            Self::Enum { options } => validate_enum(options, value, selected),
+            Self::Integer { range, .. } => {
+                range.validate(value)?;
+                Ok(value.clone())
+            }
+            Self::Struct { fields } => {
+                let obj = value
+                    .as_object()
+                    .ok_or_else(|| anyhow::anyhow!("expected an object, got {value}"))?;
+                for key in obj.keys() {
+                    if !fields.iter().any(|f| f.field_name == *key) {
+                        let names: Vec<&str> =
+                            fields.iter().map(|f| f.field_name.as_str()).collect();
+                        anyhow::bail!("unknown field {key}, expected one of {names:?}");
+                    }
+                }
+
+                let selected: Vec<&str> = fields
+                    .iter()
+                    .filter_map(|f| match (&f.field_type, obj.get(&f.field_name)) {
+                        (Self::Enum { options }, Some(v)) => options
+                            .iter()
+                            .find(|opt| !opt.value.is_null() && (opt.value == *v || opt.name_is(v)))
+                            .map(|opt| opt.name.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+
+                let mut result = serde_json::Map::new();
+                for field in fields {
+                    let name = &field.field_name;
+                    let value = match obj.get(name) {
+                        Some(v) => field
+                            .field_type
+                            .validate_value_impl(v, &selected)
+                            .with_context(|| format!("field {name}"))?,
+                        None => match &field.default_value {
+                            Some(v) => v.clone(),
+                            None if field.required => {
+                                anyhow::bail!("missing required field {name}")
+                            }
+                            None => continue,
+                        },
+                    };
+                    result.insert(name.to_string(), value);
+                }
+                Ok(JsonValue::Object(result))
+            }
+            Self::Array {
+                size,
+                element_range,
+                options,
+                ..
+            } => {
+                let items = value
+                    .as_array()
+                    .ok_or_else(|| anyhow::anyhow!("expected an array, got {value}"))?;
+                if let Some(size) = size {
+                    let len = items.len() as u32;
+                    anyhow::ensure!(
+                        len >= size.min && len <= size.max,
+                        "expected between {} and {} elements, got {len}",
+                        size.min,
+                        size.max
+                    );
+                }
+                for item in items {
+                    if !options.is_empty() {
+                        anyhow::ensure!(
+                            options
+                                .iter()
+                                .any(|opt| item.as_u64() == Some(opt.value as u64)),
+                            "{item} is not one of {:?}",
+                            options.iter().map(|opt| opt.value).collect::<Vec<_>>()
+                        );
+                    } else if let Some(range) = element_range {
+                        let v = item
+                            .as_u64()
+                            .ok_or_else(|| anyhow::anyhow!("expected an integer, got {item}"))?;
+                        anyhow::ensure!(
+                            v >= range.min as u64 && v <= range.max as u64,
+                            "{v} is outside the range {}-{}",
+                            range.min,
+                            range.max
+                        );
+                    }
+                }
+                Ok(value.clone())
+            }
+        }
+    }
+}
+
+fn validate_enum(
+    options: &[EnumOption],
+    value: &JsonValue,
+    selected: &[&str],
+) -> anyhow::Result<JsonValue> {
+    for opt in options.iter().filter(|opt| !opt.value.is_null()) {
+        if opt.value == *value {
+            return Ok(value.clone());
+        }
+        if opt.name_is(value) {
+            return Ok(opt.value.clone());
+        }
+    }
+
+    // Options without a value describe the parameter that goes with
+    // the similarly named option of a sibling field
+    let groups: Vec<&EnumOption> = options.iter().filter(|opt| opt.value.is_null()).collect();
+    let candidates: Vec<&EnumOption> = match groups
+        .iter()
+        .find(|opt| selected.contains(&opt.name.as_str()))
+    {
+        Some(opt) => vec![opt],
+        None => groups,
+    };
+    let mut errors = vec![];
+    for opt in candidates {
+        match opt.validate_group_value(value) {
+            Ok(()) => return Ok(value.clone()),
+            Err(err) => errors.push(format!("{}: {err:#}", opt.name)),
+        }
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("{value} is not valid for {}", errors.join("; "));
+    }
+
+    let choices: Vec<String> = options
+        .iter()
+        .map(|opt| format!("{} ({})", opt.name, opt.value))
+        .collect();
+    anyhow::bail!("{value} is not one of {}", choices.join(", "));
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -956,6 +1135,21 @@ pub struct IntegerRange {
     pub precision: u32,
 }
 
+impl IntegerRange {
+    pub fn validate(&self, value: &JsonValue) -> anyhow::Result<()> {
+        let v = value
+            .as_i64()
+            .ok_or_else(|| anyhow::anyhow!("expected an integer, got {value}"))?;
+        anyhow::ensure!(
+            v >= self.min as i64 && v <= self.max as i64,
+            "{v} is outside the range {}-{}",
+            self.min,
+            self.max
+        );
+        Ok(())
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct EnumOption {
     pub name: String,
@@ -965,6 +1159,40 @@ pub struct EnumOption {
     pub extras: HashMap<String, JsonValue>,
 }
 
+impl EnumOption {
+    fn name_is(&self, value: &JsonValue) -> bool {
+        value
+            .as_str()
+            .map(|name| self.name.eq_ignore_ascii_case(name))
+            .unwrap_or(false)
+    }
+
+    /// Validates `value` against the nested `options` or `range`
+    /// of an option that has no value of its own
+    fn validate_group_value(&self, value: &JsonValue) -> anyhow::Result<()> {
+        if let Some(options) = self.extras.get("options").and_then(|o| o.as_array()) {
+            let values: Vec<&JsonValue> = options.iter().filter_map(|o| o.get("value")).collect();
+            anyhow::ensure!(values.contains(&value), "{value} is not one of {values:?}");
+        } else if let Some(range) = self.extras.get("range") {
+            let min = range
+                .get("min")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(i64::MIN);
+            let max = range
+                .get("max")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(i64::MAX);
+            let v = value
+                .as_i64()
+                .ok_or_else(|| anyhow::anyhow!("expected an integer, got {value}"))?;
+            anyhow::ensure!(v >= min && v <= max, "{v} is outside the range {min}-{max}");
+        } else {
+            anyhow::ensure!(value.is_i64(), "expected an integer, got {value}");
+        }
+        Ok(())
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct ArrayOption {
@@ -995,13 +1223,78 @@ struct EmbeddedRequestStatus {
 pub struct HttpRequestFailed {
     status: reqwest::StatusCode,
     content: String,
+    /// How long the server asked us to wait before retrying
+    retry_after: Option<Duration>,
 }
 
 impl HttpRequestFailed {
-    #[allow(unused)]
+    pub fn new(
+        status: reqwest::StatusCode,
+        content: String,
+        retry_after: Option<Duration>,
+    ) -> Self {
+        Self {
+            status,
+            content,
+            retry_after,
+        }
+    }
+
     pub fn from_err(err: &anyhow::Error) -> Option<&Self> {
         err.root_cause().downcast_ref::<Self>()
     }
+
+    pub fn status(&self) -> reqwest::StatusCode {
+        self.status
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    /// Returns true if the response body says that the daily
+    /// request limit was reached
+    fn is_daily_limit(&self) -> bool {
+        self.content.to_ascii_lowercase().contains("daily")
+    }
+}
+
+/// The ways in which using the Platform API can fail, other than
+/// an unsuccessful request
+#[derive(Error, Debug)]
+pub enum PlatformApiError {
+    #[error("device has no {0}")]
+    MissingCapability(String),
+    #[error("Scene '{0}' is not available for this device")]
+    UnknownScene(String),
+    #[error("{0} has no Platform API info")]
+    NoDeviceInfo(String),
+    #[error("The Platform API daily request quota is exhausted until {reset}")]
+    QuotaExhausted {
+        reset: chrono::DateTime<chrono::Utc>,
+        source: anyhow::Error,
+    },
+}
+
+impl Classify for PlatformApiError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::MissingCapability(_) | Self::UnknownScene(_) | Self::NoDeviceInfo(_) => {
+                ErrorKind::Unsupported
+            }
+            Self::QuotaExhausted { .. } => ErrorKind::Transient,
+        }
+    }
+
+    /// There is no point trying again until the quota is replenished
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::QuotaExhausted { reset, .. } => {
+                Some((*reset - chrono::Utc::now()).to_std().unwrap_or_default())
+            }
+            _ => None,
+        }
+    }
 }
 
 pub async fn json_body<T: serde::de::DeserializeOwned>(
@@ -1016,14 +1309,15 @@ pub async fn json_body<T: serde::de::DeserializeOwned>(
     if let Ok(status) = from_json::<EmbeddedRequestStatus, _>(&data) {
         if status.status != reqwest::StatusCode::OK.as_u16() {
             if let Ok(code) = reqwest::StatusCode::from_u16(status.status) {
-                return Err(HttpRequestFailed {
-                    status: code,
-                    content: format!(
+                return Err(HttpRequestFailed::new(
+                    code,
+                    format!(
                         "Request to {url} failed with code {code} {message}. Full response: {}",
                         String::from_utf8_lossy(&data),
                         message = status.message
                     ),
-                })
+                    None,
+                ))
                 .with_context(|| format!("parsing {url} response"));
             }
 
@@ -1039,6 +1333,42 @@ pub async fn json_body<T: serde::de::DeserializeOwned>(
     from_json(&data).with_context(|| format!("parsing {url} response"))
 }
 
+/// Produces the error for a response with an unsuccessful status
+async fn request_failed(response: reqwest::Response) -> anyhow::Error {
+    let url = response.url().clone();
+    let status = response.status();
+    let retry_after = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, chrono::Utc::now()));
+    match response.bytes().await {
+        Ok(body_bytes) => HttpRequestFailed::new(
+            status,
+            format!(
+                "request {url}. Response body: {}",
+                String::from_utf8_lossy(&body_bytes)
+            ),
+            retry_after,
+        )
+        .into(),
+        Err(err) => anyhow::Error::from(err).context(format!(
+            "request {url} status {}: {}, and failed to read response body",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or("")
+        )),
+    }
+}
+
+/// Turns a response whose status indicates a transient failure
+/// into an error, so that the request can be retried
+pub async fn fail_if_transient(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    if is_transient_status(response.status()) {
+        return Err(request_failed(response).await);
+    }
+    Ok(response)
+}
+
 pub async fn http_response_body<R: serde::de::DeserializeOwned>(
     response: reqwest::Response,
 ) -> anyhow::Result<R> {
@@ -1046,20 +1376,7 @@ pub async fn http_response_body<R: serde::de::DeserializeOwned>(
 
     let status = response.status();
     if !status.is_success() {
-        let body_bytes = response.bytes().await.with_context(|| {
-            format!(
-                "request {url} status {}: {}, and failed to read response body",
-                status.as_u16(),
-                status.canonical_reason().unwrap_or("")
-            )
-        })?;
-
-        anyhow::bail!(
-            "request {url} status {}: {}. Response body: {}",
-            status.as_u16(),
-            status.canonical_reason().unwrap_or(""),
-            String::from_utf8_lossy(&body_bytes)
-        );
+        return Err(request_failed(response).await);
     }
     json_body(response).await.with_context(|| {
         format!(
@@ -1070,20 +1387,91 @@ pub async fn http_response_body<R: serde::de::DeserializeOwned>(
     })
 }
 
+fn record_platform_api_request(path: &str, response: &Result<reqwest::Response, reqwest::Error>) {
+    let status = match response {
+        Ok(response) => response.status().as_u16().to_string(),
+        Err(_) => "error".to_string(),
+    };
+    crate::service::metrics::PLATFORM_API_REQUESTS.inc(&[path, &status]);
+    if let Ok(response) = response {
+        PLATFORM_QUOTA.record_response(response.headers());
+    }
+
+    // A client error, other than an authentication failure, is
+    // a problem with a specific request rather than with our
+    // ability to use the API
+    match response {
+        Ok(response)
+            if !response.status().is_server_error()
+                && response.status() != reqwest::StatusCode::UNAUTHORIZED
+                && response.status() != reqwest::StatusCode::FORBIDDEN =>
+        {
+            HEALTH.set_ok(Subsystem::PlatformApi);
+        }
+        Ok(response) => HEALTH.set_error(
+            Subsystem::PlatformApi,
+            format!("{path}: status {}", response.status()),
+        ),
+        Err(err) => HEALTH.set_error(Subsystem::PlatformApi, format!("{path}: {err}")),
+    }
+}
+
+/// Returns true if the failed request was rejected because
+/// the daily quota has run out
+fn is_quota_exhausted(failed: &HttpRequestFailed, quota: &PlatformQuota) -> bool {
+    failed.status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        && ((quota.reported && quota.remaining == 0) || failed.is_daily_limit())
+}
+
+/// A 429 status, which the API may embed in the response body rather
+/// than using the HTTP status, only means that the daily quota is
+/// exhausted if no requests remain or the body says so. Otherwise it
+/// is a short-lived rate limit, and the error is left as-is so that
+/// its Retry-After applies.
+fn check_rate_limit<R>(result: anyhow::Result<R>) -> anyhow::Result<R> {
+    let Err(err) = result else {
+        return result;
+    };
+    match HttpRequestFailed::from_err(&err) {
+        Some(failed) if is_quota_exhausted(failed, &PLATFORM_QUOTA.snapshot()) => {
+            PLATFORM_QUOTA.exhausted();
+            Err(PlatformApiError::QuotaExhausted {
+                reset: PLATFORM_QUOTA.snapshot().reset,
+                source: err,
+            }
+            .into())
+        }
+        _ => Err(err),
+    }
+}
+
+/// Send `request`, retrying transient failures. The Platform API
+/// requests that we make set absolute values, so they are safe to repeat.
+async fn execute_request<R: serde::de::DeserializeOwned>(
+    request: reqwest::Request,
+) -> anyhow::Result<R> {
+    let path = request.url().path().to_string();
+    retry(&RetryPolicy::CLOUD, &path, retryable, || async {
+        let request = request
+            .try_clone()
+            .ok_or_else(|| anyhow::anyhow!("request to {path} cannot be retried"))?;
+        let response = execute("platform", request).await;
+        record_platform_api_request(&path, &response);
+        check_rate_limit(http_response_body(response?).await)
+    })
+    .await
+}
+
 impl GoveeApiClient {
     async fn get_request_with_json_response<T: reqwest::IntoUrl, R: serde::de::DeserializeOwned>(
         &self,
         url: T,
     ) -> anyhow::Result<R> {
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()?
+        let request = http_client()
             .request(Method::GET, url)
             .header("Govee-API-Key", &self.key)
-            .send()
-            .await?;
-
-        http_response_body(response).await
+            .build()?;
+        execute_request(request).await
     }
 
     async fn request_with_json_response<
@@ -1096,16 +1484,12 @@ impl GoveeApiClient {
         url: T,
         body: &B,
     ) -> anyhow::Result<R> {
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()?
+        let request = http_client()
             .request(method, url)
             .header("Govee-API-Key", &self.key)
             .json(body)
-            .send()
-            .await?;
-
-        http_response_body(response).await
+            .build()?;
+        execute_request(request).await
     }
 }
 
@@ -1151,6 +1535,75 @@ mod test {
         k9::assert_matches_snapshot!(format!("{resp:#?}"));
     }
 
+    #[test]
+    fn validate_capability_values() {
+        let resp: GetDevicesResponse = from_json(LIST_DEVICES_EXAMPLE).unwrap();
+        let device = &resp.data[0];
+        let validate = |instance: &str, value: JsonValue| {
+            device
+                .capability_by_instance(instance)
+                .unwrap()
+                .parameters
+                .as_ref()
+                .unwrap()
+                .validate_value(&value)
+        };
+
+        k9::assert_equal!(validate("powerSwitch", json!(1)).unwrap(), json!(1));
+        k9::assert_equal!(validate("powerSwitch", json!("off")).unwrap(), json!(0));
+        assert!(validate("powerSwitch", json!(2)).is_err());
+
+        k9::assert_equal!(validate("brightness", json!(100)).unwrap(), json!(100));
+        assert!(validate("brightness", json!(0)).is_err());
+        assert!(validate("brightness", json!("50")).is_err());
+
+        k9::assert_equal!(
+            validate("segmentedColorRgb", json!({"segment": [0, 1], "rgb": 255})).unwrap(),
+            json!({"segment": [0, 1], "rgb": 255})
+        );
+        assert!(validate("segmentedColorRgb", json!({"segment": [99], "rgb": 255})).is_err());
+        assert!(validate("segmentedColorRgb", json!({"segment": [0]})).is_err());
+        assert!(validate(
+            "segmentedColorRgb",
+            json!({"segment": [0], "rgb": 1, "bogus": 1})
+        )
+        .is_err());
+
+        k9::assert_equal!(
+            validate(
+                "musicMode",
+                json!({"musicMode": "Rhythm", "sensitivity": 50, "rgb": 0})
+            )
+            .unwrap(),
+            json!({"musicMode": 3, "sensitivity": 50, "rgb": 0})
+        );
+    }
+
+    #[test]
+    fn validate_work_mode_value() {
+        let cap: DeviceCapability =
+            from_json(include_str!("../test-data/work-mode-issue-81.json")).unwrap();
+        let params = cap.parameters.unwrap();
+
+        k9::assert_equal!(
+            params
+                .validate_value(&json!({"workMode": "Manual", "modeValue": 3}))
+                .unwrap(),
+            json!({"workMode": 1, "modeValue": 3})
+        );
+        k9::assert_equal!(
+            params
+                .validate_value(&json!({"workMode": 3, "modeValue": 60}))
+                .unwrap(),
+            json!({"workMode": 3, "modeValue": 60})
+        );
+        // 60 is valid for Auto, but not for Manual
+        assert!(params
+            .validate_value(&json!({"workMode": 1, "modeValue": 60}))
+            .is_err());
+        assert!(params.validate_value(&json!({"workMode": 4})).is_err());
+    }
+
     #[test]
     fn enum_repr() {
         k9::assert_equal!(
@@ -1162,4 +1615,84 @@ mod test {
             "\"something\""
         );
     }
+
+    #[test]
+    fn quota_exhaustion() {
+        let quota = PlatformQuota {
+            limit: 10000,
+            remaining: 500,
+            reset: chrono::Utc::now(),
+            reported: true,
+        };
+        let too_many = |content: &str| {
+            HttpRequestFailed::new(
+                reqwest::StatusCode::TOO_MANY_REQUESTS,
+                content.to_string(),
+                Some(Duration::from_secs(1)),
+            )
+        };
+
+        assert!(!is_quota_exhausted(&too_many("Too Many Requests"), &quota));
+        assert!(is_quota_exhausted(
+            &too_many("API rate limit exceeded: daily limit reached"),
+            &quota
+        ));
+        assert!(is_quota_exhausted(
+            &too_many("Too Many Requests"),
+            &PlatformQuota {
+                remaining: 0,
+                ..quota.clone()
+            }
+        ));
+        assert!(!is_quota_exhausted(
+            &too_many("Too Many Requests"),
+            &PlatformQuota {
+                remaining: 0,
+                reported: false,
+                ..quota.clone()
+            }
+        ));
+        assert!(!is_quota_exhausted(
+            &HttpRequestFailed::new(reqwest::StatusCode::BAD_REQUEST, "daily".to_string(), None),
+            &quota
+        ));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_is_retried() {
+        use axum::response::IntoResponse;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/router/api/v1/user/devices",
+            axum::routing::get({
+                let attempts = attempts.clone();
+                move || async move {
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        (
+                            reqwest::StatusCode::TOO_MANY_REQUESTS,
+                            [(reqwest::header::RETRY_AFTER, "1")],
+                            "Too Many Requests",
+                        )
+                            .into_response()
+                    } else {
+                        axum::Json(serde_json::json!({"code": 200})).into_response()
+                    }
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let request = http_client()
+            .get(format!("http://{addr}/router/api/v1/user/devices"))
+            .build()
+            .unwrap();
+        let response: serde_json::Value = execute_request(request).await.unwrap();
+        assert_eq!(response, serde_json::json!({"code": 200}));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
 }
@@ -1,4 +1,5 @@
 use crate::cache::{cache_get, CacheComputeResult, CacheGetOptions};
+use crate::http_client::{http_client, send_with_retry};
 use crate::platform_api::{http_response_body, ONE_WEEK};
 use reqwest::Method;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -142,13 +143,13 @@ impl RestApiClient {
         &self,
         url: T,
     ) -> anyhow::Result<R> {
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()?
-            .request(Method::GET, url)
-            .header("Govee-API-Key", &self.key)
-            .send()
-            .await?;
+        let url = url.into_url()?;
+        let response = send_with_retry("rest", url.path(), || {
+            Ok(http_client()
+                .request(Method::GET, url.clone())
+                .header("Govee-API-Key", &self.key))
+        })
+        .await?;
 
         http_response_body(response).await
     }
@@ -163,14 +164,14 @@ impl RestApiClient {
         url: T,
         body: &B,
     ) -> anyhow::Result<R> {
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(60))
-            .build()?
-            .request(method, url)
-            .header("Govee-API-Key", &self.key)
-            .json(body)
-            .send()
-            .await?;
+        let url = url.into_url()?;
+        let response = send_with_retry("rest", url.path(), || {
+            Ok(http_client()
+                .request(method.clone(), url.clone())
+                .header("Govee-API-Key", &self.key)
+                .json(body))
+        })
+        .await?;
 
         http_response_body(response).await
     }
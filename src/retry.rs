@@ -0,0 +1,246 @@
+use crate::error::{classify, ErrorKind};
+use crate::service::metrics::RETRIES;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::StatusCode;
+use std::future::Future;
+use std::time::Duration;
+
+/// How a failed attempt should be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retryable {
+    /// The failure is not transient, eg: the request was invalid
+    /// or unauthorized, so don't retry
+    No,
+    /// Retry after backing off
+    Yes,
+    /// Retry after the delay that the server asked for
+    After(Duration),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Including the first attempt
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    /// The longest that we will wait between attempts.  If the server
+    /// asks us to wait longer than this, we give up instead.
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// For requests to Govee's cloud services, which are typically
+    /// made on behalf of someone waiting for a device to respond
+    pub const CLOUD: Self = Self {
+        max_attempts: 3,
+        initial_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(10),
+    };
+
+    /// Exponential backoff, with jitter so that concurrent requests
+    /// that failed together don't retry together
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .initial_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        rand::thread_rng().gen_range(ceiling / 2..=ceiling)
+    }
+}
+
+/// Run `operation`, retrying it according to `policy` for as long as
+/// `classify` considers its error to be transient.
+/// Only use this for operations that are safe to repeat.
+pub async fn retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    label: &str,
+    classify: impl Fn(&anyhow::Error) -> Retryable,
+    mut operation: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        let err = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(err) => err,
+        };
+        if attempt >= policy.max_attempts {
+            return Err(err);
+        }
+        let delay = match classify(&err) {
+            Retryable::No => return Err(err),
+            Retryable::Yes => policy.backoff(attempt),
+            Retryable::After(delay) if delay > policy.max_delay => return Err(err),
+            Retryable::After(delay) => delay,
+        };
+        log::warn!(
+            "{label}: attempt {attempt} of {} failed, retrying in {delay:?}: {err:#}",
+            policy.max_attempts
+        );
+        RETRIES.inc(&[label]);
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Rate limiting and server errors are worth retrying
+pub fn is_transient_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Transient failures are worth retrying, everything else,
+/// including authentication failures and invalid parameters, is not.
+pub fn retryable(err: &anyhow::Error) -> Retryable {
+    match classify(err) {
+        (ErrorKind::Transient, Some(delay)) => Retryable::After(delay),
+        (ErrorKind::Transient, None) => Retryable::Yes,
+        _ => Retryable::No,
+    }
+}
+
+/// Parses the value of a `Retry-After` header, which is either
+/// a number of seconds or an HTTP date
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let when = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (when.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform_api::HttpRequestFailed;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    const FAST: RetryPolicy = RetryPolicy {
+        max_attempts: 3,
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(10),
+    };
+
+    fn failed(status: StatusCode, retry_after: Option<Duration>) -> anyhow::Error {
+        HttpRequestFailed::new(status, "oops".to_string(), retry_after).into()
+    }
+
+    #[test]
+    fn classify() {
+        assert_eq!(
+            retryable(&failed(StatusCode::BAD_GATEWAY, None)),
+            Retryable::Yes
+        );
+        assert_eq!(
+            retryable(
+                &failed(StatusCode::TOO_MANY_REQUESTS, Some(Duration::from_secs(2)))
+                    .context("control device")
+            ),
+            Retryable::After(Duration::from_secs(2))
+        );
+        assert_eq!(
+            retryable(&failed(StatusCode::UNAUTHORIZED, None)),
+            Retryable::No
+        );
+        assert_eq!(
+            retryable(&failed(StatusCode::BAD_REQUEST, None)),
+            Retryable::No
+        );
+        assert_eq!(
+            retryable(&anyhow::anyhow!("invalid parameter")),
+            Retryable::No
+        );
+    }
+
+    #[test]
+    fn retry_after() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:27:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::CLOUD;
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(250) && first <= Duration::from_millis(500));
+            assert!(policy.backoff(10) <= policy.max_delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let attempts = AtomicU32::new(0);
+        let result = retry(&FAST, "test", retryable, || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(failed(StatusCode::SERVICE_UNAVAILABLE, None)),
+                _ => Ok(42),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        attempts.store(0, Ordering::SeqCst);
+        let result: anyhow::Result<()> = retry(&FAST, "test", retryable, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(failed(StatusCode::BAD_GATEWAY, None))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), FAST.max_attempts);
+    }
+
+    #[tokio::test]
+    async fn fails_fast() {
+        let attempts = AtomicU32::new(0);
+        let result: anyhow::Result<()> = retry(&FAST, "test", retryable, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(failed(StatusCode::UNAUTHORIZED, None))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        // The server wants us to wait longer than we're prepared to
+        attempts.store(0, Ordering::SeqCst);
+        let result: anyhow::Result<()> = retry(&FAST, "test", retryable, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(failed(
+                StatusCode::TOO_MANY_REQUESTS,
+                Some(Duration::from_secs(3600)),
+            ))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -6,6 +6,7 @@ use crate::platform_api::{
 };
 use crate::service::quirks::{resolve_quirk, Quirk, BULB};
 use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::HashMap;
@@ -23,6 +24,9 @@ pub struct Device {
 
     pub lan_device_status: Option<LanDeviceStatus>,
     pub last_lan_device_status_update: Option<DateTime<Utc>>,
+    /// When the device stopped answering LAN API status polls;
+    /// cleared when we hear from it via the LAN API again
+    pub lan_stale_since: Option<DateTime<Utc>>,
 
     pub http_device_info: Option<HttpDeviceInfo>,
     pub last_http_device_update: Option<DateTime<Utc>>,
@@ -43,9 +47,21 @@ pub struct Device {
 
     pub last_polled: Option<DateTime<Utc>>,
 
+    /// The room name remembered from the device registry;
+    /// used until the undocumented API tells us otherwise
+    pub persisted_room_name: Option<String>,
+
     active_scene: Option<ActiveSceneInfo>,
 }
 
+/// How long we can go without hearing from a device via the LAN API
+/// before we consider it to be offline. LAN discovery runs at least
+/// once per minute, so this allows for a couple of lost packets.
+const LAN_AVAILABILITY_TIMEOUT: chrono::Duration = chrono::Duration::minutes(3);
+/// The IoT API only reports changes, so activity within this period
+/// is a sign that the device is online, but silence doesn't mean much
+const IOT_AVAILABILITY_TIMEOUT: chrono::Duration = chrono::Duration::minutes(15);
+
 impl std::fmt::Display for Device {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(fmt, "{} ({} {})", self.name(), self.id, self.sku)
@@ -64,7 +80,7 @@ struct ActiveSceneInfo {
 
 /// Represents the device state; synthesized from the various
 /// sources of facts that we have in the Device
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct DeviceState {
     /// Whether the device is powered on
     pub on: bool,
@@ -91,7 +107,21 @@ pub struct DeviceState {
     pub updated: DateTime<Utc>,
 }
 
-#[derive(Debug, Clone)]
+impl DeviceState {
+    /// Compares the observable state, ignoring where and
+    /// when the information was obtained
+    pub fn same_as(&self, other: &Self) -> bool {
+        self.on == other.on
+            && self.light_on == other.light_on
+            && self.online == other.online
+            && self.kelvin == other.kelvin
+            && self.color == other.color
+            && self.brightness == other.brightness
+            && self.scene == other.scene
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UndocDeviceInfo {
     pub room_name: Option<String>,
     pub entry: crate::undoc_api::DeviceEntry,
@@ -127,6 +157,9 @@ impl Device {
         if let Some(info) = &self.http_device_info {
             return Some(&info.device_name);
         }
+        if let Some(info) = &self.undoc_device_info {
+            return Some(&info.entry.device_name);
+        }
         None
     }
 
@@ -134,7 +167,7 @@ impl Device {
         if let Some(info) = &self.undoc_device_info {
             return info.room_name.as_deref();
         }
-        None
+        self.persisted_room_name.as_deref()
     }
 
     /// compute a name from the SKU and the last couple of bytes from the
@@ -162,10 +195,10 @@ impl Device {
                 if self.device_state().map(|s| s.on).unwrap_or(false) {
                     chrono::Duration::seconds(60)
                 } else {
-                    *POLL_INTERVAL
+                    POLL_INTERVAL
                 }
             }
-            _ => *POLL_INTERVAL,
+            _ => POLL_INTERVAL,
         }
     }
 
@@ -194,6 +227,7 @@ impl Device {
     pub fn set_lan_device(&mut self, device: LanDevice) {
         self.lan_device.replace(device);
         self.last_lan_device_update.replace(Utc::now());
+        self.lan_stale_since.take();
     }
 
     /// Update the LAN device status information
@@ -205,10 +239,16 @@ impl Device {
             .unwrap_or(true);
         self.lan_device_status.replace(status);
         self.last_lan_device_status_update.replace(Utc::now());
+        self.lan_stale_since.take();
         self.clear_scene_if_color_changed();
         changed
     }
 
+    /// Record that the device has stopped answering via the LAN API
+    pub fn set_lan_stale(&mut self) {
+        self.lan_stale_since.get_or_insert_with(Utc::now);
+    }
+
     pub fn set_iot_device_status(&mut self, status: LanDeviceStatus) {
         self.iot_device_status.replace(status);
         self.last_iot_device_status_update.replace(Utc::now());
@@ -348,6 +388,42 @@ impl Device {
         })
     }
 
+    /// Returns true if the device appears to be online.
+    /// Recent activity via the LAN or IoT APIs means that it is
+    /// reachable. If we have seen it via the LAN API during this
+    /// session but it has since gone quiet, or stopped answering
+    /// status polls, it is offline.
+    /// Otherwise we fall back to the platform API `online` capability,
+    /// and if we know nothing at all, we assume that it is available.
+    pub fn is_available(&self) -> bool {
+        let now = Utc::now();
+
+        let lan_seen = self
+            .last_lan_device_update
+            .into_iter()
+            .chain(self.last_lan_device_status_update)
+            .max();
+        if let Some(seen) = lan_seen {
+            if self.lan_stale_since.is_none() && now - seen <= LAN_AVAILABILITY_TIMEOUT {
+                return true;
+            }
+        }
+
+        if let Some(updated) = self.last_iot_device_status_update {
+            if now - updated <= IOT_AVAILABILITY_TIMEOUT {
+                return true;
+            }
+        }
+
+        if lan_seen.is_some() {
+            return false;
+        }
+
+        self.compute_http_device_state()
+            .and_then(|state| state.online)
+            .unwrap_or(true)
+    }
+
     /// Returns the most recently received state information
     pub fn device_state(&self) -> Option<DeviceState> {
         let mut candidates = vec![];
@@ -618,4 +694,25 @@ mod test {
         let device = Device::new("H6127", "ce");
         assert_eq!(device.name(), "H6127_CE");
     }
+
+    #[test]
+    fn availability() {
+        let mut device = Device::new("H6000", "AA:BB:CC:DD:EE:FF:42:2A");
+        assert!(device.is_available(), "no information means available");
+
+        device.last_lan_device_update = Some(Utc::now());
+        assert!(device.is_available());
+
+        device.last_lan_device_update =
+            Some(Utc::now() - LAN_AVAILABILITY_TIMEOUT - chrono::Duration::seconds(1));
+        assert!(!device.is_available(), "LAN device went quiet");
+
+        device.set_lan_device_status(LanDeviceStatus::default());
+        assert!(device.is_available());
+        device.set_lan_stale();
+        assert!(!device.is_available(), "LAN device stopped answering polls");
+
+        device.last_iot_device_status_update = Some(Utc::now());
+        assert!(device.is_available(), "but IoT says otherwise");
+    }
 }
@@ -0,0 +1,669 @@
+use crate::cache::dump_cache;
+use crate::hass_mqtt::enumerator::{enumerate_all_entites, enumerate_entities_for_device};
+use crate::hass_mqtt::instance::EntityList;
+use crate::hass_mqtt::work_mode::ParsedWorkMode;
+use crate::service::device::{Device, DeviceState};
+use crate::service::state::StateHandle;
+use crate::service::transport::TransportKind;
+use crate::service::transport_health::TransportStats;
+use crate::undoc_api::to_shareable_json;
+use crate::version_info::govee_version;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::{Captures, Regex};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+use std::collections::{BTreeMap, VecDeque};
+
+/// How many of the most recent log lines to include in diagnostics
+const MAX_LOG_LINES: usize = 2000;
+/// How many of the most recent packets to include in diagnostics
+const MAX_PACKETS: usize = 500;
+
+/// The name of the directory that holds the files in the archive
+const BUNDLE_DIR: &str = "govee2mqtt-diagnostics";
+
+/// Cache entries that hold credentials, rather than data about devices.
+/// Their contents are never included in diagnostics.
+const CREDENTIAL_CACHE_KEYS: &[&str] = &["account-info", "community-login", "iot-key"];
+
+static RECENT_LOGS: Lazy<Mutex<VecDeque<String>>> = Lazy::new(Default::default);
+static RECENT_PACKETS: Lazy<Mutex<VecDeque<PacketRecord>>> = Lazy::new(Default::default);
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, item: T, limit: usize) {
+    while queue.len() >= limit {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+/// Remember a formatted log line, so that it can be included
+/// in a diagnostics bundle
+pub fn record_log_line(line: String) {
+    push_bounded(&mut RECENT_LOGS.lock(), line, MAX_LOG_LINES);
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketTransport {
+    Lan,
+    Iot,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Clone, Debug)]
+struct PacketRecord {
+    timestamp: DateTime<Utc>,
+    transport: PacketTransport,
+    direction: PacketDirection,
+    peer: String,
+    payload: String,
+}
+
+/// Remember a packet that was exchanged with a device, so that it
+/// can be included in a diagnostics bundle.
+/// `peer` is the address or topic of the other end.
+pub fn record_packet(
+    transport: PacketTransport,
+    direction: PacketDirection,
+    peer: impl std::fmt::Display,
+    payload: &[u8],
+) {
+    let record = PacketRecord {
+        timestamp: Utc::now(),
+        transport,
+        direction,
+        peer: peer.to_string(),
+        payload: String::from_utf8_lossy(payload).to_string(),
+    };
+    push_bounded(&mut RECENT_PACKETS.lock(), record, MAX_PACKETS);
+}
+
+/// Returns true if a setting, flag or field with this name
+/// holds a credential or personally identifying value
+fn is_sensitive_name(name: &str) -> bool {
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    const SENSITIVE: &[&str] = &[
+        "token",
+        "password",
+        "pass",
+        "secretcode",
+        "secret",
+        "email",
+        "apikey",
+        "basicauth",
+        "mac",
+        "address",
+        "p12",
+    ];
+    SENSITIVE.iter().any(|suffix| name.ends_with(suffix))
+}
+
+static EMAIL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)+").unwrap());
+/// Govee IoT topics look like `GD/0123abcd...` for devices and
+/// `GA/...` for accounts
+static IOT_TOPIC: Lazy<Regex> = Lazy::new(|| Regex::new(r"\bG[A-Z]/[0-9A-Za-z]{4,}").unwrap());
+/// A run of colon separated octets, such as a MAC address or device id
+static OCTETS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\b[0-9A-Fa-f]{2}(:[0-9A-Fa-f]{2})+\b").unwrap());
+
+/// Masks a MAC address, or the MAC address portion of a device id.
+/// Device ids are 8 octets, the first 6 of which are the MAC address
+/// of the device. The last 2 are kept so that devices can still be
+/// told apart in an issue report.
+fn mask_octets(octets: &str) -> Option<String> {
+    let parts: Vec<&str> = octets.split(':').collect();
+    match parts.len() {
+        6 => Some("XX:XX:XX:XX:XX:XX".to_string()),
+        8 => Some(format!("XX:XX:XX:XX:XX:XX:{}:{}", parts[6], parts[7])),
+        _ => None,
+    }
+}
+
+/// Removes credentials, IoT topics, email addresses and MAC addresses
+/// from text and JSON that is destined for a diagnostics bundle.
+/// This complements `undoc_api::Redacted`, which can only redact
+/// values whose type is known to us.
+#[derive(Default)]
+pub struct Scrubber {
+    /// Literal text and its replacement, longest first
+    replacements: Vec<(String, String)>,
+}
+
+impl Scrubber {
+    /// Returns a Scrubber that knows about the credentials that
+    /// were passed to us via the environment or command line
+    pub fn from_environment() -> Self {
+        let mut scrubber = Self::default();
+        for (name, value) in std::env::vars() {
+            if name.starts_with("GOVEE_") && is_sensitive_name(&name) {
+                scrubber.add_secret(&value);
+            }
+        }
+        let mut args = std::env::args().peekable();
+        while let Some(arg) = args.next() {
+            if let Some((flag, value)) = arg.split_once('=') {
+                if flag.starts_with("--") && is_sensitive_name(flag) {
+                    scrubber.add_secret(value);
+                }
+            } else if arg.starts_with("--") && is_sensitive_name(&arg) {
+                if let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+                    scrubber.add_secret(&value);
+                }
+            }
+        }
+        scrubber
+    }
+
+    fn add_replacement(&mut self, text: String, replacement: String) {
+        if self.replacements.iter().any(|(t, _)| *t == text) {
+            return;
+        }
+        self.replacements.push((text, replacement));
+        self.replacements
+            .sort_by_key(|(text, _)| std::cmp::Reverse(text.len()));
+    }
+
+    /// Arrange for `secret` to be replaced wherever it appears.
+    /// Credentials of the form `USER:PASSWORD` also have their
+    /// password replaced on its own.
+    pub fn add_secret(&mut self, secret: &str) {
+        // Very short values would match too much unrelated text
+        if secret.len() < 4 {
+            return;
+        }
+        self.add_replacement(secret.to_string(), "REDACTED".to_string());
+        if let Some((_user, password)) = secret.split_once(':') {
+            self.add_secret(password);
+        }
+    }
+
+    /// Arrange for the MAC address portion of the device id to be
+    /// masked, including in the forms that are used in hass topics
+    /// and unique ids.
+    pub fn add_device_id(&mut self, id: &str) {
+        let Some(masked) = mask_octets(id) else {
+            return;
+        };
+        let plain: String = id.chars().filter(|&c| c != ':').collect();
+        let masked_plain: String = masked.chars().filter(|&c| c != ':').collect();
+
+        self.add_replacement(id.to_string(), masked.clone());
+        self.add_replacement(plain.to_ascii_uppercase(), masked_plain.clone());
+        self.add_replacement(
+            plain.to_ascii_lowercase(),
+            masked_plain.to_ascii_lowercase(),
+        );
+        self.add_replacement(
+            id.replace(':', "_").to_ascii_lowercase(),
+            masked.replace(':', "_").to_ascii_lowercase(),
+        );
+    }
+
+    pub fn scrub_text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (secret, replacement) in &self.replacements {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), replacement);
+            }
+        }
+        let text = EMAIL.replace_all(&text, "REDACTED");
+        let text = IOT_TOPIC.replace_all(&text, "REDACTED");
+        OCTETS
+            .replace_all(&text, |caps: &Captures| {
+                let octets = &caps[0];
+                mask_octets(octets).unwrap_or_else(|| octets.to_string())
+            })
+            .to_string()
+    }
+
+    pub fn scrub_json(&self, value: JsonValue) -> JsonValue {
+        match value {
+            JsonValue::String(s) => JsonValue::String(self.scrub_text(&s)),
+            JsonValue::Array(items) => {
+                JsonValue::Array(items.into_iter().map(|v| self.scrub_json(v)).collect())
+            }
+            JsonValue::Object(map) => JsonValue::Object(
+                map.into_iter()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            JsonValue::String(s) if !s.is_empty() && is_sensitive_name(&key) => {
+                                JsonValue::String("REDACTED".to_string())
+                            }
+                            JsonValue::Number(_) if is_sensitive_name(&key) => {
+                                JsonValue::String("REDACTED".to_string())
+                            }
+                            value => self.scrub_json(value),
+                        };
+                        (self.scrub_text(&key), value)
+                    })
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+}
+
+/// A fact about a device, along with when it was last updated
+#[derive(Serialize)]
+struct SourceFact<T> {
+    updated: Option<DateTime<Utc>>,
+    data: Option<T>,
+}
+
+impl<T: Clone> SourceFact<T> {
+    fn new(data: &Option<T>, updated: Option<DateTime<Utc>>) -> Self {
+        Self {
+            updated,
+            data: data.clone(),
+        }
+    }
+}
+
+/// The state of a device as computed from each source of facts,
+/// and the `merged` state that we report
+#[derive(Serialize)]
+struct DebugStates {
+    lan: Option<DeviceState>,
+    iot: Option<DeviceState>,
+    platform: Option<DeviceState>,
+    merged: Option<DeviceState>,
+}
+
+/// Everything that we know about a given device, for use when
+/// troubleshooting. It contains sensitive values, so serialize
+/// it via `to_redacted_json` or `to_shareable_json`.
+#[derive(Serialize)]
+pub struct DeviceDebug<'a> {
+    sku: &'a str,
+    id: &'a str,
+    name: String,
+    computed_name: String,
+    room: Option<&'a str>,
+    available: bool,
+    last_polled: Option<DateTime<Utc>>,
+    lan_device: SourceFact<crate::lan_api::LanDevice>,
+    lan_device_status: SourceFact<crate::lan_api::DeviceStatus>,
+    iot_device_status: SourceFact<crate::lan_api::DeviceStatus>,
+    http_device_info: SourceFact<crate::platform_api::HttpDeviceInfo>,
+    http_device_state: SourceFact<crate::platform_api::HttpDeviceState>,
+    undoc_device_info: SourceFact<crate::service::device::UndocDeviceInfo>,
+    nightlight_state: Option<crate::ble::NotifyHumidifierNightlightParams>,
+    target_humidity_percent: Option<u8>,
+    humidifier_work_mode: Option<u8>,
+    quirk: Option<crate::service::quirks::Quirk>,
+    work_modes: JsonValue,
+    states: DebugStates,
+    transports: BTreeMap<TransportKind, TransportStats>,
+    hass_entities: JsonValue,
+}
+
+impl<'a> DeviceDebug<'a> {
+    pub async fn new(state: &StateHandle, device: &'a Device) -> anyhow::Result<Self> {
+        let work_modes = match ParsedWorkMode::with_device(device) {
+            Ok(modes) => serde_json::to_value(modes)?,
+            Err(err) => json!({"error": format!("{err:#}")}),
+        };
+
+        let mut entities = EntityList::new();
+        let hass_entities = match enumerate_entities_for_device(device, state, &mut entities).await
+        {
+            Ok(()) => entities
+                .capture_config(state)
+                .await
+                .and_then(|configs| Ok(serde_json::to_value(configs)?)),
+            Err(err) => Err(err),
+        }
+        .unwrap_or_else(|err| json!({"error": format!("{err:#}")}));
+
+        Ok(Self {
+            sku: &device.sku,
+            id: &device.id,
+            name: device.name(),
+            computed_name: device.computed_name(),
+            room: device.room_name(),
+            available: device.is_available(),
+            last_polled: device.last_polled,
+            lan_device: SourceFact::new(&device.lan_device, device.last_lan_device_update),
+            lan_device_status: SourceFact::new(
+                &device.lan_device_status,
+                device.last_lan_device_status_update,
+            ),
+            iot_device_status: SourceFact::new(
+                &device.iot_device_status,
+                device.last_iot_device_status_update,
+            ),
+            http_device_info: SourceFact::new(
+                &device.http_device_info,
+                device.last_http_device_update,
+            ),
+            http_device_state: SourceFact::new(
+                &device.http_device_state,
+                device.last_http_device_state_update,
+            ),
+            undoc_device_info: SourceFact::new(
+                &device.undoc_device_info,
+                device.last_undoc_device_info_update,
+            ),
+            nightlight_state: device.nightlight_state,
+            target_humidity_percent: device.target_humidity_percent,
+            humidifier_work_mode: device.humidifier_work_mode,
+            quirk: device.resolve_quirk(),
+            work_modes,
+            states: DebugStates {
+                lan: device.compute_lan_device_state(),
+                iot: device.compute_iot_device_state(),
+                platform: device.compute_http_device_state(),
+                merged: device.device_state(),
+            },
+            transports: state.transport_health_for_device(&device.id).await,
+            hass_entities,
+        })
+    }
+}
+
+/// Returns the suggested file name for a diagnostics bundle
+pub fn bundle_file_name() -> String {
+    format!(
+        "govee2mqtt-diagnostics-{}.tar",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    )
+}
+
+fn error_json(err: anyhow::Error) -> JsonValue {
+    json!({"error": format!("{err:#}")})
+}
+
+/// The command line and GOVEE_ environment, with credentials removed
+fn config_json() -> JsonValue {
+    let mut env = BTreeMap::new();
+    for (name, value) in std::env::vars() {
+        if name.starts_with("GOVEE_") || name == "RUST_LOG" || name == "TZ" {
+            let value = if is_sensitive_name(&name) && !value.is_empty() {
+                "REDACTED".to_string()
+            } else {
+                value
+            };
+            env.insert(name, value);
+        }
+    }
+
+    let mut args = vec![];
+    let mut redact_next = false;
+    for arg in std::env::args() {
+        if redact_next && !arg.starts_with("--") {
+            args.push("REDACTED".to_string());
+            redact_next = false;
+            continue;
+        }
+        redact_next = false;
+        match arg.split_once('=') {
+            Some((flag, _)) if flag.starts_with("--") && is_sensitive_name(flag) => {
+                args.push(format!("{flag}=REDACTED"));
+            }
+            _ => {
+                redact_next = arg.starts_with("--") && is_sensitive_name(&arg);
+                args.push(arg);
+            }
+        }
+    }
+
+    json!({
+        "args": args,
+        "env": env,
+    })
+}
+
+async fn devices_json(state: &StateHandle) -> anyhow::Result<JsonValue> {
+    let mut devices = state.devices().await;
+    devices.sort_by(|a, b| (&a.sku, &a.id).cmp(&(&b.sku, &b.id)));
+
+    let mut result = vec![];
+    for device in &devices {
+        let debug = DeviceDebug::new(state, device).await?;
+        result.push(to_shareable_json(&debug)?);
+    }
+    Ok(JsonValue::Array(result))
+}
+
+async fn hass_discovery_json(state: &StateHandle) -> anyhow::Result<JsonValue> {
+    let entities = enumerate_all_entites(state).await?;
+    let configs = entities.capture_config(state).await?;
+    to_shareable_json(&configs)
+}
+
+fn cache_json() -> anyhow::Result<JsonValue> {
+    let mut entries = dump_cache()?;
+    for entry in &mut entries {
+        if CREDENTIAL_CACHE_KEYS.contains(&entry.key.as_str()) {
+            entry.value = JsonValue::String("REDACTED".to_string());
+        }
+    }
+    Ok(serde_json::to_value(entries)?)
+}
+
+fn packets_json() -> JsonValue {
+    let packets: Vec<_> = RECENT_PACKETS
+        .lock()
+        .iter()
+        .map(|packet| {
+            json!({
+                "timestamp": packet.timestamp,
+                "transport": packet.transport,
+                "direction": packet.direction,
+                "peer": packet.peer,
+                "payload": serde_json::from_str::<JsonValue>(&packet.payload)
+                    .unwrap_or_else(|_| JsonValue::String(packet.payload.clone())),
+            })
+        })
+        .collect();
+    JsonValue::Array(packets)
+}
+
+/// Produces a tar archive containing the version, configuration,
+/// devices, cache contents, recent logs and packets and the hass
+/// discovery configs. Sensitive values are scrubbed so that the
+/// archive can be attached to an issue report.
+pub async fn build_diagnostics_bundle(state: &StateHandle) -> anyhow::Result<Vec<u8>> {
+    let mut scrubber = Scrubber::from_environment();
+    for device in state.devices().await {
+        scrubber.add_device_id(&device.id);
+    }
+
+    let generated = Utc::now();
+    let mut files: Vec<(&str, JsonValue)> = vec![
+        ("config.json", config_json()),
+        (
+            "devices.json",
+            devices_json(state).await.unwrap_or_else(error_json),
+        ),
+        (
+            "hass-discovery.json",
+            hass_discovery_json(state).await.unwrap_or_else(error_json),
+        ),
+        ("cache.json", cache_json().unwrap_or_else(error_json)),
+        ("packets.json", packets_json()),
+    ];
+
+    let manifest = json!({
+        "version": govee_version(),
+        "generated": generated,
+        "files": files.iter().map(|(name, _)| *name)
+            .chain(std::iter::once("log.txt"))
+            .collect::<Vec<_>>(),
+        "note": "Credentials, tokens, IoT topics, email addresses and MAC addresses \
+            have been removed. Device ids have their MAC address portion masked.",
+    });
+    files.insert(0, ("manifest.json", manifest));
+
+    let mut tar = TarBuilder::new(generated.timestamp().max(0) as u64);
+    for (name, value) in files {
+        let value = scrubber.scrub_json(value);
+        tar.add_file(
+            &format!("{BUNDLE_DIR}/{name}"),
+            serde_json::to_string_pretty(&value)?.as_bytes(),
+        )?;
+    }
+
+    let mut log = String::new();
+    for line in RECENT_LOGS.lock().iter() {
+        log.push_str(&scrubber.scrub_text(line));
+        log.push('\n');
+    }
+    tar.add_file(&format!("{BUNDLE_DIR}/log.txt"), log.as_bytes())?;
+
+    Ok(tar.finish())
+}
+
+/// Produces an uncompressed ustar archive of regular files
+struct TarBuilder {
+    data: Vec<u8>,
+    mtime: u64,
+}
+
+const TAR_BLOCK: usize = 512;
+
+/// Writes `value` as a NUL terminated, zero padded octal number
+/// that fills `field`
+fn write_octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{value:0width$o}");
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
+
+impl TarBuilder {
+    fn new(mtime: u64) -> Self {
+        Self {
+            data: vec![],
+            mtime,
+        }
+    }
+
+    fn add_file(&mut self, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+        anyhow::ensure!(path.len() < 100, "tar: path {path} is too long");
+        anyhow::ensure!(
+            (contents.len() as u64) < 0o77777777777,
+            "tar: {path} is too large"
+        );
+
+        let mut header = [0u8; TAR_BLOCK];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        write_octal(&mut header[100..108], 0o644);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], contents.len() as u64);
+        write_octal(&mut header[136..148], self.mtime);
+        // The checksum is computed as though its own field were spaces
+        header[148..156].fill(b' ');
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        let checksum: u64 = header.iter().map(|&b| b as u64).sum();
+        write_octal(&mut header[148..155], checksum);
+
+        self.data.extend_from_slice(&header);
+        self.data.extend_from_slice(contents);
+        let padding = (TAR_BLOCK - contents.len() % TAR_BLOCK) % TAR_BLOCK;
+        self.data.resize(self.data.len() + padding, 0);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        // The archive is terminated by two empty blocks
+        self.data.resize(self.data.len() + 2 * TAR_BLOCK, 0);
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_octal(field: &[u8]) -> u64 {
+        let s = std::str::from_utf8(field).unwrap();
+        u64::from_str_radix(s.trim_matches(|c| c == '\0' || c == ' '), 8).unwrap()
+    }
+
+    #[test]
+    fn tar_archive() {
+        let mut tar = TarBuilder::new(1700000000);
+        tar.add_file("dir/hello.txt", b"hello").unwrap();
+        tar.add_file("dir/empty.txt", b"").unwrap();
+        let data = tar.finish();
+
+        // header + 1 block of content, header only, 2 terminating blocks
+        assert_eq!(data.len(), 5 * TAR_BLOCK);
+
+        let header = &data[..TAR_BLOCK];
+        assert_eq!(&header[..13], b"dir/hello.txt");
+        assert_eq!(parse_octal(&header[124..136]), 5);
+        assert_eq!(parse_octal(&header[136..148]), 1700000000);
+        assert_eq!(&data[TAR_BLOCK..TAR_BLOCK + 5], b"hello");
+
+        let mut unsigned = header.to_vec();
+        unsigned[148..156].fill(b' ');
+        let expect: u64 = unsigned.iter().map(|&b| b as u64).sum();
+        assert_eq!(parse_octal(&header[148..156]), expect);
+
+        assert_eq!(&data[2 * TAR_BLOCK..2 * TAR_BLOCK + 13], b"dir/empty.txt");
+        assert!(data[3 * TAR_BLOCK..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn scrub() {
+        let mut scrubber = Scrubber::default();
+        scrubber.add_secret("hunter2hunter2");
+        scrubber.add_secret("admin:sekrit");
+        scrubber.add_device_id("AA:BB:CC:DD:EE:FF:07:4D");
+
+        assert_eq!(
+            scrubber.scrub_text(
+                "login someone@example.com with hunter2hunter2, basic sekrit \
+                 topic GD/0123456789abcdef mac 11:22:33:44:55:66 \
+                 device AA:BB:CC:DD:EE:FF:07:4D 12:34:56:78:9A:BC:DE:F0 \
+                 gv2mqtt/light/aabbccddeeff074d aa_bb_cc_dd_ee_ff_07_4d \
+                 at 10:30:00"
+            ),
+            "login REDACTED with REDACTED, basic REDACTED \
+             topic REDACTED mac XX:XX:XX:XX:XX:XX \
+             device XX:XX:XX:XX:XX:XX:07:4D XX:XX:XX:XX:XX:XX:DE:F0 \
+             gv2mqtt/light/xxxxxxxxxxxx074d xx_xx_xx_xx_xx_xx_07_4d \
+             at 10:30:00"
+        );
+
+        assert_eq!(
+            scrubber.scrub_json(json!({
+                "secretCode": "/K3UYYw=",
+                "wifiMac": "11:22:33:44:55:66",
+                "refresh_token": "abcdef",
+                "accountId": 1234,
+                "ip": "10.0.0.1",
+                "email": "",
+                "AA:BB:CC:DD:EE:FF:07:4D": ["someone@example.com"],
+            })),
+            json!({
+                "secretCode": "REDACTED",
+                "wifiMac": "REDACTED",
+                "refresh_token": "REDACTED",
+                "accountId": 1234,
+                "ip": "10.0.0.1",
+                "email": "",
+                "XX:XX:XX:XX:XX:XX:07:4D": ["REDACTED"],
+            })
+        );
+    }
+}
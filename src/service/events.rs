@@ -0,0 +1,231 @@
+use crate::error::ErrorKind;
+use crate::service::device::DeviceState;
+use crate::service::state::StateHandle;
+use crate::service::transport::TransportKind;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+
+/// How many events can be buffered for a slow subscriber before
+/// it starts to miss them
+const EVENT_BUS_CAPACITY: usize = 256;
+/// How many state changes are retained per device by the recorder
+pub const HISTORY_PER_DEVICE: usize = 50;
+
+/// Emitted whenever something about a device may have changed
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct DeviceStateChanged {
+    pub device_id: String,
+    /// The state that was last reported for the device, if any
+    pub old: Option<DeviceState>,
+    pub new: Option<DeviceState>,
+    /// Which source produced the new state, eg: "LAN API"
+    pub source: Option<&'static str>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl DeviceStateChanged {
+    /// Returns true if the observable device state differs between
+    /// old and new. Events are also emitted when other facts about
+    /// the device changed, in which case this returns false.
+    pub fn is_change(&self) -> bool {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => !old.same_as(new),
+            (None, None) => false,
+            _ => true,
+        }
+    }
+}
+
+/// Emitted when we learn about a device, or its LAN address changes
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct DeviceDiscovered {
+    pub device_id: String,
+    pub sku: String,
+    /// How we found it, eg: "LAN API"
+    pub source: &'static str,
+    pub ip: Option<IpAddr>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Emitted when a device is no longer associated with the account
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct DeviceRemoved {
+    pub device_id: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Emitted when a control command has been attempted
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct CommandResult {
+    pub device_id: String,
+    pub command: String,
+    /// The transport that carried out the command, if it succeeded
+    pub transport: Option<TransportKind>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// The classification of the error, if it failed
+    pub error_kind: Option<ErrorKind>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServiceEvent {
+    DeviceStateChanged(DeviceStateChanged),
+    DeviceDiscovered(DeviceDiscovered),
+    DeviceRemoved(DeviceRemoved),
+    CommandResult(CommandResult),
+}
+
+impl ServiceEvent {
+    /// The name of the event, matching its serialized `type`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::DeviceStateChanged(_) => "device_state_changed",
+            Self::DeviceDiscovered(_) => "device_discovered",
+            Self::DeviceRemoved(_) => "device_removed",
+            Self::CommandResult(_) => "command_result",
+        }
+    }
+}
+
+/// A broadcast channel that allows integrations to observe
+/// what is happening, independently of each other
+pub struct EventBus {
+    tx: Sender<ServiceEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (tx, _rx) = channel(EVENT_BUS_CAPACITY);
+        Self { tx }
+    }
+}
+
+impl EventBus {
+    pub fn publish(&self, event: ServiceEvent) {
+        // An error here just means that there are no subscribers
+        self.tx.send(event).ok();
+    }
+
+    pub fn subscribe(&self) -> Receiver<ServiceEvent> {
+        self.tx.subscribe()
+    }
+}
+
+/// Receive the next event, skipping over any that were dropped
+/// because the subscriber fell behind.
+/// Returns None once the bus has been closed.
+pub async fn next_event(rx: &mut Receiver<ServiceEvent>, subscriber: &str) -> Option<ServiceEvent> {
+    loop {
+        match rx.recv().await {
+            Ok(event) => return Some(event),
+            Err(RecvError::Lagged(n)) => {
+                log::warn!("{subscriber} fell behind and missed {n} events");
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Retains a bounded history of state changes per device
+#[derive(Default, Debug)]
+pub struct StateHistory {
+    by_device: HashMap<String, VecDeque<DeviceStateChanged>>,
+}
+
+impl StateHistory {
+    pub fn record(&mut self, event: DeviceStateChanged) {
+        let history = self.by_device.entry(event.device_id.clone()).or_default();
+        if history.len() >= HISTORY_PER_DEVICE {
+            history.pop_front();
+        }
+        history.push_back(event);
+    }
+
+    pub fn for_device(&self, device_id: &str) -> Vec<DeviceStateChanged> {
+        self.by_device
+            .get(device_id)
+            .map(|history| history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Subscribes to the event bus and records state changes
+pub async fn run_history_recorder(state: StateHandle) {
+    let mut rx = state.subscribe_events();
+    while let Some(event) = next_event(&mut rx, "history recorder").await {
+        if let ServiceEvent::DeviceStateChanged(change) = event {
+            if change.is_change() {
+                state.record_state_history(change).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn change(id: &str, brightness: u8) -> DeviceStateChanged {
+        let new = DeviceState {
+            on: true,
+            light_on: None,
+            online: None,
+            kelvin: 0,
+            color: Default::default(),
+            brightness,
+            scene: None,
+            source: "LAN API",
+            updated: Utc::now(),
+        };
+        DeviceStateChanged {
+            device_id: id.to_string(),
+            old: None,
+            new: Some(new),
+            source: Some("LAN API"),
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let mut history = StateHistory::default();
+        for i in 0..HISTORY_PER_DEVICE + 5 {
+            history.record(change("a", i as u8));
+        }
+        let a = history.for_device("a");
+        assert_eq!(a.len(), HISTORY_PER_DEVICE);
+        assert_eq!(a[0].new.as_ref().unwrap().brightness, 5);
+        assert!(history.for_device("b").is_empty());
+    }
+
+    #[test]
+    fn event_names() {
+        let event = ServiceEvent::DeviceRemoved(DeviceRemoved {
+            device_id: "a".to_string(),
+            timestamp: Utc::now(),
+        });
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], event.name());
+        assert_eq!(json["device_id"], "a");
+    }
+
+    #[test]
+    fn change_detection() {
+        let mut event = change("a", 10);
+        assert!(event.is_change());
+
+        event.old = event.new.clone();
+        event.old.as_mut().unwrap().updated = Utc::now() - chrono::Duration::seconds(5);
+        assert!(!event.is_change());
+
+        event.old.as_mut().unwrap().brightness = 20;
+        assert!(event.is_change());
+    }
+}
@@ -1,21 +1,31 @@
+use crate::error::{error_kind, ErrorKind};
+use crate::hass_mqtt::base::device_identifier;
 use crate::hass_mqtt::climate::mqtt_set_temperature;
 use crate::hass_mqtt::enumerator::{enumerate_all_entites, enumerate_entities_for_device};
 use crate::hass_mqtt::humidifier::{mqtt_device_set_work_mode, mqtt_humidifier_set_target};
-use crate::hass_mqtt::instance::EntityList;
+use crate::hass_mqtt::instance::{
+    forget_published_topics, garbage_collect_entity_configs, load_published_topics,
+    published_topics_file_name, record_published_topics, save_published_topics, EntityList,
+};
 use crate::hass_mqtt::number::mqtt_number_command;
 use crate::hass_mqtt::select::mqtt_set_mode_scene;
 use crate::lan_api::DeviceColor;
 use crate::opt_env_var;
 use crate::platform_api::{from_json, DeviceType};
 use crate::service::device::Device as ServiceDevice;
+use crate::service::events::ServiceEvent;
+use crate::service::health::{Subsystem, HEALTH};
+use crate::service::metrics::{ConnectionTracker, MQTT_CONNECTED, MQTT_RECONNECTS};
 use crate::service::state::StateHandle;
 use crate::temperature::TemperatureScale;
 use anyhow::Context;
 use async_channel::Receiver;
-use mosquitto_rs::router::{MqttRouter, Params, Payload, State};
+use mosquitto_rs::router::{MqttRouter, Params, Payload, RouterError, State};
 use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver as EventReceiver;
 use tokio::sync::Mutex as TokioMutex;
 use mosquitto_rs::{Client, Event, QoS};
 use serde::{Deserialize, Serialize};
@@ -168,19 +178,126 @@ impl HassArguments {
     }
 }
 
+#[derive(Clone)]
+enum HassSink {
+    Mqtt(Client),
+    /// Collects the messages instead of publishing them,
+    /// so that we can show what would have been sent
+    Capture(Arc<parking_lot::Mutex<Vec<CapturedMessage>>>),
+}
+
+/// A message that was captured by `HassClient::capture`
+#[derive(Serialize, Clone, Debug)]
+pub struct CapturedMessage {
+    pub topic: String,
+    /// The parsed payload, if it is JSON, otherwise the raw text
+    pub payload: serde_json::Value,
+    pub retain: bool,
+}
+
 #[derive(Clone)]
 pub struct HassClient {
-    client: Client,
+    sink: HassSink,
+    /// Whether we have registered our entities yet. Held while
+    /// registering, so that garbage collection and concurrent
+    /// registrations see a complete set of published topics.
+    registered: Arc<TokioMutex<bool>>,
 }
 
 impl HassClient {
+    fn new(client: Client) -> Self {
+        Self {
+            sink: HassSink::Mqtt(client),
+            registered: Default::default(),
+        }
+    }
+
+    /// Returns a client that records what it is asked to publish,
+    /// rather than sending it to the broker
+    pub fn capture() -> Self {
+        Self {
+            sink: HassSink::Capture(Default::default()),
+            registered: Default::default(),
+        }
+    }
+
+    /// Returns the messages recorded by a client created via `capture`
+    pub fn captured(&self) -> Vec<CapturedMessage> {
+        match &self.sink {
+            HassSink::Mqtt(_) => vec![],
+            HassSink::Capture(messages) => messages.lock().clone(),
+        }
+    }
+
+    pub fn is_capture(&self) -> bool {
+        matches!(self.sink, HassSink::Capture(_))
+    }
+
+    async fn send(&self, topic: &str, payload: &[u8], retain: bool) -> anyhow::Result<()> {
+        match &self.sink {
+            HassSink::Mqtt(client) => {
+                client
+                    .publish(topic, payload, QoS::AtMostOnce, retain)
+                    .await?;
+            }
+            HassSink::Capture(messages) => {
+                let text = String::from_utf8_lossy(payload);
+                messages.lock().push(CapturedMessage {
+                    topic: topic.to_string(),
+                    payload: serde_json::from_str(&text)
+                        .unwrap_or_else(|_| serde_json::Value::String(text.to_string())),
+                    retain,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Register our entities with hass, recording the outcome
+    /// as the health of the registration
     async fn register_with_hass(&self, state: &StateHandle) -> anyhow::Result<()> {
+        let result = self.register_entities_with_hass(state).await;
+        match &result {
+            Ok(()) => HEALTH.set_ok(Subsystem::HassRegistration),
+            Err(err) => HEALTH.set_error(Subsystem::HassRegistration, format!("{err:#}")),
+        }
+        result
+    }
+
+    async fn register_entities_with_hass(&self, state: &StateHandle) -> anyhow::Result<()> {
+        let mut registered = self.registered.lock().await;
         let entities = enumerate_all_entites(state).await?;
 
+        // Anything we published before, either earlier in this session
+        // or by a prior run, is a candidate for garbage collection
+        let mut previous = state.take_all_hass_config_topics().await;
+        let topics_file = published_topics_file_name();
+        match load_published_topics(&topics_file) {
+            Ok(topics) => previous.extend(topics),
+            Err(err) => log::warn!("Unable to load previously published topics: {err:#}"),
+        }
+
         // Register the configs
         log::trace!("register_with_hass: register entities");
         entities.publish_config(state, self).await?;
 
+        *registered = true;
+
+        let current = state.all_hass_config_topics().await;
+        if state.is_device_list_complete().await {
+            log::trace!("register_with_hass: remove stale entities");
+            garbage_collect_entity_configs(self, &previous, &current, &topics_file)
+                .await
+                .context("garbage_collect_entity_configs")?;
+        } else {
+            // Until the cloud APIs have told us which devices are in
+            // the account, eg: if the device registry was missing,
+            // we can't tell which of the previous topics are stale.
+            // Keep them on record until collect_stale_entities runs.
+            log::info!("Deferring removal of stale entities until the device list is complete");
+            save_published_topics(&topics_file, &previous.union(&current).cloned().collect())?;
+        }
+
         // Allow hass extra time to register the entities before
         // we mark them as available
         let delay = tokio::time::Duration::from_millis((10 * entities.len()) as u64);
@@ -195,6 +312,11 @@ impl HassClient {
         self.publish(availability_topic(), "online")
             .await
             .context("online -> availability_topic")?;
+        for device in state.devices().await {
+            self.publish_device_availability(&device)
+                .await
+                .context("publish_device_availability")?;
+        }
 
         // report initial state
         log::trace!("register_with_hass: reporting state");
@@ -205,16 +327,90 @@ impl HassClient {
         Ok(())
     }
 
+    /// Publish the entity configs for a device that appeared, or whose
+    /// capabilities changed, after we initially registered with hass,
+    /// and report its state
+    pub async fn register_device(
+        &self,
+        state: &StateHandle,
+        device: &ServiceDevice,
+    ) -> anyhow::Result<()> {
+        let _registered = self.registered.lock().await;
+        let mut entities = EntityList::new();
+        enumerate_entities_for_device(device, state, &mut entities).await?;
+
+        // Anything that we published for the device before, but
+        // not this time around, no longer applies to it
+        let previous = state
+            .take_hass_config_topics(&device_identifier(device))
+            .await;
+        if entities.len() == 0 && previous.is_empty() {
+            return Ok(());
+        }
+        log::info!("Registering {} entities for {device}", entities.len());
+        entities.publish_config(state, self).await?;
+        let current = state.all_hass_config_topics().await;
+        let stale: BTreeSet<String> = previous.difference(&current).cloned().collect();
+        for topic in &stale {
+            log::info!("Removing stale entity config {topic}");
+            self.clear_retained(topic).await?;
+        }
+        let topics_file = published_topics_file_name();
+        forget_published_topics(&topics_file, &stale)?;
+        record_published_topics(&topics_file, &current)?;
+        self.publish_device_availability(device).await?;
+        entities.notify_state(self).await.context("notify_state")?;
+        Ok(())
+    }
+
+    /// Clear the entity configs that we published for a device,
+    /// causing hass to remove its entities
+    pub async fn unregister_device(
+        &self,
+        state: &StateHandle,
+        device: &ServiceDevice,
+    ) -> anyhow::Result<()> {
+        let _registered = self.registered.lock().await;
+        let topics = state
+            .take_hass_config_topics(&device_identifier(device))
+            .await;
+        log::info!("Removing {} entities for {device}", topics.len());
+        for topic in &topics {
+            self.clear_retained(topic).await?;
+        }
+        forget_published_topics(&published_topics_file_name(), &topics)
+    }
+
+    /// Clear the entity configs that we published previously, possibly
+    /// in a prior run, but which no longer apply. Call this once the
+    /// device list is complete; until then, registration defers this.
+    pub async fn collect_stale_entities(&self, state: &StateHandle) -> anyhow::Result<()> {
+        let registered = self.registered.lock().await;
+        if !*registered {
+            // register_entities_with_hass will take care of it
+            return Ok(());
+        }
+        let topics_file = published_topics_file_name();
+        let previous = load_published_topics(&topics_file)?;
+        let current = state.all_hass_config_topics().await;
+        garbage_collect_entity_configs(self, &previous, &current, &topics_file).await
+    }
+
+    /// Publish an empty retained payload to `topic`, which deletes
+    /// any retained message and, for discovery config topics,
+    /// removes the corresponding entity from hass
+    pub async fn clear_retained(&self, topic: &str) -> anyhow::Result<()> {
+        log::trace!("{topic} -> (clear)");
+        self.send(topic, b"", true).await
+    }
+
     pub async fn publish<T: AsRef<str> + std::fmt::Display, P: AsRef<[u8]> + std::fmt::Display>(
         &self,
         topic: T,
         payload: P,
     ) -> anyhow::Result<()> {
         log::trace!("{topic} -> {payload}");
-        self.client
-            .publish(topic, payload, QoS::AtMostOnce, false)
-            .await?;
-        Ok(())
+        self.send(topic.as_ref(), payload.as_ref(), false).await
     }
 
     pub async fn publish_obj<T: AsRef<str> + std::fmt::Display, P: Serialize>(
@@ -224,10 +420,7 @@ impl HassClient {
     ) -> anyhow::Result<()> {
         let payload = serde_json::to_string(&payload)?;
         log::trace!("{topic} -> {payload}");
-        self.client
-            .publish(topic, payload, QoS::AtMostOnce, false)
-            .await?;
-        Ok(())
+        self.send(topic.as_ref(), payload.as_bytes(), false).await
     }
 
     pub async fn advise_hass_of_light_state(
@@ -235,12 +428,24 @@ impl HassClient {
         device: &ServiceDevice,
         state: &StateHandle,
     ) -> anyhow::Result<()> {
+        self.publish_device_availability(device).await?;
+
         let mut entities = EntityList::new();
         enumerate_entities_for_device(device, state, &mut entities).await?;
         entities.notify_state(self).await?;
 
         Ok(())
     }
+
+    pub async fn publish_device_availability(&self, device: &ServiceDevice) -> anyhow::Result<()> {
+        let payload = if device.is_available() {
+            "online"
+        } else {
+            "offline"
+        };
+        self.publish(device_availability_topic(device), payload)
+            .await
+    }
 }
 
 pub fn topic_safe_string(s: &str) -> String {
@@ -286,6 +491,11 @@ pub fn availability_topic() -> String {
     "gv2mqtt/availability".to_string()
 }
 
+/// The topic that reports whether an individual device is online
+pub fn device_availability_topic(device: &ServiceDevice) -> String {
+    format!("gv2mqtt/{id}/availability", id = topic_safe_id(device))
+}
+
 pub fn oneclick_topic() -> String {
     "gv2mqtt/oneclick".to_string()
 }
@@ -884,6 +1094,7 @@ async fn run_mqtt_loop(
 
     let mut router = rebuild_router(&client, &state).await?;
     let mut need_rebuild = false;
+    let mut connection = ConnectionTracker::new(Subsystem::Mqtt, &MQTT_CONNECTED, &MQTT_RECONNECTS);
 
     while let Ok(event) = subscriber.recv().await {
         match event {
@@ -892,16 +1103,29 @@ async fn run_mqtt_loop(
                 let state = state.clone();
                 tokio::spawn(async move {
                     if let Err(err) = router.dispatch(msg.clone(), state.clone()).await {
-                        log::error!("While dispatching {msg:?}: {err:#}");
+                        // Asking a device to do something that it can't
+                        // is not a problem with the service itself
+                        let kind = match &err {
+                            RouterError::Any(err) => error_kind(err),
+                            _ => ErrorKind::Permanent,
+                        };
+                        match kind {
+                            ErrorKind::Unsupported => {
+                                log::warn!("While dispatching {msg:?}: {err:#}")
+                            }
+                            kind => log::error!("While dispatching {msg:?} ({kind}): {err:#}"),
+                        }
                     }
                 });
             }
             Event::Disconnected(reason) => {
                 log::warn!("MQTT disconnected with reason={reason}");
+                connection.disconnected(reason);
                 need_rebuild = true;
             }
             Event::Connected(status) => {
                 log::info!("MQTT connected with status={status}");
+                connection.connected();
                 if need_rebuild {
                     router = rebuild_router(&client, &state).await?;
                 }
@@ -914,6 +1138,35 @@ async fn run_mqtt_loop(
     Ok(())
 }
 
+/// Publish device state changes to hass as they are announced
+/// on the event bus
+async fn run_hass_event_relay(state: StateHandle, mut events: EventReceiver<ServiceEvent>) {
+    loop {
+        let device_ids = match events.recv().await {
+            Ok(ServiceEvent::DeviceStateChanged(change)) => vec![change.device_id],
+            Ok(_) => continue,
+            Err(RecvError::Lagged(n)) => {
+                // We don't know which devices the missed events were
+                // for, so bring hass up to date with all of them
+                log::warn!("hass fell behind and missed {n} events, re-advising all device states");
+                state.devices().await.into_iter().map(|d| d.id).collect()
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Some(hass) = state.get_hass_client().await else {
+            continue;
+        };
+        for id in device_ids {
+            let Some(device) = state.device_by_id(&id).await else {
+                continue;
+            };
+            if let Err(err) = hass.advise_hass_of_light_state(&device, &state).await {
+                log::error!("Failed to advise hass of state of {device}: {err:#}");
+            }
+        }
+    }
+}
+
 pub async fn spawn_hass_integration(
     state: StateHandle,
     args: &HassArguments,
@@ -947,14 +1200,15 @@ pub async fn spawn_hass_integration(
         )
         .await
         .with_context(|| format!("connecting to mqtt broker {mqtt_host}:{mqtt_port}"))?;
+    HEALTH.set_ok(Subsystem::Mqtt);
     let subscriber = client.subscriber().expect("to own the subscriber");
 
     state
-        .set_hass_client(HassClient {
-            client: client.clone(),
-        })
+        .set_hass_client(HassClient::new(client.clone()))
         .await;
 
+    tokio::spawn(run_hass_event_relay(state.clone(), state.subscribe_events()));
+
     let disco_prefix = args.hass_discovery_prefix.clone();
     state.set_hass_disco_prefix(disco_prefix).await;
 
@@ -963,6 +1217,8 @@ pub async fn spawn_hass_integration(
         if let Err(err) = res {
             log::error!("run_mqtt_loop: {err:#}");
             log::error!("FATAL: hass integration will not function.");
+            HEALTH.set_error(Subsystem::Mqtt, format!("{err:#}"));
+            HEALTH.set_fatal(format!("run_mqtt_loop: {err:#}"));
             log::error!("Pausing for 30 seconds before terminating.");
             tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
             std::process::exit(1);
@@ -0,0 +1,186 @@
+use chrono::{DateTime, Utc};
+use parking_lot::{const_mutex, Mutex};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The parts of the service whose health is reported by `/readyz`
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    /// The connection to the MQTT broker used by Home Assistant
+    Mqtt,
+    /// The connection to Govee's AWS IoT broker
+    Iot,
+    /// Whether the Govee Platform API is reachable
+    PlatformApi,
+    /// The socket used for LAN API discovery
+    LanDiscovery,
+    /// Whether our entities have been registered with Home Assistant
+    HassRegistration,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 5] = [
+        Self::Mqtt,
+        Self::Iot,
+        Self::PlatformApi,
+        Self::LanDiscovery,
+        Self::HassRegistration,
+    ];
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// Has not yet reported its status
+    Starting,
+    Ok,
+    Error,
+    /// Not configured, so it doesn't affect readiness
+    Disabled,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug, PartialEq)]
+pub struct SubsystemHealth {
+    pub status: HealthStatus,
+    /// Explains an error, if any
+    pub detail: Option<String>,
+    /// When the status last changed
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Tracks the health of each subsystem
+pub struct HealthRegistry {
+    subsystems: Mutex<BTreeMap<Subsystem, SubsystemHealth>>,
+    fatal: Mutex<Option<String>>,
+}
+
+pub static HEALTH: HealthRegistry = HealthRegistry::new();
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct Liveness {
+    pub alive: bool,
+    pub fatal: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct Readiness {
+    /// True when every subsystem that isn't disabled is ok
+    pub ready: bool,
+    pub subsystems: BTreeMap<Subsystem, SubsystemHealth>,
+}
+
+impl HealthRegistry {
+    pub const fn new() -> Self {
+        Self {
+            subsystems: const_mutex(BTreeMap::new()),
+            fatal: const_mutex(None),
+        }
+    }
+
+    /// Record the current status of a subsystem
+    pub fn set(&self, subsystem: Subsystem, status: HealthStatus, detail: Option<String>) {
+        let mut subsystems = self.subsystems.lock();
+        let since = match subsystems.get(&subsystem) {
+            Some(prior) if prior.status == status => prior.since,
+            _ => Some(Utc::now()),
+        };
+        subsystems.insert(
+            subsystem,
+            SubsystemHealth {
+                status,
+                detail,
+                since,
+            },
+        );
+    }
+
+    pub fn set_ok(&self, subsystem: Subsystem) {
+        self.set(subsystem, HealthStatus::Ok, None);
+    }
+
+    pub fn set_error(&self, subsystem: Subsystem, detail: String) {
+        self.set(subsystem, HealthStatus::Error, Some(detail));
+    }
+
+    pub fn set_disabled(&self, subsystem: Subsystem) {
+        self.set(subsystem, HealthStatus::Disabled, None);
+    }
+
+    /// Record that the service has failed in a way that it cannot
+    /// recover from, and is about to terminate
+    pub fn set_fatal(&self, reason: String) {
+        self.fatal.lock().replace(reason);
+    }
+
+    /// Reports whether the process is functioning; it is not, once
+    /// a fatal error has been recorded
+    pub fn liveness(&self) -> Liveness {
+        let fatal = self.fatal.lock().clone();
+        Liveness {
+            alive: fatal.is_none(),
+            fatal,
+        }
+    }
+
+    pub fn readiness(&self) -> Readiness {
+        let recorded = self.subsystems.lock();
+        let subsystems: BTreeMap<_, _> = Subsystem::ALL
+            .iter()
+            .map(|subsystem| {
+                let health = recorded.get(subsystem).cloned().unwrap_or(SubsystemHealth {
+                    status: HealthStatus::Starting,
+                    detail: None,
+                    since: None,
+                });
+                (*subsystem, health)
+            })
+            .collect();
+        let ready = self.fatal.lock().is_none()
+            && subsystems
+                .values()
+                .all(|health| matches!(health.status, HealthStatus::Ok | HealthStatus::Disabled));
+
+        Readiness { ready, subsystems }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn readiness() {
+        let health = HealthRegistry::new();
+        let readiness = health.readiness();
+        assert!(!readiness.ready);
+        assert_eq!(readiness.subsystems.len(), Subsystem::ALL.len());
+        assert_eq!(
+            readiness.subsystems[&Subsystem::Mqtt].status,
+            HealthStatus::Starting
+        );
+
+        health.set_ok(Subsystem::Mqtt);
+        health.set_ok(Subsystem::HassRegistration);
+        health.set_ok(Subsystem::LanDiscovery);
+        health.set_disabled(Subsystem::Iot);
+        health.set_error(Subsystem::PlatformApi, "status 500".to_string());
+        let readiness = health.readiness();
+        assert!(!readiness.ready);
+        assert_eq!(
+            readiness.subsystems[&Subsystem::PlatformApi]
+                .detail
+                .as_deref(),
+            Some("status 500")
+        );
+
+        health.set_ok(Subsystem::PlatformApi);
+        assert!(health.readiness().ready);
+        assert!(health.liveness().alive);
+
+        health.set_fatal("mqtt loop failed".to_string());
+        assert!(!health.readiness().ready);
+        assert!(!health.liveness().alive);
+    }
+}
@@ -1,25 +1,106 @@
+use crate::error::{error_kind, ErrorKind};
+use crate::hass_mqtt::work_mode::ParsedWorkMode;
+use crate::opt_env_var;
+use crate::platform_api::DeviceCapabilityKind;
 use crate::service::coordinator::Coordinator;
 use crate::service::device::{Device, DeviceState};
+use crate::service::diagnostics::{build_diagnostics_bundle, bundle_file_name, DeviceDebug};
+use crate::service::events::next_event;
+use crate::service::health::HEALTH;
+use crate::service::http_auth::{require_scope, HttpAuthArguments, Scope};
+use crate::service::metrics::render_metrics;
 use crate::service::state::StateHandle;
+use crate::service::transport::TransportKind;
+use crate::service::transport_health::TransportStats;
+use crate::undoc_api::to_redacted_json;
 use anyhow::Context;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
 use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::handler::Handler;
+use axum::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+use axum::http::{Method, StatusCode};
+use axum::middleware::from_fn_with_state;
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
-use axum::routing::get;
+use axum::routing::{get, MethodRouter};
 use axum::{Json, Router};
-use serde::Serialize;
-use std::net::IpAddr;
+use futures_util::Stream;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::Arc;
 use tower_http::services::ServeDir;
 
+#[derive(clap::Parser, Debug)]
+pub struct HttpArguments {
+    /// The port on which the HTTP API will listen
+    #[arg(long, default_value_t = 8056)]
+    pub http_port: u16,
+
+    /// Also serve the older `GET /api/device/:id/power/on` style
+    /// control routes, for compatibility with existing scripts.
+    /// New integrations should use `PUT /api/device/:id/state`.
+    /// You may also set GOVEE_HTTP_LEGACY_GET_ROUTES=true via
+    /// the environment.
+    #[arg(long)]
+    pub http_legacy_get_routes: bool,
+
+    /// The address on which the HTTP API will listen.
+    /// Use 127.0.0.1 to only accept connections from the local
+    /// machine, or :: to listen on IPv6 as well as IPv4.
+    /// You may also set this via the GOVEE_HTTP_BIND environment
+    /// variable. If unspecified, uses 0.0.0.0.
+    #[arg(long)]
+    pub http_bind: Option<IpAddr>,
+
+    #[command(flatten)]
+    pub auth_args: HttpAuthArguments,
+}
+
+impl HttpArguments {
+    pub fn legacy_get_routes(&self) -> anyhow::Result<bool> {
+        if let Some(v) = opt_env_var::<String>("GOVEE_HTTP_LEGACY_GET_ROUTES")? {
+            return crate::lan_api::truthy(&v);
+        }
+        Ok(self.http_legacy_get_routes)
+    }
+
+    pub fn bind_address(&self) -> anyhow::Result<IpAddr> {
+        resolve_bind_address(self.http_bind)
+    }
+}
+
+/// Returns the address on which the HTTP API listens, given the
+/// value of `--http-bind`, if any
+pub fn resolve_bind_address(http_bind: Option<IpAddr>) -> anyhow::Result<IpAddr> {
+    match http_bind {
+        Some(addr) => Ok(addr),
+        None => Ok(opt_env_var("GOVEE_HTTP_BIND")?.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))),
+    }
+}
+
+/// The body of the responses that don't return any other data,
+/// including errors
+#[derive(Serialize, JsonSchema)]
+pub struct ApiResponse {
+    /// The HTTP status code
+    pub code: u16,
+    pub msg: String,
+}
+
 fn response_with_code<T: ToString + std::fmt::Display>(code: StatusCode, err: T) -> Response {
     if !code.is_success() {
         log::error!("err: {err:#}");
     }
 
-    let mut response = Json(serde_json::json!({
-        "code": code.as_u16(),
-        "msg": format!("{err:#}")
-    }))
+    let mut response = Json(ApiResponse {
+        code: code.as_u16(),
+        msg: format!("{err:#}"),
+    })
     .into_response();
     *response.status_mut() = code;
     response
@@ -29,6 +110,18 @@ fn generic<T: ToString + std::fmt::Display>(err: T) -> Response {
     response_with_code(StatusCode::INTERNAL_SERVER_ERROR, err)
 }
 
+/// Reports a failure to control a device with a status code
+/// that reflects the kind of the failure
+fn command_failed(err: anyhow::Error) -> Response {
+    let code = match error_kind(&err) {
+        ErrorKind::Transient => StatusCode::SERVICE_UNAVAILABLE,
+        ErrorKind::Unsupported => StatusCode::UNPROCESSABLE_ENTITY,
+        ErrorKind::Auth => StatusCode::BAD_GATEWAY,
+        ErrorKind::Permanent => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    response_with_code(code, err)
+}
+
 fn not_found<T: ToString + std::fmt::Display>(err: T) -> Response {
     response_with_code(StatusCode::NOT_FOUND, err)
 }
@@ -51,34 +144,39 @@ async fn resolve_device_read_only(state: &StateHandle, id: &str) -> Result<Devic
     state.resolve_device_read_only(&id).await.map_err(not_found)
 }
 
+#[derive(Serialize, JsonSchema)]
+pub struct DeviceItem {
+    pub sku: String,
+    pub id: String,
+    pub name: String,
+    pub room: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub state: Option<DeviceState>,
+    /// The health of each transport that has been used to control
+    /// the device, keyed by transport
+    pub transports: BTreeMap<TransportKind, TransportStats>,
+}
+
 /// Returns a json array of device information
 async fn list_devices(State(state): State<StateHandle>) -> Result<Response, Response> {
     let mut devices = state.devices().await;
     devices.sort_by_key(|d| (d.room_name().map(|name| name.to_string()), d.name()));
 
-    #[derive(Serialize)]
-    struct DeviceItem {
-        pub sku: String,
-        pub id: String,
-        pub name: String,
-        pub room: Option<String>,
-        pub ip: Option<IpAddr>,
-        pub state: Option<DeviceState>,
-    }
-
-    let devices: Vec<_> = devices
-        .into_iter()
-        .map(|d| DeviceItem {
+    let mut items = vec![];
+    for d in devices {
+        let transports = state.transport_health_for_device(&d.id).await;
+        items.push(DeviceItem {
             name: d.name(),
             room: d.room_name().map(|r| r.to_string()),
             ip: d.ip_addr(),
             state: d.device_state(),
+            transports,
             sku: d.sku,
             id: d.id,
-        })
-        .collect();
+        });
+    }
 
-    Ok(Json(devices).into_response())
+    Ok(Json(items).into_response())
 }
 
 /// Turns on a given device
@@ -91,7 +189,7 @@ async fn device_power_on(
     state
         .device_power_on(&device, true)
         .await
-        .map_err(generic)?;
+        .map_err(command_failed)?;
 
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
@@ -106,7 +204,7 @@ async fn device_power_off(
     state
         .device_power_on(&device, false)
         .await
-        .map_err(generic)?;
+        .map_err(command_failed)?;
 
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
@@ -121,7 +219,7 @@ async fn device_set_brightness(
     state
         .device_set_brightness(&device, level)
         .await
-        .map_err(generic)?;
+        .map_err(command_failed)?;
 
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
@@ -136,7 +234,7 @@ async fn device_set_color_temperature(
     state
         .device_set_color_temperature(&device, kelvin)
         .await
-        .map_err(generic)?;
+        .map_err(command_failed)?;
 
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
@@ -155,7 +253,7 @@ async fn device_set_color(
     state
         .device_set_color_rgb(&device, r, g, b)
         .await
-        .map_err(generic)?;
+        .map_err(command_failed)?;
 
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
@@ -170,11 +268,305 @@ async fn device_set_scene(
     state
         .device_set_scene(&device, &scene)
         .await
-        .map_err(generic)?;
+        .map_err(command_failed)?;
+
+    Ok(response_with_code(StatusCode::OK, "ok"))
+}
+
+/// The changes are applied in the order: power, brightness,
+/// color, kelvin, scene, work_mode
+#[derive(Deserialize, JsonSchema, Debug, Default)]
+#[serde(deny_unknown_fields)]
+#[schemars(example = "device_state_request_example")]
+pub struct DeviceStateRequest {
+    power: Option<bool>,
+    /// The brightness in percent
+    #[schemars(range(max = 100))]
+    brightness: Option<u8>,
+    /// Any CSS color, eg: `red`, `#ff0000` or `rgb(255, 0, 0)`
+    color: Option<String>,
+    kelvin: Option<u32>,
+    scene: Option<String>,
+    work_mode: Option<WorkModeRequest>,
+}
+
+fn device_state_request_example() -> JsonValue {
+    serde_json::json!({"power": true, "brightness": 80, "color": "orange"})
+}
+
+#[derive(Deserialize, JsonSchema, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WorkModeRequest {
+    /// The name of the mode, as shown in the mode select entity
+    mode: String,
+    /// The mode specific parameter; if omitted, the default
+    /// for the mode is used
+    value: Option<i64>,
+}
+
+/// Changes any combination of the power, brightness, color, color
+/// temperature, scene and work mode of a given device.
+/// The changes are applied in that order while holding the device
+/// for control, so they cannot interleave with other requests.
+async fn device_set_state(
+    State(state): State<StateHandle>,
+    Path(id): Path<String>,
+    request: Result<Json<DeviceStateRequest>, JsonRejection>,
+) -> Result<Response, Response> {
+    let Json(request) = request.map_err(|err| bad_request(err.body_text()))?;
+
+    // Validate everything up front, so that a bad request
+    // doesn't leave the device partially updated
+    if let Some(level) = request.brightness {
+        if level > 100 {
+            return Err(bad_request(format!(
+                "brightness {level} is out of range 0-100"
+            )));
+        }
+    }
+
+    let color = match &request.color {
+        Some(color) => {
+            let parsed = csscolorparser::parse(color)
+                .map_err(|err| bad_request(format!("error parsing color '{color}': {err}")))?;
+            let [r, g, b, _a] = parsed.to_rgba8();
+            Some((r, g, b))
+        }
+        None => None,
+    };
+
+    let device = resolve_device_for_control(&state, &id).await?;
+
+    if let Some(kelvin) = request.kelvin {
+        check_kelvin(&device, kelvin).map_err(bad_request)?;
+    }
+
+    let scene = match &request.scene {
+        Some(scene) => {
+            let scenes = state
+                .device_list_scenes(&device)
+                .await
+                .context("scenes")
+                .map_err(command_failed)?;
+            Some(resolve_scene(scene, &scenes).map_err(bad_request)?)
+        }
+        None => None,
+    };
+
+    let work_mode = match &request.work_mode {
+        Some(WorkModeRequest { mode, value }) => {
+            let work_modes = ParsedWorkMode::with_device(&device).map_err(bad_request)?;
+            let work_mode = work_modes
+                .mode_by_name(mode)
+                .ok_or_else(|| bad_request(format!("mode {mode} not found")))?;
+            let mode_num = work_mode
+                .value
+                .as_i64()
+                .ok_or_else(|| generic("expected workMode to be a number"))?;
+            Some((mode_num, value.unwrap_or_else(|| work_mode.default_value())))
+        }
+        None => None,
+    };
+
+    if request.power.is_none()
+        && request.brightness.is_none()
+        && color.is_none()
+        && request.kelvin.is_none()
+        && scene.is_none()
+        && work_mode.is_none()
+    {
+        return Err(bad_request("no changes were requested"));
+    }
+
+    if let Some(on) = request.power {
+        state
+            .device_power_on(&device, on)
+            .await
+            .context("power")
+            .map_err(command_failed)?;
+    }
+    if let Some(level) = request.brightness {
+        state
+            .device_set_brightness(&device, level)
+            .await
+            .context("brightness")
+            .map_err(command_failed)?;
+    }
+    if let Some((r, g, b)) = color {
+        state
+            .device_set_color_rgb(&device, r, g, b)
+            .await
+            .context("color")
+            .map_err(command_failed)?;
+    }
+    if let Some(kelvin) = request.kelvin {
+        state
+            .device_set_color_temperature(&device, kelvin)
+            .await
+            .context("kelvin")
+            .map_err(command_failed)?;
+    }
+    if let Some(scene) = &scene {
+        state
+            .device_set_scene(&device, scene)
+            .await
+            .context("scene")
+            .map_err(command_failed)?;
+    }
+    if let Some((mode_num, value)) = work_mode {
+        state
+            .humidifier_set_parameter(&device, mode_num, value)
+            .await
+            .context("work_mode")
+            .map_err(command_failed)?;
+    }
 
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
 
+/// Checks that `kelvin` is within the color temperature range of `device`
+fn check_kelvin(device: &Device, kelvin: u32) -> Result<(), String> {
+    let (min, max) = device
+        .get_color_temperature_range()
+        .ok_or_else(|| format!("{device} does not support color temperature"))?;
+    if !(min..=max).contains(&kelvin) {
+        return Err(format!(
+            "kelvin {kelvin} is out of range {min}-{max} for {device}"
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the name from `scenes` that matches `scene`, ignoring case
+fn resolve_scene(scene: &str, scenes: &[String]) -> Result<String, String> {
+    scenes
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(scene))
+        .cloned()
+        .ok_or_else(|| format!("scene '{scene}' is not available for this device"))
+}
+
+/// Returns a JSON array of the Platform API capabilities of a given
+/// device, including the parameters that each one accepts
+async fn device_list_capabilities(
+    State(state): State<StateHandle>,
+    Path(id): Path<String>,
+) -> Result<Response, Response> {
+    let device = resolve_device_read_only(&state, &id).await?;
+
+    let capabilities = device
+        .http_device_info
+        .map(|info| info.capabilities)
+        .unwrap_or_default();
+
+    Ok(Json(capabilities).into_response())
+}
+
+/// Sends the JSON body as the value of the named capability of a
+/// given device, via the Platform API, after validating it against
+/// the parameters of the capability
+async fn device_control_capability(
+    State(state): State<StateHandle>,
+    Path((id, instance)): Path<(String, String)>,
+    value: Result<Json<JsonValue>, JsonRejection>,
+) -> Result<Response, Response> {
+    let Json(value) = value.map_err(|err| bad_request(err.body_text()))?;
+
+    let device = resolve_device_for_control(&state, &id).await?;
+
+    let capability = device
+        .http_device_info
+        .as_ref()
+        .and_then(|info| info.capability_by_instance(&instance))
+        .ok_or_else(|| not_found(format!("{device} has no capability {instance}")))?;
+
+    if matches!(
+        capability.kind,
+        DeviceCapabilityKind::Online | DeviceCapabilityKind::Property | DeviceCapabilityKind::Event
+    ) {
+        return Err(bad_request(format!(
+            "{instance} is {}, which cannot be controlled",
+            capability.kind
+        )));
+    }
+
+    let value = match &capability.parameters {
+        Some(params) => params
+            .validate_value(&value)
+            .map_err(|err| bad_request(format!("{instance}: {err:#}")))?,
+        None => value,
+    };
+
+    state
+        .device_control(&device, capability, value)
+        .await
+        .map_err(command_failed)?;
+
+    Ok(response_with_code(StatusCode::OK, "ok"))
+}
+
+/// Returns everything that we know about a given device, for use
+/// when troubleshooting. Sensitive values are redacted.
+async fn device_debug(
+    State(state): State<StateHandle>,
+    Path(id): Path<String>,
+) -> Result<Response, Response> {
+    let device = resolve_device_read_only(&state, &id).await?;
+
+    let debug = DeviceDebug::new(&state, &device).await.map_err(generic)?;
+
+    Ok(Json(to_redacted_json(&debug).map_err(generic)?).into_response())
+}
+
+/// Reports whether the service is alive; it is not once it has hit
+/// a fatal error and is about to terminate
+async fn healthz() -> Response {
+    let liveness = HEALTH.liveness();
+    let code = if liveness.alive {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(liveness)).into_response()
+}
+
+/// Reports the status of each subsystem, and whether the service
+/// is ready, which requires all of them to be ok or disabled
+async fn readyz() -> Response {
+    let readiness = HEALTH.readiness();
+    let code = if readiness.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(readiness)).into_response()
+}
+
+/// Returns metrics in the Prometheus text exposition format
+async fn metrics(State(state): State<StateHandle>) -> Response {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        render_metrics(&state).await,
+    )
+        .into_response()
+}
+
+/// Returns a tar archive containing a diagnostics bundle, suitable
+/// for attaching to an issue report. Sensitive values are scrubbed.
+async fn diagnostics(State(state): State<StateHandle>) -> Result<Response, Response> {
+    let bundle = build_diagnostics_bundle(&state).await.map_err(generic)?;
+
+    let disposition = format!("attachment; filename=\"{}\"", bundle_file_name());
+    Ok((
+        [
+            (CONTENT_TYPE, "application/x-tar".to_string()),
+            (CONTENT_DISPOSITION, disposition),
+        ],
+        bundle,
+    )
+        .into_response())
+}
+
 /// Returns a JSON array of the available scene names for a given device
 async fn device_list_scenes(
     State(state): State<StateHandle>,
@@ -187,6 +579,73 @@ async fn device_list_scenes(
     Ok(Json(scenes).into_response())
 }
 
+/// Returns a JSON array of the recent state changes for a given device,
+/// oldest first
+async fn device_state_history(
+    State(state): State<StateHandle>,
+    Path(id): Path<String>,
+) -> Result<Response, Response> {
+    let device = resolve_device_read_only(&state, &id).await?;
+
+    let history = state.state_history_for_device(&device.id).await;
+
+    Ok(Json(history).into_response())
+}
+
+/// Streams events as they happen, using Server-Sent Events.
+/// Each event is named after its `type` and carries the JSON
+/// representation of the event as its data.
+async fn event_stream(
+    State(state): State<StateHandle>,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    let events = state.subscribe_events();
+    let stream = futures_util::stream::unfold(events, |mut events| async move {
+        let event = next_event(&mut events, "SSE client").await?;
+        let sse = SseEvent::default()
+            .event(event.name())
+            .json_data(&event)
+            .unwrap_or_else(|err| SseEvent::default().event("error").data(format!("{err:#}")));
+        Some((Ok(sse), events))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Streams events as they happen over a WebSocket.
+/// Each event is sent as a JSON text message.
+async fn event_websocket(ws: WebSocketUpgrade, State(state): State<StateHandle>) -> Response {
+    ws.on_upgrade(move |socket| relay_events_to_websocket(socket, state))
+}
+
+async fn relay_events_to_websocket(mut socket: WebSocket, state: StateHandle) {
+    let mut events = state.subscribe_events();
+    loop {
+        tokio::select! {
+            event = next_event(&mut events, "WebSocket client") => {
+                let Some(event) = event else {
+                    break;
+                };
+                let text = match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        log::error!("Failed to serialize {event:?}: {err:#}");
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            msg = socket.recv() => {
+                match msg {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // We don't accept any requests via the socket
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
+
 async fn list_one_clicks(State(state): State<StateHandle>) -> Result<Response, Response> {
     let undoc = state
         .get_undoc_client()
@@ -220,7 +679,7 @@ async fn activate_one_click(
         .ok_or_else(|| anyhow::anyhow!("AWS IoT client is not available"))
         .map_err(generic)?;
 
-    iot.activate_one_click(&item).await.map_err(generic)?;
+    iot.activate_one_click(item).await.map_err(command_failed)?;
 
     Ok(response_with_code(StatusCode::OK, "ok"))
 }
@@ -229,31 +688,160 @@ async fn redirect_to_index() -> Response {
     axum::response::Redirect::to("/assets/index.html").into_response()
 }
 
-pub async fn run_http_server(state: StateHandle, port: u16) -> anyhow::Result<()> {
-    let app = Router::new()
-        .route("/api/devices", get(list_devices))
-        .route("/api/device/:id/power/on", get(device_power_on))
-        .route("/api/device/:id/power/off", get(device_power_off))
-        .route(
-            "/api/device/:id/brightness/:level",
-            get(device_set_brightness),
-        )
-        .route(
-            "/api/device/:id/colortemp/:kelvin",
-            get(device_set_color_temperature),
-        )
-        .route("/api/device/:id/color/:color", get(device_set_color))
-        .route("/api/device/:id/scene/:scene", get(device_set_scene))
-        .route("/api/device/:id/scenes", get(device_list_scenes))
-        .route("/api/oneclicks", get(list_one_clicks))
-        .route("/api/oneclick/activate/:scene", get(activate_one_click))
+/// A route of the HTTP API, which is served under `/api`,
+/// with the exception of `/metrics`, `/healthz` and `/readyz`.
+/// Every API route must be listed in `api_routes` so that it is
+/// subject to authentication and is described by `/api/openapi.json`.
+pub struct ApiRoute {
+    pub method: Method,
+    pub path: &'static str,
+    pub scope: Scope,
+    handler: MethodRouter<StateHandle>,
+}
+
+impl ApiRoute {
+    fn new(
+        method: Method,
+        path: &'static str,
+        scope: Scope,
+        handler: MethodRouter<StateHandle>,
+    ) -> Self {
+        Self {
+            method,
+            path,
+            scope,
+            handler,
+        }
+    }
+
+    fn get<H, T>(path: &'static str, scope: Scope, handler: H) -> Self
+    where
+        H: Handler<T, StateHandle>,
+        T: 'static,
+    {
+        Self::new(Method::GET, path, scope, axum::routing::get(handler))
+    }
+
+    fn put<H, T>(path: &'static str, scope: Scope, handler: H) -> Self
+    where
+        H: Handler<T, StateHandle>,
+        T: 'static,
+    {
+        Self::new(Method::PUT, path, scope, axum::routing::put(handler))
+    }
+
+    fn post<H, T>(path: &'static str, scope: Scope, handler: H) -> Self
+    where
+        H: Handler<T, StateHandle>,
+        T: 'static,
+    {
+        Self::new(Method::POST, path, scope, axum::routing::post(handler))
+    }
+}
+
+pub fn api_routes(legacy_get_routes: bool) -> Vec<ApiRoute> {
+    use Scope::{Control, Public, ReadOnly};
+
+    let mut routes = vec![
+        ApiRoute::get("/api/openapi.json", ReadOnly, move || async move {
+            Json(crate::service::openapi::document(legacy_get_routes))
+        }),
+        ApiRoute::get("/api/devices", ReadOnly, list_devices),
+        ApiRoute::put("/api/device/:id/state", Control, device_set_state),
+        ApiRoute::post("/api/device/:id/state", Control, device_set_state),
+        ApiRoute::get(
+            "/api/device/:id/capabilities",
+            ReadOnly,
+            device_list_capabilities,
+        ),
+        ApiRoute::put(
+            "/api/device/:id/capability/:instance",
+            Control,
+            device_control_capability,
+        ),
+        ApiRoute::post(
+            "/api/device/:id/capability/:instance",
+            Control,
+            device_control_capability,
+        ),
+        ApiRoute::get("/api/device/:id/debug", ReadOnly, device_debug),
+        ApiRoute::get("/api/diagnostics", ReadOnly, diagnostics),
+        ApiRoute::get("/metrics", ReadOnly, metrics),
+        ApiRoute::get("/healthz", Public, healthz),
+        ApiRoute::get("/readyz", Public, readyz),
+        ApiRoute::get("/api/device/:id/scenes", ReadOnly, device_list_scenes),
+        ApiRoute::get("/api/device/:id/history", ReadOnly, device_state_history),
+        ApiRoute::get("/api/events", ReadOnly, event_stream),
+        ApiRoute::get("/api/ws", ReadOnly, event_websocket),
+        ApiRoute::get("/api/oneclicks", ReadOnly, list_one_clicks),
+        ApiRoute::get("/api/oneclick/activate/:scene", Control, activate_one_click),
+    ];
+
+    if legacy_get_routes {
+        routes.extend([
+            ApiRoute::get("/api/device/:id/power/on", Control, device_power_on),
+            ApiRoute::get("/api/device/:id/power/off", Control, device_power_off),
+            ApiRoute::get(
+                "/api/device/:id/brightness/:level",
+                Control,
+                device_set_brightness,
+            ),
+            ApiRoute::get(
+                "/api/device/:id/colortemp/:kelvin",
+                Control,
+                device_set_color_temperature,
+            ),
+            ApiRoute::get("/api/device/:id/color/:color", Control, device_set_color),
+            ApiRoute::get("/api/device/:id/scene/:scene", Control, device_set_scene),
+        ]);
+    }
+
+    routes
+}
+
+pub async fn run_http_server(state: StateHandle, args: &HttpArguments) -> anyhow::Result<()> {
+    let port = args.http_port;
+    let bind = args.bind_address()?;
+    let auth = Arc::new(args.auth_args.to_http_auth()?);
+    if !auth.is_enabled() {
+        log::warn!(
+            "The HTTP API does not require authentication; \
+             anyone that can reach {bind}:{port} can control your devices"
+        );
+    }
+
+    let legacy_get_routes = args.legacy_get_routes()?;
+    if legacy_get_routes {
+        log::warn!(
+            "Serving the legacy GET control routes; \
+             please migrate to PUT /api/device/:id/state"
+        );
+    }
+
+    let mut app = Router::new();
+    for route in api_routes(legacy_get_routes) {
+        app = app.route(
+            route.path,
+            route.handler.route_layer(from_fn_with_state(
+                (auth.clone(), route.scope),
+                require_scope,
+            )),
+        );
+    }
+
+    let ui = Router::new()
         .route("/", get(redirect_to_index))
         .nest_service("/assets", ServeDir::new("assets"))
-        .with_state(state);
+        .route_layer(from_fn_with_state(
+            (auth.clone(), Scope::ReadOnly),
+            require_scope,
+        ));
 
-    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port))
+    let app = app.merge(ui).with_state(state);
+
+    let listener = tokio::net::TcpListener::bind((bind, port))
         .await
-        .with_context(|| format!("run_http_server: binding to port {port}"))?;
+        .with_context(|| format!("run_http_server: binding to {bind} port {port}"))?;
     let addr = listener.local_addr()?;
     log::info!("http server addr is {addr:?}");
     if let Err(err) = axum::serve(listener, app).await {
@@ -262,3 +850,55 @@ pub async fn run_http_server(state: StateHandle, port: u16) -> anyhow::Result<()
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_state_request() {
+        let request: DeviceStateRequest = serde_json::from_str(
+            r#"{"power": true, "color": "orange", "work_mode": {"mode": "Manual"}}"#,
+        )
+        .unwrap();
+        assert_eq!(request.power, Some(true));
+        assert_eq!(request.color.as_deref(), Some("orange"));
+        assert_eq!(request.work_mode.unwrap().value, None);
+        assert_eq!(request.brightness, None);
+
+        assert!(serde_json::from_str::<DeviceStateRequest>(r#"{"on": true}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn set_state_validation() {
+        let state = Arc::new(crate::service::state::State::new());
+        let device = Device::new("H6159", "AA:BB:CC:DD:EE:FF:00:01");
+        drop(state.device_mut(&device.sku, &device.id).await);
+
+        let set_state = |json: JsonValue| {
+            let state = state.clone();
+            let id = device.id.clone();
+            async move {
+                let request = serde_json::from_value(json).unwrap();
+                device_set_state(State(state), Path(id), Ok(Json(request))).await
+            }
+        };
+
+        let response = set_state(serde_json::json!({"brightness": 101}))
+            .await
+            .unwrap_err();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let (min, max) = device.get_color_temperature_range().unwrap();
+        for kelvin in [min - 1, max + 1] {
+            let response = set_state(serde_json::json!({"power": true, "kelvin": kelvin}))
+                .await
+                .unwrap_err();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+
+        let scenes = vec!["Sunrise".to_string(), "Music: Energic".to_string()];
+        assert_eq!(resolve_scene("sunrise", &scenes).unwrap(), "Sunrise");
+        assert!(resolve_scene("Sunset", &scenes).is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use crate::opt_env_var;
+use axum::extract::{Request, State};
+use axum::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::sync::Arc;
+
+/// What a set of credentials is allowed to do.
+/// Control implies ReadOnly, which implies Public.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Available without credentials, such as health checks
+    Public,
+    /// Observe devices and their state
+    ReadOnly,
+    /// Change the state of devices
+    Control,
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let label = match self {
+            Self::Public => "public",
+            Self::ReadOnly => "read-only",
+            Self::Control => "control",
+        };
+        label.fmt(fmt)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Credential {
+    Bearer(String),
+    Basic { user: String, password: String },
+}
+
+impl Credential {
+    fn parse_basic(s: &str) -> anyhow::Result<Self> {
+        let (user, password) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("expected USER:PASSWORD"))?;
+        anyhow::ensure!(!user.is_empty(), "expected USER:PASSWORD, user is empty");
+        Ok(Self::Basic {
+            user: user.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    fn matches(&self, authorization: &Authorization) -> bool {
+        match (self, authorization) {
+            (Self::Bearer(expect), Authorization::Bearer(token)) => {
+                constant_time_eq(expect.as_bytes(), token.as_bytes())
+            }
+            (
+                Self::Basic { user, password },
+                Authorization::Basic {
+                    user: got_user,
+                    password: got_password,
+                },
+            ) => {
+                // Avoid short-circuiting, so that the timing doesn't
+                // reveal whether the user name was correct
+                let user_ok = constant_time_eq(user.as_bytes(), got_user.as_bytes());
+                let password_ok = constant_time_eq(password.as_bytes(), got_password.as_bytes());
+                user_ok & password_ok
+            }
+            _ => false,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a, b)
+}
+
+/// The credentials presented in a request
+#[derive(Debug, PartialEq, Eq)]
+enum Authorization {
+    Bearer(String),
+    Basic { user: String, password: String },
+}
+
+impl Authorization {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, param) = value.trim().split_once(' ')?;
+        let param = param.trim();
+
+        if scheme.eq_ignore_ascii_case("bearer") {
+            Some(Self::Bearer(param.to_string()))
+        } else if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = data_encoding::BASE64.decode(param.as_bytes()).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (user, password) = decoded.split_once(':')?;
+            Some(Self::Basic {
+                user: user.to_string(),
+                password: password.to_string(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct HttpAuthArguments {
+    /// Require this bearer token for requests that control devices.
+    /// It also grants read-only access.
+    /// You may also set this via the GOVEE_HTTP_CONTROL_TOKEN
+    /// environment variable.
+    #[arg(long)]
+    pub http_control_token: Option<String>,
+
+    /// Accept this bearer token for requests that only observe
+    /// devices and their state.
+    /// You may also set this via the GOVEE_HTTP_READ_ONLY_TOKEN
+    /// environment variable.
+    #[arg(long)]
+    pub http_read_only_token: Option<String>,
+
+    /// Accept HTTP basic auth with these credentials, specified
+    /// as USER:PASSWORD, for requests that control devices.
+    /// It also grants read-only access.
+    /// You may also set this via the GOVEE_HTTP_CONTROL_BASIC_AUTH
+    /// environment variable.
+    #[arg(long)]
+    pub http_control_basic_auth: Option<String>,
+
+    /// Accept HTTP basic auth with these credentials, specified
+    /// as USER:PASSWORD, for requests that only observe devices
+    /// and their state.
+    /// You may also set this via the GOVEE_HTTP_READ_ONLY_BASIC_AUTH
+    /// environment variable.
+    #[arg(long)]
+    pub http_read_only_basic_auth: Option<String>,
+}
+
+impl HttpAuthArguments {
+    fn opt_arg_or_env(arg: &Option<String>, name: &str) -> anyhow::Result<Option<String>> {
+        match arg {
+            Some(v) => Ok(Some(v.to_string())),
+            None => Ok(opt_env_var::<String>(name)?.filter(|v| !v.is_empty())),
+        }
+    }
+
+    pub fn to_http_auth(&self) -> anyhow::Result<HttpAuth> {
+        let mut auth = HttpAuth::default();
+
+        if let Some(token) =
+            Self::opt_arg_or_env(&self.http_control_token, "GOVEE_HTTP_CONTROL_TOKEN")?
+        {
+            auth.add(Scope::Control, Credential::Bearer(token));
+        }
+        if let Some(token) =
+            Self::opt_arg_or_env(&self.http_read_only_token, "GOVEE_HTTP_READ_ONLY_TOKEN")?
+        {
+            auth.add(Scope::ReadOnly, Credential::Bearer(token));
+        }
+        if let Some(creds) = Self::opt_arg_or_env(
+            &self.http_control_basic_auth,
+            "GOVEE_HTTP_CONTROL_BASIC_AUTH",
+        )? {
+            let cred = Credential::parse_basic(&creds)
+                .map_err(|err| anyhow::anyhow!("http control basic auth: {err:#}"))?;
+            auth.add(Scope::Control, cred);
+        }
+        if let Some(creds) = Self::opt_arg_or_env(
+            &self.http_read_only_basic_auth,
+            "GOVEE_HTTP_READ_ONLY_BASIC_AUTH",
+        )? {
+            let cred = Credential::parse_basic(&creds)
+                .map_err(|err| anyhow::anyhow!("http read-only basic auth: {err:#}"))?;
+            auth.add(Scope::ReadOnly, cred);
+        }
+
+        Ok(auth)
+    }
+}
+
+/// The credentials accepted by the HTTP server.
+/// When none are configured, all requests are permitted.
+#[derive(Debug, Default)]
+pub struct HttpAuth {
+    credentials: Vec<(Scope, Credential)>,
+}
+
+impl HttpAuth {
+    fn add(&mut self, scope: Scope, credential: Credential) {
+        self.credentials.push((scope, credential));
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.credentials.is_empty()
+    }
+
+    fn has_basic_auth(&self) -> bool {
+        self.credentials
+            .iter()
+            .any(|(_, cred)| matches!(cred, Credential::Basic { .. }))
+    }
+
+    /// Returns the scope granted to the request, or None if it
+    /// didn't present acceptable credentials
+    fn granted_scope(&self, headers: &HeaderMap) -> Option<Scope> {
+        if !self.is_enabled() {
+            return Some(Scope::Control);
+        }
+        let authorization = Authorization::from_headers(headers)?;
+        self.credentials
+            .iter()
+            .filter(|(_, cred)| cred.matches(&authorization))
+            .map(|(scope, _)| *scope)
+            .max()
+    }
+
+    /// Returns the response to send if the request is not
+    /// permitted to access a route that requires `required`
+    fn rejection(&self, headers: &HeaderMap, required: Scope) -> Option<Response> {
+        if required == Scope::Public {
+            return None;
+        }
+        match self.granted_scope(headers) {
+            Some(scope) if scope >= required => None,
+            Some(_) => Some(auth_error(
+                StatusCode::FORBIDDEN,
+                format!("{required} access is required"),
+                None,
+            )),
+            None => {
+                let challenge = if self.has_basic_auth() {
+                    r#"Basic realm="govee2mqtt""#
+                } else {
+                    r#"Bearer realm="govee2mqtt""#
+                };
+                Some(auth_error(
+                    StatusCode::UNAUTHORIZED,
+                    "authentication is required".to_string(),
+                    Some(challenge),
+                ))
+            }
+        }
+    }
+}
+
+fn auth_error(code: StatusCode, msg: String, challenge: Option<&'static str>) -> Response {
+    log::warn!("http: {msg}");
+    let mut response = Json(serde_json::json!({
+        "code": code.as_u16(),
+        "msg": msg,
+    }))
+    .into_response();
+    *response.status_mut() = code;
+    if let Some(challenge) = challenge {
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+    }
+    response
+}
+
+/// Middleware that rejects requests that don't have the scope
+/// required by the route.  Use with `axum::middleware::from_fn_with_state`.
+pub async fn require_scope(
+    State((auth, required)): State<(Arc<HttpAuth>, Scope)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    match auth.rejection(request.headers(), required) {
+        Some(response) => response,
+        None => next.run(request).await,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    fn basic(user_pass: &str) -> HeaderMap {
+        headers(&format!(
+            "Basic {}",
+            data_encoding::BASE64.encode(user_pass.as_bytes())
+        ))
+    }
+
+    #[test]
+    fn disabled_allows_everything() {
+        let auth = HttpAuth::default();
+        assert!(auth.rejection(&HeaderMap::new(), Scope::Control).is_none());
+    }
+
+    #[test]
+    fn scopes() {
+        let mut auth = HttpAuth::default();
+        auth.add(Scope::Control, Credential::Bearer("secret".to_string()));
+        auth.add(Scope::ReadOnly, Credential::Bearer("peek".to_string()));
+        auth.add(
+            Scope::ReadOnly,
+            Credential::parse_basic("viewer:pass:word").unwrap(),
+        );
+
+        assert_eq!(auth.granted_scope(&HeaderMap::new()), None);
+        assert_eq!(
+            auth.granted_scope(&headers("Bearer secret")),
+            Some(Scope::Control)
+        );
+        assert_eq!(
+            auth.granted_scope(&headers("bearer peek")),
+            Some(Scope::ReadOnly)
+        );
+        assert_eq!(auth.granted_scope(&headers("Bearer wrong")), None);
+        assert_eq!(
+            auth.granted_scope(&basic("viewer:pass:word")),
+            Some(Scope::ReadOnly)
+        );
+        assert_eq!(auth.granted_scope(&basic("viewer:pass")), None);
+
+        let err = auth
+            .rejection(&headers("Bearer peek"), Scope::Control)
+            .unwrap();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+
+        assert!(auth.rejection(&HeaderMap::new(), Scope::Public).is_none());
+        assert!(auth
+            .rejection(&headers("Bearer wrong"), Scope::Public)
+            .is_none());
+
+        let err = auth.rejection(&HeaderMap::new(), Scope::ReadOnly).unwrap();
+        assert_eq!(err.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            err.headers().get(WWW_AUTHENTICATE).unwrap(),
+            r#"Basic realm="govee2mqtt""#
+        );
+    }
+
+    #[test]
+    fn parse_basic() {
+        assert!(Credential::parse_basic("nopassword").is_err());
+        assert!(Credential::parse_basic(":password").is_err());
+    }
+}
@@ -1,6 +1,11 @@
 use crate::ble::{Base64HexBytes, GoveeBlePacket, HumidifierAutoMode, NotifyHumidifierMode};
+use crate::error::{Classify, ErrorKind};
 use crate::lan_api::{DeviceColor, DeviceStatus};
 use crate::platform_api::from_json;
+use crate::retry::{retry, retryable, RetryPolicy};
+use crate::service::diagnostics::{record_packet, PacketDirection, PacketTransport};
+use crate::service::health::{Subsystem, HEALTH};
+use crate::service::metrics::{ConnectionTracker, IOT_CONNECTED, IOT_RECONNECTS};
 use crate::service::state::StateHandle;
 use crate::undoc_api::{ms_timestamp, DeviceEntry, LoginAccountResponse, ParsedOneClick};
 use crate::Args;
@@ -9,14 +14,53 @@ use async_channel::Receiver;
 use mosquitto_rs::{Event, QoS};
 use serde::Deserialize;
 use std::time::Duration;
+use thiserror::Error;
 use tokio::time::timeout;
 
+/// The ways in which controlling a device via the IoT API can fail
+#[derive(Error, Debug)]
+pub enum IotError {
+    #[error("{0} has no undocumented API info")]
+    NoDeviceInfo(String),
+    #[error("{0} is not supported by the IoT API")]
+    Unsupported(String),
+    #[error("publishing to the IoT broker failed: {0}")]
+    Publish(#[from] mosquitto_rs::Error),
+}
+
+impl Classify for IotError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::NoDeviceInfo(_) | Self::Unsupported(_) => ErrorKind::Unsupported,
+            Self::Publish(err) => err.kind(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct IotClient {
     client: mosquitto_rs::Client,
 }
 
 impl IotClient {
+    /// Publish a command, retrying if the connection to the broker
+    /// is temporarily unavailable
+    async fn publish(&self, topic: &str, payload: String) -> anyhow::Result<()> {
+        retry(
+            &RetryPolicy::CLOUD,
+            "IotClient::publish",
+            retryable,
+            || async {
+                self.client
+                    .publish(topic, &payload, QoS::AtMostOnce, false)
+                    .await
+                    .map_err(IotError::Publish)?;
+                Ok(())
+            },
+        )
+        .await
+    }
+
     pub fn is_device_compatible(&self, device: &DeviceEntry) -> bool {
         device.device_ext.device_settings.topic.is_some()
     }
@@ -24,21 +68,18 @@ impl IotClient {
     pub async fn request_status_update(&self, device: &DeviceEntry) -> anyhow::Result<()> {
         let device_topic = device.device_topic()?;
 
-        self.client
-            .publish(
-                device_topic,
-                serde_json::to_string(&serde_json::json!({
-                    "msg": {
-                        "cmd": "status",
-                        "cmdVersion": 2,
-                        "transaction": format!("v_{}000", ms_timestamp()),
-                        "type": 0,
-                    }
-                }))?,
-                QoS::AtMostOnce,
-                false,
-            )
-            .await?;
+        self.publish(
+            device_topic,
+            serde_json::to_string(&serde_json::json!({
+                "msg": {
+                    "cmd": "status",
+                    "cmdVersion": 2,
+                    "transaction": format!("v_{}000", ms_timestamp()),
+                    "type": 0,
+                }
+            }))?,
+        )
+        .await?;
 
         Ok(())
     }
@@ -60,50 +101,44 @@ impl IotClient {
             _ => pwr(on, 1, 0),
         };
 
-        self.client
-            .publish(
-                device_topic,
-                serde_json::to_string(&serde_json::json!({
-                    "msg": {
-                        "cmd": "turn",
-                        "data": {
-                            "val": power_state,
-                        },
-                        "cmdVersion": 0,
-                        "transaction": format!("v_{}000", ms_timestamp()),
-                        "type": 1,
-                    }
-                }))?,
-                QoS::AtMostOnce,
-                false,
-            )
-            .await
-            .context("IotClient::set_power_state")?;
+        self.publish(
+            device_topic,
+            serde_json::to_string(&serde_json::json!({
+                "msg": {
+                    "cmd": "turn",
+                    "data": {
+                        "val": power_state,
+                    },
+                    "cmdVersion": 0,
+                    "transaction": format!("v_{}000", ms_timestamp()),
+                    "type": 1,
+                }
+            }))?,
+        )
+        .await
+        .context("IotClient::set_power_state")?;
         Ok(())
     }
 
     pub async fn set_brightness(&self, device: &DeviceEntry, percent: u8) -> anyhow::Result<()> {
         log::trace!("set_brightness for {} to {percent}", device.device);
         let device_topic = device.device_topic()?;
-        self.client
-            .publish(
-                device_topic,
-                serde_json::to_string(&serde_json::json!({
-                    "msg": {
-                        "cmd": "brightness",
-                        "data": {
-                            "val": percent,
-                        },
-                        "cmdVersion": 0,
-                        "transaction": format!("v_{}000", ms_timestamp()),
-                        "type": 1,
-                    }
-                }))?,
-                QoS::AtMostOnce,
-                false,
-            )
-            .await
-            .context("IotClient::set_brightness")?;
+        self.publish(
+            device_topic,
+            serde_json::to_string(&serde_json::json!({
+                "msg": {
+                    "cmd": "brightness",
+                    "data": {
+                        "val": percent,
+                    },
+                    "cmdVersion": 0,
+                    "transaction": format!("v_{}000", ms_timestamp()),
+                    "type": 1,
+                }
+            }))?,
+        )
+        .await
+        .context("IotClient::set_brightness")?;
         Ok(())
     }
 
@@ -115,30 +150,27 @@ impl IotClient {
         log::trace!("set_color_temperature for {} to {kelvin}", device.device);
         let device_topic = device.device_topic()?;
 
-        self.client
-            .publish(
-                device_topic,
-                serde_json::to_string(&serde_json::json!({
-                    "msg": {
-                        "cmd": "colorwc",
-                        "data": {
-                            "color": {
-                                "r": 0,
-                                "g": 0,
-                                "b": 0,
-                            },
-                            "colorTemInKelvin": kelvin,
+        self.publish(
+            device_topic,
+            serde_json::to_string(&serde_json::json!({
+                "msg": {
+                    "cmd": "colorwc",
+                    "data": {
+                        "color": {
+                            "r": 0,
+                            "g": 0,
+                            "b": 0,
                         },
-                        "cmdVersion": 0,
-                        "transaction": format!("v_{}000", ms_timestamp()),
-                        "type": 1,
-                    }
-                }))?,
-                QoS::AtMostOnce,
-                false,
-            )
-            .await
-            .context("IotClient::set_color_temperature")?;
+                        "colorTemInKelvin": kelvin,
+                    },
+                    "cmdVersion": 0,
+                    "transaction": format!("v_{}000", ms_timestamp()),
+                    "type": 1,
+                }
+            }))?,
+        )
+        .await
+        .context("IotClient::set_color_temperature")?;
         Ok(())
     }
 
@@ -152,30 +184,27 @@ impl IotClient {
         log::trace!("set_color_rgb for {} to {r},{g},{b}", device.device);
         let device_topic = device.device_topic()?;
 
-        self.client
-            .publish(
-                device_topic,
-                serde_json::to_string(&serde_json::json!({
-                    "msg": {
-                        "cmd": "colorwc",
-                        "data": {
-                            "color":{
-                                "r": r,
-                                "g": g,
-                                "b": b,
-                            },
-                            "colorTemInKelvin": 0,
+        self.publish(
+            device_topic,
+            serde_json::to_string(&serde_json::json!({
+                "msg": {
+                    "cmd": "colorwc",
+                    "data": {
+                        "color":{
+                            "r": r,
+                            "g": g,
+                            "b": b,
                         },
-                        "cmdVersion": 0,
-                        "transaction": format!("v_{}000", ms_timestamp()),
-                        "type": 1,
-                    }
-                }))?,
-                QoS::AtMostOnce,
-                false,
-            )
-            .await
-            .context("IotClient::set_color_rgb")?;
+                        "colorTemInKelvin": 0,
+                    },
+                    "cmdVersion": 0,
+                    "transaction": format!("v_{}000", ms_timestamp()),
+                    "type": 1,
+                }
+            }))?,
+        )
+        .await
+        .context("IotClient::set_color_rgb")?;
         Ok(())
     }
 
@@ -187,38 +216,29 @@ impl IotClient {
         log::trace!("send_real for {} to {commands:?}", device.device);
         let device_topic = device.device_topic()?;
 
-        self.client
-            .publish(
-                device_topic,
-                serde_json::to_string(&serde_json::json!({
-                    "msg": {
-                        "cmd": "ptReal",
-                        "data": {
-                            "command": commands,
-                        },
-                        "cmdVersion": 0,
-                        "transaction": format!("v_{}000", ms_timestamp()),
-                        "type": 1,
-                    }
-                }))?,
-                QoS::AtMostOnce,
-                false,
-            )
-            .await
-            .context("IotClient::send_real")?;
+        self.publish(
+            device_topic,
+            serde_json::to_string(&serde_json::json!({
+                "msg": {
+                    "cmd": "ptReal",
+                    "data": {
+                        "command": commands,
+                    },
+                    "cmdVersion": 0,
+                    "transaction": format!("v_{}000", ms_timestamp()),
+                    "type": 1,
+                }
+            }))?,
+        )
+        .await
+        .context("IotClient::send_real")?;
         Ok(())
     }
 
     pub async fn activate_one_click(&self, item: &ParsedOneClick) -> anyhow::Result<()> {
         for entry in &item.entries {
             for command in &entry.msgs {
-                self.client
-                    .publish(
-                        entry.topic.as_str(),
-                        serde_json::to_string(command)?,
-                        QoS::AtMostOnce,
-                        false,
-                    )
+                self.publish(entry.topic.as_str(), serde_json::to_string(command)?)
                     .await
                     .context("sending OneClick")?;
             }
@@ -299,6 +319,7 @@ pub async fn start_iot_client(
             log::error!("IoT loop failed: {err:#}");
         }
         log::info!("IoT loop terminated");
+        HEALTH.set_error(Subsystem::Iot, "IoT loop terminated".to_string());
         Ok::<(), anyhow::Error>(())
     });
 
@@ -376,11 +397,19 @@ async fn run_iot_subscriber(
     client: mosquitto_rs::Client,
     acct: LoginAccountResponse,
 ) -> anyhow::Result<()> {
+    let mut connection = ConnectionTracker::new(Subsystem::Iot, &IOT_CONNECTED, &IOT_RECONNECTS);
+    connection.connected();
     while let Ok(event) = subscriptions.recv().await {
         match event {
             Event::Message(msg) => {
                 let payload = String::from_utf8_lossy(&msg.payload);
                 log::trace!("{} -> {payload}", msg.topic);
+                record_packet(
+                    PacketTransport::Iot,
+                    PacketDirection::Received,
+                    &msg.topic,
+                    &msg.payload,
+                );
 
                 match from_json::<Packet, _>(&msg.payload) {
                     Ok(packet) => {
@@ -478,9 +507,11 @@ async fn run_iot_subscriber(
             }
             Event::Disconnected(reason) => {
                 log::warn!("IoT disconnected with reason {reason}");
+                connection.disconnected(reason);
             }
             Event::Connected(status) => {
                 log::info!("IoT (re)connected with status {status}");
+                connection.connected();
 
                 client
                     .subscribe(&acct.topic, mosquitto_rs::QoS::AtMostOnce)
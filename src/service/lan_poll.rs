@@ -0,0 +1,178 @@
+use crate::lan_api::Client as LanClient;
+use crate::service::state::StateHandle;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+
+/// How many consecutive polls a device can fail to answer
+/// before we consider it to be stale
+const STALE_AFTER_MISSES: u32 = 3;
+/// How long to wait for a device to answer a poll
+const POLL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Periodically query the status of each device that is reachable
+/// via the LAN API, so that changes made via the Govee app or the
+/// device itself are noticed.
+pub async fn periodic_lan_poll(state: StateHandle, interval: Duration) {
+    // The number of consecutive polls that each device has failed to answer
+    let mut misses = HashMap::new();
+    loop {
+        sleep(interval).await;
+        let Some(client) = state.get_lan_client().await else {
+            continue;
+        };
+        if let Err(err) = poll_lan_devices(&state, &client, &mut misses).await {
+            log::error!("poll_lan_devices: {err:#}");
+        }
+    }
+}
+
+async fn poll_lan_devices(
+    state: &StateHandle,
+    client: &LanClient,
+    misses: &mut HashMap<String, u32>,
+) -> anyhow::Result<()> {
+    let mut polls = JoinSet::new();
+    for device in state.devices().await {
+        let Some(lan_device) = device.lan_device else {
+            continue;
+        };
+        let client = client.clone();
+        polls.spawn(async move {
+            let result = client.query_status_within(&lan_device, POLL_TIMEOUT).await;
+            (lan_device, result)
+        });
+    }
+
+    let mut rediscover = false;
+    while let Some(joined) = polls.join_next().await {
+        let (lan_device, result) = match joined {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                log::error!("LAN poll: {err:#}");
+                continue;
+            }
+        };
+        let id = &lan_device.device;
+
+        let (changed, was_stale) = {
+            let mut device = state.device_mut(&lan_device.sku, id).await;
+            if device.ip_addr() != Some(lan_device.ip) {
+                // Discovery found it at a different address while
+                // we were polling; we'll pick that up next time
+                misses.remove(id);
+                continue;
+            }
+            let was_stale = device.lan_stale_since.is_some();
+            match result {
+                Ok(status) => {
+                    misses.remove(id);
+                    (device.set_lan_device_status(status), was_stale)
+                }
+                Err(err) => {
+                    let count = misses.entry(id.clone()).or_default();
+                    *count += 1;
+                    log::debug!(
+                        "LAN poll of {} at {} failed {count} time(s): {err:#}",
+                        *device,
+                        lan_device.ip
+                    );
+                    // It may have been assigned a new address via DHCP.
+                    // Only look when it first misses and when it becomes
+                    // stale, so that a device that has been unplugged
+                    // doesn't cause us to scan on every poll
+                    if *count == 1 || *count == STALE_AFTER_MISSES {
+                        rediscover = true;
+                    }
+                    if *count >= STALE_AFTER_MISSES && !was_stale {
+                        log::warn!(
+                            "{} has stopped answering via the LAN API at {}",
+                            *device,
+                            lan_device.ip
+                        );
+                        device.set_lan_stale();
+                        (true, was_stale)
+                    } else {
+                        (false, was_stale)
+                    }
+                }
+            }
+        };
+
+        // Report problems for this device, but carry on with the others
+        // so that their results and miss counts are not lost
+        if changed {
+            if let Err(err) = state.notify_of_state_change(id).await {
+                log::error!("LAN poll: notify_of_state_change for {id}: {err:#}");
+            }
+        }
+
+        let is_stale = misses.get(id).copied().unwrap_or(0) >= STALE_AFTER_MISSES;
+        if was_stale != is_stale {
+            if !is_stale {
+                log::info!("{id} is answering via the LAN API again");
+            }
+            if let (Some(hass), Some(device)) =
+                (state.get_hass_client().await, state.device_by_id(id).await)
+            {
+                if let Err(err) = hass.publish_device_availability(&device).await {
+                    log::error!("LAN poll: publish_device_availability for {id}: {err:#}");
+                }
+            }
+        }
+    }
+
+    if rediscover {
+        client.rediscover();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lan_sim::{LanSimulator, SimOptions};
+    use crate::service::state::State;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn poll() {
+        let (sim, client, mut scan) = LanSimulator::start_with_client(SimOptions::default())
+            .await
+            .unwrap();
+        let lan_device = tokio::time::timeout(Duration::from_secs(5), scan.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let state = Arc::new(State::new());
+        state.set_lan_client(client.clone()).await;
+        state
+            .device_mut(&lan_device.sku, &lan_device.device)
+            .await
+            .set_lan_device(lan_device.clone());
+
+        // Change the device behind our back, as the Govee app would
+        lan_device.send_brightness(&client, 10).await.unwrap();
+
+        let mut misses = HashMap::new();
+        poll_lan_devices(&state, &client, &mut misses)
+            .await
+            .unwrap();
+        let device = state.device_by_id(&lan_device.device).await.unwrap();
+        assert_eq!(device.lan_device_status.as_ref().unwrap().brightness, 10);
+        assert!(device.is_available());
+
+        // Now the device stops answering
+        drop(sim);
+        for _ in 0..STALE_AFTER_MISSES {
+            poll_lan_devices(&state, &client, &mut misses)
+                .await
+                .unwrap();
+        }
+        let device = state.device_by_id(&lan_device.device).await.unwrap();
+        assert!(device.lan_stale_since.is_some());
+        assert!(!device.is_available());
+    }
+}
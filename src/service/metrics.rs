@@ -0,0 +1,392 @@
+use crate::service::health::{Subsystem, HEALTH};
+use crate::service::state::StateHandle;
+use chrono::Utc;
+use parking_lot::{const_mutex, Mutex};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Commands sent to devices, counted per transport attempt
+pub static COMMANDS: CounterVec = CounterVec::new(
+    "govee_commands_total",
+    "Commands sent to devices, by device, transport and outcome",
+    &["device", "transport", "outcome"],
+);
+
+pub static COMMAND_DURATION: HistogramVec = HistogramVec::new(
+    "govee_command_duration_seconds",
+    "How long it took a transport to send a command to a device",
+    &["device", "transport"],
+    &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0],
+);
+
+pub static PLATFORM_API_REQUESTS: CounterVec = CounterVec::new(
+    "govee_platform_api_requests_total",
+    "Requests made to the Govee Platform API, by path and HTTP status code",
+    &["path", "status"],
+);
+
+pub static HTTP_REQUESTS: CounterVec = CounterVec::new(
+    "govee_http_requests_total",
+    "Requests made to Govee's cloud services, by service and HTTP status code",
+    &["service", "status"],
+);
+
+pub static HTTP_REQUEST_DURATION: HistogramVec = HistogramVec::new(
+    "govee_http_request_duration_seconds",
+    "How long requests to Govee's cloud services took, by service",
+    &["service"],
+    &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0],
+);
+
+/// Attempts that failed transiently and were retried
+pub static RETRIES: CounterVec = CounterVec::new(
+    "govee_retries_total",
+    "Failed attempts that were retried, by operation",
+    &["operation"],
+);
+
+pub static IOT_CONNECTED: GaugeVec = GaugeVec::new(
+    "govee_iot_connected",
+    "Whether we are connected to the AWS IoT broker",
+    &[],
+);
+
+pub static IOT_RECONNECTS: CounterVec = CounterVec::new(
+    "govee_iot_reconnects_total",
+    "How many times we have reconnected to the AWS IoT broker",
+    &[],
+);
+
+pub static MQTT_CONNECTED: GaugeVec = GaugeVec::new(
+    "govee_mqtt_connected",
+    "Whether we are connected to the MQTT broker",
+    &[],
+);
+
+pub static MQTT_RECONNECTS: CounterVec = CounterVec::new(
+    "govee_mqtt_reconnects_total",
+    "How many times we have reconnected to the MQTT broker",
+    &[],
+);
+
+pub static LAN_DISCOVERY_RESPONSES: CounterVec = CounterVec::new(
+    "govee_lan_discovery_responses_total",
+    "Responses received to LAN API discovery requests, by SKU",
+    &["sku"],
+);
+
+pub static CACHE_REQUESTS: CounterVec = CounterVec::new(
+    "govee_cache_requests_total",
+    "Cache lookups, by topic and whether they were a hit or a miss",
+    &["topic", "result"],
+);
+
+/// Records the outcome of sending a command to a device via a transport
+pub fn record_command(device_id: &str, transport: &str, success: bool, elapsed: Duration) {
+    let outcome = if success { "success" } else { "failure" };
+    COMMANDS.inc(&[device_id, transport, outcome]);
+    COMMAND_DURATION.observe(&[device_id, transport], elapsed.as_secs_f64());
+}
+
+/// Tracks the connection state of an MQTT style client, both as
+/// metrics and as the health of its subsystem.
+/// A reconnect is a connection that follows a disconnection.
+pub struct ConnectionTracker {
+    subsystem: Subsystem,
+    connected: &'static GaugeVec,
+    reconnects: &'static CounterVec,
+    disconnected: bool,
+}
+
+impl ConnectionTracker {
+    pub fn new(
+        subsystem: Subsystem,
+        connected: &'static GaugeVec,
+        reconnects: &'static CounterVec,
+    ) -> Self {
+        Self {
+            subsystem,
+            connected,
+            reconnects,
+            disconnected: false,
+        }
+    }
+
+    pub fn connected(&mut self) {
+        if self.disconnected {
+            self.reconnects.inc(&[]);
+        }
+        self.disconnected = false;
+        self.connected.set(&[], 1.0);
+        HEALTH.set_ok(self.subsystem);
+    }
+
+    pub fn disconnected(&mut self, reason: impl std::fmt::Display) {
+        self.disconnected = true;
+        self.connected.set(&[], 0.0);
+        HEALTH.set_error(self.subsystem, format!("disconnected: {reason}"));
+    }
+}
+
+/// Renders all metrics in the Prometheus text exposition format
+pub async fn render_metrics(state: &StateHandle) -> String {
+    let mut out = String::new();
+    COMMANDS.render(&mut out);
+    COMMAND_DURATION.render(&mut out);
+    PLATFORM_API_REQUESTS.render(&mut out);
+    HTTP_REQUESTS.render(&mut out);
+    HTTP_REQUEST_DURATION.render(&mut out);
+    RETRIES.render(&mut out);
+    IOT_CONNECTED.render(&mut out);
+    IOT_RECONNECTS.render(&mut out);
+    MQTT_CONNECTED.render(&mut out);
+    MQTT_RECONNECTS.render(&mut out);
+    LAN_DISCOVERY_RESPONSES.render(&mut out);
+    CACHE_REQUESTS.render(&mut out);
+
+    // The age is computed at scrape time, rather than being tracked
+    let age = GaugeVec::new(
+        "govee_device_state_age_seconds",
+        "How long ago the state of a device was last updated",
+        &["device", "sku", "name"],
+    );
+    let now = Utc::now();
+    for device in state.devices().await {
+        if let Some(device_state) = device.device_state() {
+            let seconds = (now - device_state.updated).num_milliseconds() as f64 / 1000.0;
+            age.set(&[&device.id, &device.sku, &device.name()], seconds);
+        }
+    }
+    age.render(&mut out);
+
+    out
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Formats the `{name="value",...}` portion of a sample
+fn format_labels(names: &[&str], values: &[String], extra: Option<(&str, &str)>) -> String {
+    let mut pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{name}=\"{}\"", escape_label_value(value)))
+        .collect();
+    if let Some((name, value)) = extra {
+        pairs.push(format!("{name}=\"{value}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// A metric with a set of labels, and a value for each distinct
+/// combination of label values
+struct Family<V> {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, V>>,
+}
+
+impl<V: Default> Family<V> {
+    const fn new(name: &'static str, help: &'static str, labels: &'static [&'static str]) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            values: const_mutex(BTreeMap::new()),
+        }
+    }
+
+    fn update(&self, labels: &[&str], apply: impl FnOnce(&mut V)) {
+        debug_assert_eq!(labels.len(), self.labels.len(), "labels for {}", self.name);
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        apply(self.values.lock().entry(key).or_default());
+    }
+
+    fn render_header(&self, out: &mut String, kind: &str) {
+        writeln!(out, "# HELP {} {}", self.name, self.help).ok();
+        writeln!(out, "# TYPE {} {kind}", self.name).ok();
+    }
+}
+
+pub struct CounterVec {
+    family: Family<u64>,
+}
+
+impl CounterVec {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self {
+            family: Family::new(name, help, labels),
+        }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.family.update(labels, |value| *value += 1);
+    }
+
+    fn render(&self, out: &mut String) {
+        let family = &self.family;
+        family.render_header(out, "counter");
+        let values = family.values.lock();
+        if values.is_empty() && family.labels.is_empty() {
+            writeln!(out, "{} 0", family.name).ok();
+        }
+        for (labels, value) in values.iter() {
+            let labels = format_labels(family.labels, labels, None);
+            writeln!(out, "{}{labels} {value}", family.name).ok();
+        }
+    }
+}
+
+pub struct GaugeVec {
+    family: Family<f64>,
+}
+
+impl GaugeVec {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self {
+            family: Family::new(name, help, labels),
+        }
+    }
+
+    pub fn set(&self, labels: &[&str], value: f64) {
+        self.family.update(labels, |v| *v = value);
+    }
+
+    fn render(&self, out: &mut String) {
+        let family = &self.family;
+        family.render_header(out, "gauge");
+        let values = family.values.lock();
+        if values.is_empty() && family.labels.is_empty() {
+            writeln!(out, "{} 0", family.name).ok();
+        }
+        for (labels, value) in values.iter() {
+            let labels = format_labels(family.labels, labels, None);
+            writeln!(out, "{}{labels} {value}", family.name).ok();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// The count of observations in each bucket, not cumulative
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+pub struct HistogramVec {
+    family: Family<Histogram>,
+    /// The upper bounds of the buckets, in ascending order
+    buckets: &'static [f64],
+}
+
+impl HistogramVec {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        buckets: &'static [f64],
+    ) -> Self {
+        Self {
+            family: Family::new(name, help, labels),
+            buckets,
+        }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        self.family.update(labels, |histogram| {
+            histogram.buckets.resize(self.buckets.len(), 0);
+            if let Some(idx) = self.buckets.iter().position(|&bound| value <= bound) {
+                histogram.buckets[idx] += 1;
+            }
+            histogram.sum += value;
+            histogram.count += 1;
+        });
+    }
+
+    fn render(&self, out: &mut String) {
+        let family = &self.family;
+        family.render_header(out, "histogram");
+        for (labels, histogram) in family.values.lock().iter() {
+            let mut cumulative = 0;
+            for (bound, count) in self.buckets.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let le = bound.to_string();
+                let labels = format_labels(family.labels, labels, Some(("le", &le)));
+                writeln!(out, "{}_bucket{labels} {cumulative}", family.name).ok();
+            }
+            let inf = format_labels(family.labels, labels, Some(("le", "+Inf")));
+            writeln!(out, "{}_bucket{inf} {}", family.name, histogram.count).ok();
+            let labels = format_labels(family.labels, labels, None);
+            writeln!(out, "{}_sum{labels} {}", family.name, histogram.sum).ok();
+            writeln!(out, "{}_count{labels} {}", family.name, histogram.count).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render_counter() {
+        let counter = CounterVec::new("things_total", "Things", &["kind"]);
+        counter.inc(&["b\"c"]);
+        counter.inc(&["a"]);
+        counter.inc(&["a"]);
+
+        let mut out = String::new();
+        counter.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP things_total Things\n\
+             # TYPE things_total counter\n\
+             things_total{kind=\"a\"} 2\n\
+             things_total{kind=\"b\\\"c\"} 1\n"
+        );
+
+        let unlabeled = CounterVec::new("events_total", "Events", &[]);
+        let mut out = String::new();
+        unlabeled.render(&mut out);
+        assert!(out.ends_with("events_total 0\n"));
+    }
+
+    #[test]
+    fn render_histogram() {
+        let histogram = HistogramVec::new("latency_seconds", "Latency", &["op"], &[0.1, 1.0]);
+        histogram.observe(&["get"], 0.05);
+        histogram.observe(&["get"], 0.5);
+        histogram.observe(&["get"], 5.0);
+
+        let mut out = String::new();
+        histogram.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP latency_seconds Latency\n\
+             # TYPE latency_seconds histogram\n\
+             latency_seconds_bucket{op=\"get\",le=\"0.1\"} 1\n\
+             latency_seconds_bucket{op=\"get\",le=\"1\"} 2\n\
+             latency_seconds_bucket{op=\"get\",le=\"+Inf\"} 3\n\
+             latency_seconds_sum{op=\"get\"} 5.55\n\
+             latency_seconds_count{op=\"get\"} 3\n"
+        );
+    }
+}
@@ -1,7 +1,18 @@
 pub mod coordinator;
 pub mod device;
+pub mod diagnostics;
+pub mod events;
 pub mod hass;
+pub mod health;
 pub mod http;
+pub mod http_auth;
 pub mod iot;
+pub mod lan_poll;
+pub mod metrics;
+pub mod openapi;
 pub mod quirks;
+pub mod quota;
+pub mod registry;
 pub mod state;
+pub mod transport;
+pub mod transport_health;
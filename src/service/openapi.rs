@@ -0,0 +1,595 @@
+use crate::platform_api::DeviceCapability;
+use crate::service::events::{DeviceStateChanged, ServiceEvent};
+use crate::service::health::{Liveness, Readiness};
+use crate::service::http::{api_routes, ApiResponse, ApiRoute, DeviceItem, DeviceStateRequest};
+use crate::service::http_auth::Scope;
+use crate::undoc_api::ParsedOneClick;
+use crate::version_info::govee_version;
+use axum::http::Method;
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Map, Value};
+
+fn schema_ref(name: &str) -> Value {
+    json!({"$ref": format!("#/components/schemas/{name}")})
+}
+
+fn array_of(items: Value) -> Value {
+    json!({"type": "array", "items": items})
+}
+
+/// Derives the schemas from the types used by the handlers, so that
+/// they match their serde representation. Only the types referenced
+/// by `describe` need to be listed; the types that they contain are
+/// added automatically.
+fn schemas() -> Map<String, Value> {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    gen.subschema_for::<ApiResponse>();
+    gen.subschema_for::<DeviceItem>();
+    gen.subschema_for::<DeviceStateRequest>();
+    gen.subschema_for::<DeviceCapability>();
+    gen.subschema_for::<DeviceStateChanged>();
+    gen.subschema_for::<ServiceEvent>();
+    gen.subschema_for::<ParsedOneClick>();
+    gen.subschema_for::<Liveness>();
+    gen.subschema_for::<Readiness>();
+    // The visitors adapt the schemas to OpenAPI 3.0, but are
+    // only applied to root schemas, so apply them ourselves
+    let mut definitions = gen.take_definitions();
+    for visitor in gen.visitors_mut() {
+        for schema in definitions.values_mut() {
+            visitor.visit_schema(schema);
+        }
+    }
+    definitions
+        .into_iter()
+        .map(|(name, schema)| {
+            let schema = serde_json::to_value(schema).expect("serialize schema");
+            (name, schema)
+        })
+        .collect()
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": {"application/json": {"schema": schema}},
+    })
+}
+
+fn ok_response() -> Value {
+    json_response("The change was applied", schema_ref("ApiResponse"))
+}
+
+/// Describes what is specific to each operation.
+/// Returns None for a route that has not been described, which
+/// causes the `every_route_is_described` test to fail.
+fn describe(method: &Method, path: &str) -> Option<Value> {
+    let op = match (method.as_str(), path) {
+        ("GET", "/api/openapi.json") => json!({
+            "summary": "This OpenAPI document",
+            "responses": {"200": json_response("OpenAPI 3 document", json!({"type": "object"}))},
+        }),
+        ("GET", "/api/devices") => json!({
+            "summary": "List the known devices, their state and per-transport health",
+            "responses": {"200": json_response("The devices", array_of(schema_ref("DeviceItem")))},
+        }),
+        ("PUT" | "POST", "/api/device/:id/state") => json!({
+            "summary": "Change any combination of power, brightness, color, \
+                color temperature, scene and work mode",
+            "requestBody": {
+                "required": true,
+                "content": {"application/json": {"schema": schema_ref("DeviceStateRequest")}},
+            },
+            "responses": {"200": ok_response()},
+        }),
+        ("GET", "/api/device/:id/capabilities") => json!({
+            "summary": "List the Platform API capabilities of a device and their parameters",
+            "responses": {
+                "200": json_response("Capabilities", array_of(schema_ref("DeviceCapability"))),
+            },
+        }),
+        ("PUT" | "POST", "/api/device/:id/capability/:instance") => json!({
+            "summary": "Set the value of a capability via the Platform API",
+            "description": "The value is validated against the parameters of the \
+                capability. Enum option names may be used in place of their values, \
+                and omitted struct fields take their default value.",
+            "requestBody": {
+                "required": true,
+                "content": {"application/json": {"schema": {
+                    "description": "Any JSON value accepted by the capability",
+                    "example": {"musicMode": "Rhythm", "sensitivity": 50, "rgb": 0},
+                }}},
+            },
+            "responses": {"200": ok_response()},
+        }),
+        ("GET", "/api/device/:id/debug") => json!({
+            "summary": "Everything known about a device, for troubleshooting",
+            "description": "The format is subject to change. Includes the facts from \
+                each source along with when they were last updated, the resolved quirk, \
+                the parsed work modes, the state computed from each source and the hass \
+                entity configs that would be published. Sensitive values are redacted.",
+            "responses": {"200": json_response("Debug information", json!({"type": "object"}))},
+        }),
+        ("GET", "/api/device/:id/scenes") => json!({
+            "summary": "List the scene names available for a device",
+            "responses": {"200": json_response("Scene names", array_of(json!({"type": "string"})))},
+        }),
+        ("GET", "/api/device/:id/history") => json!({
+            "summary": "The most recent state changes of a device, oldest first",
+            "responses": {
+                "200": json_response("State changes", array_of(schema_ref("DeviceStateChanged"))),
+            },
+        }),
+        ("GET", "/api/events") => json!({
+            "summary": "Stream events as Server-Sent Events, named after their type",
+            "responses": {"200": {
+                "description": "An event stream; the data of each event is a ServiceEvent",
+                "content": {"text/event-stream": {"schema": schema_ref("ServiceEvent")}},
+            }},
+        }),
+        ("GET", "/api/ws") => json!({
+            "summary": "Stream events over a WebSocket, as JSON text messages \
+                matching the ServiceEvent schema",
+            "responses": {"101": {"description": "Switching to the WebSocket protocol"}},
+        }),
+        ("GET", "/api/diagnostics") => json!({
+            "summary": "Download a diagnostics bundle for attaching to an issue report",
+            "description": "A tar archive containing the version, configuration, devices and \
+                their capabilities, cache contents, recent logs and packets, and the hass \
+                discovery configs. Credentials, tokens, IoT topics, email addresses and MAC \
+                addresses are removed.",
+            "responses": {"200": {
+                "description": "The diagnostics bundle",
+                "content": {"application/x-tar": {
+                    "schema": {"type": "string", "format": "binary"},
+                }},
+            }},
+        }),
+        ("GET", "/healthz") => json!({
+            "summary": "Report whether the service is alive",
+            "description": "Fails once the service has hit a fatal error and is about \
+                to terminate. Does not require authentication.",
+            "responses": {
+                "200": json_response("The service is alive", schema_ref("Liveness")),
+                "503": json_response("The service has failed", schema_ref("Liveness")),
+            },
+        }),
+        ("GET", "/readyz") => json!({
+            "summary": "Report the status of each subsystem",
+            "description": "The service is ready when each of the MQTT connection, IoT \
+                connection, Platform API, LAN discovery socket and hass registration is \
+                either ok or disabled. Does not require authentication.",
+            "responses": {
+                "200": json_response("The service is ready", schema_ref("Readiness")),
+                "503": json_response("The service is not ready", schema_ref("Readiness")),
+            },
+        }),
+        ("GET", "/metrics") => json!({
+            "summary": "Metrics in the Prometheus text exposition format",
+            "responses": {"200": {
+                "description": "The metrics",
+                "content": {"text/plain": {"schema": {"type": "string"}}},
+            }},
+        }),
+        ("GET", "/api/oneclicks") => json!({
+            "summary": "List the one-click scenes in the Govee account",
+            "responses": {
+                "200": json_response("One-click scenes", array_of(schema_ref("ParsedOneClick"))),
+            },
+        }),
+        ("GET", "/api/oneclick/activate/:scene") => json!({
+            "summary": "Activate a one-click scene by name",
+            "responses": {"200": ok_response()},
+        }),
+        ("GET", "/api/device/:id/power/on") => legacy("Turn on a device"),
+        ("GET", "/api/device/:id/power/off") => legacy("Turn off a device"),
+        ("GET", "/api/device/:id/brightness/:level") => legacy("Set the brightness of a device"),
+        ("GET", "/api/device/:id/colortemp/:kelvin") => {
+            legacy("Set the color temperature of a device")
+        }
+        ("GET", "/api/device/:id/color/:color") => legacy("Set the color of a device"),
+        ("GET", "/api/device/:id/scene/:scene") => legacy("Activate a scene on a device"),
+        _ => return None,
+    };
+    Some(op)
+}
+
+fn legacy(summary: &str) -> Value {
+    json!({
+        "summary": summary,
+        "description": "Use PUT /api/device/{id}/state instead. \
+            Only served when legacy GET routes are enabled.",
+        "deprecated": true,
+        "responses": {"200": ok_response()},
+    })
+}
+
+fn path_parameter(name: &str) -> Value {
+    let (schema, description) = match name {
+        "id" => (
+            json!({"type": "string"}),
+            "The device id, its name or its computed name",
+        ),
+        "level" => (
+            json!({"type": "integer", "minimum": 0, "maximum": 100}),
+            "Brightness percentage",
+        ),
+        "kelvin" => (json!({"type": "integer"}), "Color temperature"),
+        "color" => (json!({"type": "string"}), "Any CSS color"),
+        "instance" => (
+            json!({"type": "string"}),
+            "The capability instance, eg: nightlightToggle",
+        ),
+        _ => (json!({"type": "string"}), "The name of the scene"),
+    };
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": schema,
+    })
+}
+
+/// Converts an axum path like `/api/device/:id` into the
+/// OpenAPI form `/api/device/{id}`, also returning the names
+/// of its parameters
+fn openapi_path(path: &str) -> (String, Vec<&str>) {
+    let mut params = vec![];
+    let converted = path
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => {
+                params.push(name);
+                format!("{{{name}}}")
+            }
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    (converted, params)
+}
+
+fn error_responses(op: &mut Value, scope: Scope) {
+    if scope == Scope::Public {
+        // Credentials are neither required nor checked
+        op["security"] = json!([]);
+        return;
+    }
+    let responses = op["responses"].as_object_mut().expect("responses");
+    let error = |description: &str| json_response(description, schema_ref("ApiResponse"));
+    responses.insert(
+        "401".to_string(),
+        error("Authentication is enabled and no acceptable credentials were presented"),
+    );
+    if scope == Scope::Control {
+        responses.insert(
+            "403".to_string(),
+            error("The credentials do not have the control scope"),
+        );
+    }
+    responses.insert("default".to_string(), error("An error occurred"));
+}
+
+fn build_document(routes: &[ApiRoute]) -> Value {
+    let mut paths = Map::new();
+    for route in routes {
+        let Some(mut op) = describe(&route.method, route.path) else {
+            log::warn!("openapi: {} {} is not described", route.method, route.path);
+            continue;
+        };
+        let (path, params) = openapi_path(route.path);
+        if !params.is_empty() {
+            op["parameters"] = params.into_iter().map(path_parameter).collect();
+        }
+        op["x-required-scope"] = json!(route.scope.to_string());
+        error_responses(&mut op, route.scope);
+
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[route.method.as_str().to_ascii_lowercase()] = op;
+    }
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "govee2mqtt",
+            "version": govee_version(),
+            "description": "Control and observe Govee devices via govee2mqtt",
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "bearerAuth": {"type": "http", "scheme": "bearer"},
+                "basicAuth": {"type": "http", "scheme": "basic"},
+            },
+        },
+        // Authentication is optional, and may use either scheme
+        "security": [{"bearerAuth": []}, {"basicAuth": []}, {}],
+    })
+}
+
+/// Returns the OpenAPI 3 document for the routes that are served.
+/// The paths are taken from `http::api_routes`, so that the document
+/// matches what is actually served.
+pub fn document(legacy_get_routes: bool) -> Value {
+    build_document(&api_routes(legacy_get_routes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::lan_api::DeviceColor;
+    use crate::platform_api::HttpDeviceInfo;
+    use crate::service::device::DeviceState;
+    use crate::service::events::{CommandResult, DeviceDiscovered, DeviceRemoved};
+    use crate::service::health::{HealthRegistry, Subsystem};
+    use crate::service::transport::TransportKind;
+    use crate::service::transport_health::TransportStats;
+    use chrono::Utc;
+
+    /// Checks `value` against the subset of JSON schema that is
+    /// produced by schemars
+    fn validate(
+        value: &Value,
+        schema: &Value,
+        schemas: &Map<String, Value>,
+        path: &str,
+    ) -> Result<(), String> {
+        macro_rules! ensure {
+            ($cond:expr, $($fmt:tt)*) => {
+                if !$cond {
+                    return Err(format!($($fmt)*));
+                }
+            };
+        }
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            let target = schemas
+                .get(name)
+                .ok_or_else(|| format!("{path}: unknown schema {reference}"))?;
+            return validate(value, target, schemas, path);
+        }
+        if value.is_null() && schema["nullable"] == json!(true) {
+            return Ok(());
+        }
+        for s in schema
+            .get("allOf")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            validate(value, s, schemas, path)?;
+        }
+        if let Some(one_of) = schema.get("oneOf").and_then(Value::as_array) {
+            let matches = one_of
+                .iter()
+                .filter(|s| validate(value, s, schemas, path).is_ok())
+                .count();
+            ensure!(
+                matches == 1,
+                "{path}: {value} matched {matches} of {one_of:?}"
+            );
+            return Ok(());
+        }
+        if let Some(choices) = schema.get("enum").and_then(Value::as_array) {
+            ensure!(
+                choices.contains(value),
+                "{path}: {value} not in {choices:?}"
+            );
+        }
+
+        match schema.get("type").and_then(Value::as_str) {
+            None => {}
+            Some("string") => ensure!(value.is_string(), "{path}: expected string: {value}"),
+            Some("integer") => ensure!(
+                value.is_i64() || value.is_u64(),
+                "{path}: expected integer: {value}"
+            ),
+            Some("number") => ensure!(value.is_number(), "{path}: expected number: {value}"),
+            Some("boolean") => ensure!(value.is_boolean(), "{path}: expected bool: {value}"),
+            Some("array") => {
+                let items = value
+                    .as_array()
+                    .ok_or_else(|| format!("{path}: expected array: {value}"))?;
+                for (idx, item) in items.iter().enumerate() {
+                    validate(item, &schema["items"], schemas, &format!("{path}[{idx}]"))?;
+                }
+            }
+            Some("object") => {
+                let obj = value
+                    .as_object()
+                    .ok_or_else(|| format!("{path}: expected object: {value}"))?;
+                let props = schema.get("properties").and_then(Value::as_object);
+                for key in schema["required"].as_array().into_iter().flatten() {
+                    let key = key.as_str().unwrap_or_default();
+                    ensure!(obj.contains_key(key), "{path}: missing {key} in {value}");
+                }
+                for (key, v) in obj {
+                    let sub = format!("{path}.{key}");
+                    match (
+                        props.and_then(|p| p.get(key)),
+                        &schema["additionalProperties"],
+                    ) {
+                        (Some(prop), _) => validate(v, prop, schemas, &sub)?,
+                        (None, extra) if extra.is_object() => validate(v, extra, schemas, &sub)?,
+                        (None, Value::Bool(true)) => {}
+                        (None, _) => ensure!(props.is_none(), "{sub} is not in the schema"),
+                    }
+                }
+            }
+            Some(other) => return Err(format!("{path}: unhandled type {other}")),
+        }
+        Ok(())
+    }
+
+    fn assert_matches<T: serde::Serialize>(name: &str, value: &T) {
+        let schemas = schemas();
+        let value = serde_json::to_value(value).unwrap();
+        if let Err(err) = validate(&value, &schema_ref(name), &schemas, name) {
+            panic!("{err}");
+        }
+    }
+
+    fn sample_state() -> DeviceState {
+        DeviceState {
+            on: true,
+            light_on: Some(true),
+            online: None,
+            kelvin: 4000,
+            color: DeviceColor {
+                r: 255,
+                g: 128,
+                b: 0,
+            },
+            brightness: 80,
+            scene: Some("Sunrise".to_string()),
+            source: "LAN API",
+            updated: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn every_route_is_described() {
+        for route in api_routes(true) {
+            assert!(
+                describe(&route.method, route.path).is_some(),
+                "{} {} has no OpenAPI description; add it to openapi::describe",
+                route.method,
+                route.path
+            );
+        }
+    }
+
+    #[test]
+    fn document_references_resolve() {
+        fn walk(value: &Value, schemas: &Map<String, Value>) {
+            match value {
+                Value::Object(obj) => {
+                    if let Some(Value::String(r)) = obj.get("$ref") {
+                        let name = r.trim_start_matches("#/components/schemas/");
+                        assert!(schemas.contains_key(name), "dangling {r}");
+                    }
+                    obj.values().for_each(|v| walk(v, schemas));
+                }
+                Value::Array(items) => items.iter().for_each(|v| walk(v, schemas)),
+                _ => {}
+            }
+        }
+
+        let doc = document(true);
+        let schemas = doc["components"]["schemas"].as_object().unwrap().clone();
+        walk(&doc, &schemas);
+
+        assert!(doc["paths"]["/api/device/{id}/state"]["put"].is_object());
+        assert!(doc["paths"]["/api/device/{id}/state"]["post"].is_object());
+        assert!(document(false)["paths"]
+            .get("/api/device/{id}/power/on")
+            .is_none());
+    }
+
+    #[test]
+    fn schemas_match_serde() {
+        assert_matches("DeviceState", &sample_state());
+
+        let stats = TransportStats {
+            last_error: Some("timed out".to_string()),
+            ..Default::default()
+        };
+        assert_matches(
+            "DeviceItem",
+            &DeviceItem {
+                sku: "H6199".to_string(),
+                id: "AA:BB".to_string(),
+                name: "Lamp".to_string(),
+                room: None,
+                ip: Some("10.0.0.2".parse().unwrap()),
+                state: Some(sample_state()),
+                transports: [(TransportKind::Lan, stats)].into_iter().collect(),
+            },
+        );
+
+        let list: Value =
+            serde_json::from_str(include_str!("../../test-data/list_devices.json")).unwrap();
+        let info: HttpDeviceInfo = serde_json::from_value(list["data"][0].clone()).unwrap();
+        for cap in &info.capabilities {
+            assert_matches("DeviceCapability", cap);
+        }
+
+        let one_click: ParsedOneClick = serde_json::from_value(json!({
+            "name": "Movie",
+            "entries": [{"topic": "GD/123", "msgs": [{"msg": {"cmd": "ptReal"}}]}],
+        }))
+        .unwrap();
+        assert_matches("ParsedOneClick", &one_click);
+
+        let change = DeviceStateChanged {
+            device_id: "AA:BB".to_string(),
+            old: None,
+            new: Some(sample_state()),
+            source: Some("LAN API"),
+            timestamp: Utc::now(),
+        };
+        assert_matches("DeviceStateChanged", &change);
+
+        for event in [
+            ServiceEvent::DeviceStateChanged(change),
+            ServiceEvent::DeviceDiscovered(DeviceDiscovered {
+                device_id: "AA:BB".to_string(),
+                sku: "H6199".to_string(),
+                source: "LAN API",
+                ip: None,
+                timestamp: Utc::now(),
+            }),
+            ServiceEvent::DeviceRemoved(DeviceRemoved {
+                device_id: "AA:BB".to_string(),
+                timestamp: Utc::now(),
+            }),
+            ServiceEvent::CommandResult(CommandResult {
+                device_id: "AA:BB".to_string(),
+                command: "power on".to_string(),
+                transport: Some(TransportKind::Platform),
+                success: true,
+                error: None,
+                error_kind: None,
+                timestamp: Utc::now(),
+            }),
+            ServiceEvent::CommandResult(CommandResult {
+                device_id: "AA:BB".to_string(),
+                command: "scene Sunrise".to_string(),
+                transport: None,
+                success: false,
+                error: Some("no suitable transport".to_string()),
+                error_kind: Some(ErrorKind::Unsupported),
+                timestamp: Utc::now(),
+            }),
+        ] {
+            assert_matches("ServiceEvent", &event);
+        }
+    }
+
+    #[test]
+    fn health_schemas_match_serde() {
+        let health = HealthRegistry::new();
+        health.set_ok(Subsystem::Mqtt);
+        health.set_error(Subsystem::PlatformApi, "status 500".to_string());
+        assert_matches("Readiness", &health.readiness());
+
+        health.set_fatal("run_mqtt_loop failed".to_string());
+        assert_matches("Liveness", &health.liveness());
+
+        let doc = document(false);
+        assert_eq!(doc["paths"]["/readyz"]["get"]["security"], json!([]));
+        assert!(doc["paths"]["/readyz"]["get"]["responses"]
+            .get("401")
+            .is_none());
+    }
+
+    #[test]
+    fn request_example_is_accepted() {
+        let example = schemas()["DeviceStateRequest"]["example"].clone();
+        serde_json::from_value::<DeviceStateRequest>(example).unwrap();
+    }
+}
@@ -1,11 +1,12 @@
 use crate::platform_api::DeviceType;
 use crate::temperature::TemperatureUnits;
 use once_cell::sync::Lazy;
+use serde::Serialize;
 use std::borrow::Cow;
 use std::collections::HashMap;
 
 #[allow(unused)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub enum HumidityUnits {
     RelativePercent,
     RelativePercentTimes100,
@@ -20,7 +21,7 @@ impl HumidityUnits {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Quirk {
     pub sku: Cow<'static, str>,
     pub icon: Cow<'static, str>,
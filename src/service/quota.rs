@@ -0,0 +1,316 @@
+use crate::cache::write_file_atomically;
+use anyhow::Context;
+use chrono::{DateTime, TimeZone, Utc};
+use parking_lot::{const_mutex, Mutex};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The number of Platform API requests that Govee allows per
+/// account per day, used until the API tells us otherwise
+const DEFAULT_DAILY_LIMIT: u32 = 10_000;
+
+/// The fraction of the daily limit that polling leaves untouched,
+/// so that commands and device list refreshes keep working
+const RESERVE_FRACTION: f64 = 0.1;
+
+const QUOTA_VERSION: u32 = 1;
+
+/// What we know about the daily Platform API request quota
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlatformQuota {
+    pub limit: u32,
+    pub remaining: u32,
+    /// When the quota is next replenished
+    pub reset: DateTime<Utc>,
+    /// True if `remaining` was reported by the API, rather than
+    /// being estimated by counting our requests
+    pub reported: bool,
+}
+
+impl PlatformQuota {
+    fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            limit: DEFAULT_DAILY_LIMIT,
+            remaining: DEFAULT_DAILY_LIMIT,
+            reset: now + chrono::Duration::days(1),
+            reported: false,
+        }
+    }
+
+    /// Replenish the quota if its reset time has passed
+    fn roll_over(&mut self, now: DateTime<Utc>) {
+        if now < self.reset {
+            return;
+        }
+        while self.reset <= now {
+            self.reset += chrono::Duration::days(1);
+        }
+        self.remaining = self.limit;
+        self.reported = false;
+    }
+
+    /// Update the quota from the response to a request.
+    /// A 429 response doesn't by itself mean that the daily quota
+    /// is exhausted, as it may be due to a short-lived rate limit;
+    /// check_rate_limit decides that.
+    fn apply_response(&mut self, headers: &HeaderMap, now: DateTime<Utc>) {
+        self.roll_over(now);
+
+        if let Some(limit) = header_number(headers, "x-ratelimit-limit") {
+            self.limit = limit as u32;
+        }
+        if let Some(reset) =
+            header_number(headers, "x-ratelimit-reset").and_then(|r| parse_reset(r, now))
+        {
+            self.reset = reset;
+        }
+        match header_number(headers, "x-ratelimit-remaining") {
+            Some(remaining) => {
+                self.remaining = remaining as u32;
+                self.reported = true;
+            }
+            None => {
+                self.remaining = self.remaining.saturating_sub(1);
+            }
+        }
+    }
+
+    /// How many requests polling may use before the quota resets
+    fn poll_budget(&self) -> u32 {
+        let reserve = (self.limit as f64 * RESERVE_FRACTION).ceil() as u32;
+        self.remaining.saturating_sub(reserve)
+    }
+
+    /// Returns how often a device with the given priority `weight`
+    /// may be polled, when the polling budget is shared by devices
+    /// whose weights add up to `total_weight`.
+    /// Returns None if the budget is exhausted.
+    pub fn poll_interval(
+        &self,
+        weight: u32,
+        total_weight: u32,
+        now: DateTime<Utc>,
+    ) -> Option<chrono::Duration> {
+        let budget = self.poll_budget();
+        if budget == 0 || weight == 0 {
+            return None;
+        }
+        let share = budget as f64 * weight as f64 / total_weight.max(weight) as f64;
+        let until_reset = (self.reset - now).num_seconds().max(0) as f64;
+        Some(chrono::Duration::seconds(
+            (until_reset / share).ceil() as i64
+        ))
+    }
+}
+
+fn header_number(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// The reset header is a timestamp, although the units are not
+/// documented; accept milliseconds or seconds since the epoch,
+/// or a relative number of seconds
+fn parse_reset(value: u64, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if value > 1_000_000_000_000 {
+        Utc.timestamp_millis_opt(value as i64).single()
+    } else if value > 1_000_000_000 {
+        Utc.timestamp_opt(value as i64, 0).single()
+    } else {
+        Some(now + chrono::Duration::seconds(value as i64))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct QuotaFile {
+    version: u32,
+    quota: PlatformQuota,
+}
+
+fn quota_file_name() -> PathBuf {
+    crate::cache::cache_dir().join("govee2mqtt-platform-quota.json")
+}
+
+/// Tracks the Platform API quota across the requests made by
+/// any `GoveeApiClient`
+pub struct QuotaTracker {
+    quota: Mutex<Option<PlatformQuota>>,
+    dirty: Mutex<bool>,
+}
+
+pub static PLATFORM_QUOTA: QuotaTracker = QuotaTracker::new();
+
+impl QuotaTracker {
+    const fn new() -> Self {
+        Self {
+            quota: const_mutex(None),
+            dirty: const_mutex(false),
+        }
+    }
+
+    /// Returns the current quota, accounting for any reset
+    pub fn snapshot(&self) -> PlatformQuota {
+        let now = Utc::now();
+        let mut quota = self.quota.lock();
+        let quota = quota.get_or_insert_with(|| PlatformQuota::new(now));
+        quota.roll_over(now);
+        quota.clone()
+    }
+
+    pub fn record_response(&self, headers: &HeaderMap) {
+        let now = Utc::now();
+        self.quota
+            .lock()
+            .get_or_insert_with(|| PlatformQuota::new(now))
+            .apply_response(headers, now);
+        *self.dirty.lock() = true;
+    }
+
+    /// Record that the API told us we have run out of requests
+    pub fn exhausted(&self) {
+        let now = Utc::now();
+        let mut quota = self.quota.lock();
+        let quota = quota.get_or_insert_with(|| PlatformQuota::new(now));
+        quota.roll_over(now);
+        quota.remaining = 0;
+        *self.dirty.lock() = true;
+    }
+
+    /// Restore the quota saved by a prior run
+    pub fn load(&self) -> anyhow::Result<()> {
+        let path = quota_file_name();
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err).with_context(|| format!("reading {path:?}")),
+        };
+        let file: QuotaFile =
+            serde_json::from_slice(&data).with_context(|| format!("parsing {path:?}"))?;
+        if file.version != QUOTA_VERSION {
+            log::warn!(
+                "Ignoring Platform API quota {path:?} with version {}, expected {QUOTA_VERSION}",
+                file.version
+            );
+            return Ok(());
+        }
+        self.quota.lock().replace(file.quota);
+        Ok(())
+    }
+
+    /// Save the quota, if it has changed since it was last saved
+    pub fn persist(&self) -> anyhow::Result<()> {
+        self.persist_to(&quota_file_name())
+    }
+
+    fn persist_to(&self, path: &Path) -> anyhow::Result<()> {
+        if !std::mem::take(&mut *self.dirty.lock()) {
+            return Ok(());
+        }
+        let result = serde_json::to_string_pretty(&QuotaFile {
+            version: QUOTA_VERSION,
+            quota: self.snapshot(),
+        })
+        .map_err(anyhow::Error::from)
+        .and_then(|data| write_file_atomically(path, data));
+        if result.is_err() {
+            // Try again next time
+            *self.dirty.lock() = true;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            let name: reqwest::header::HeaderName = name.parse().unwrap();
+            headers.insert(name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn tracks_headers() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut quota = PlatformQuota::new(now);
+
+        quota.apply_response(&HeaderMap::new(), now);
+        assert_eq!(quota.remaining, DEFAULT_DAILY_LIMIT - 1);
+        assert!(!quota.reported);
+
+        quota.apply_response(
+            &headers(&[
+                ("X-RateLimit-Limit", "1000"),
+                ("X-RateLimit-Remaining", "420"),
+                ("X-RateLimit-Reset", "1700003600000"),
+            ]),
+            now,
+        );
+        assert_eq!(quota.limit, 1000);
+        assert_eq!(quota.remaining, 420);
+        assert!(quota.reported);
+        assert_eq!(quota.reset, now + chrono::Duration::hours(1));
+
+        quota.apply_response(&headers(&[("X-RateLimit-Remaining", "0")]), now);
+        assert_eq!(quota.remaining, 0);
+
+        quota.roll_over(now + chrono::Duration::hours(2));
+        assert_eq!(quota.remaining, 1000);
+        assert_eq!(quota.reset, now + chrono::Duration::hours(25));
+    }
+
+    #[test]
+    fn poll_interval() {
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let quota = PlatformQuota {
+            limit: 1000,
+            remaining: 700,
+            reset: now + chrono::Duration::hours(10),
+            reported: true,
+        };
+
+        // 600 requests are available for polling; a device with
+        // a third of the total weight gets 200 of them
+        assert_eq!(
+            quota.poll_interval(1, 3, now),
+            Some(chrono::Duration::seconds(180))
+        );
+        assert_eq!(
+            quota.poll_interval(2, 3, now),
+            Some(chrono::Duration::seconds(90))
+        );
+
+        let exhausted = PlatformQuota {
+            remaining: 100,
+            ..quota
+        };
+        assert_eq!(exhausted.poll_interval(1, 3, now), None);
+    }
+
+    #[test]
+    fn persist_retries() {
+        let tracker = QuotaTracker::new();
+        tracker.exhausted();
+
+        let dir = std::env::temp_dir().join(format!("govee-quota-{}", std::process::id()));
+        let path = dir.join("quota.json");
+
+        // The directory doesn't exist yet, so the write fails
+        // and the change must be saved next time
+        assert!(tracker.persist_to(&path).is_err());
+        assert!(*tracker.dirty.lock());
+
+        std::fs::create_dir_all(&dir).unwrap();
+        tracker.persist_to(&path).unwrap();
+        assert!(!*tracker.dirty.lock());
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        let file: QuotaFile = serde_json::from_slice(&data).unwrap();
+        assert_eq!(file.quota.remaining, 0);
+    }
+}
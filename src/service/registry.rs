@@ -0,0 +1,159 @@
+use crate::cache::write_file_atomically;
+use crate::lan_api::LanDevice;
+use crate::platform_api::HttpDeviceInfo;
+use crate::service::device::{Device, UndocDeviceInfo};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Bump this if the on-disk format changes in an incompatible way;
+/// a registry with a different version is ignored rather than
+/// causing startup to fail.
+const REGISTRY_VERSION: u32 = 1;
+
+/// The subset of what we know about a device that is remembered
+/// across restarts, so that we can bring up the device and its
+/// entities without waiting for (or in the absence of) Govee's
+/// cloud APIs.  Quirks are not stored here, as they are resolved
+/// from the SKU.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PersistedDevice {
+    pub sku: String,
+    pub id: String,
+    pub room_name: Option<String>,
+    /// The most recently seen LAN API information, including its IP
+    pub lan_device: Option<LanDevice>,
+    /// Capabilities, as reported by the platform API
+    pub http_device_info: Option<HttpDeviceInfo>,
+    /// The name and room, as reported by the undocumented API
+    pub undoc_device_info: Option<UndocDeviceInfo>,
+    pub updated: DateTime<Utc>,
+}
+
+impl PersistedDevice {
+    pub fn from_device(device: &Device) -> Self {
+        Self {
+            sku: device.sku.clone(),
+            id: device.id.clone(),
+            room_name: device.room_name().map(|name| name.to_string()),
+            lan_device: device.lan_device.clone(),
+            http_device_info: device.http_device_info.clone(),
+            undoc_device_info: device.undoc_device_info.clone(),
+            updated: Utc::now(),
+        }
+    }
+
+    /// Fill in the facts that `device` doesn't already know
+    /// from live sources
+    pub fn apply_to(&self, device: &mut Device) {
+        if device.lan_device.is_none() {
+            device.lan_device = self.lan_device.clone();
+        }
+        if device.http_device_info.is_none() {
+            device.http_device_info = self.http_device_info.clone();
+        }
+        if device.undoc_device_info.is_none() {
+            device.undoc_device_info = self.undoc_device_info.clone();
+        }
+        device.persisted_room_name = self.room_name.clone();
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RegistryFile {
+    version: u32,
+    devices: Vec<PersistedDevice>,
+}
+
+pub fn registry_file_name() -> PathBuf {
+    crate::cache::cache_dir().join("govee2mqtt-devices.json")
+}
+
+pub fn load_registry(path: &Path) -> anyhow::Result<Vec<PersistedDevice>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err).with_context(|| format!("reading {path:?}")),
+    };
+    let file: RegistryFile =
+        serde_json::from_slice(&data).with_context(|| format!("parsing {path:?}"))?;
+    if file.version != REGISTRY_VERSION {
+        log::warn!(
+            "Ignoring device registry {path:?} with version {}, expected {REGISTRY_VERSION}",
+            file.version
+        );
+        return Ok(vec![]);
+    }
+    Ok(file.devices)
+}
+
+/// Write the registry to `path`, atomically
+pub fn save_registry(path: &Path, mut devices: Vec<PersistedDevice>) -> anyhow::Result<()> {
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
+    let data = serde_json::to_string_pretty(&RegistryFile {
+        version: REGISTRY_VERSION,
+        devices,
+    })?;
+    write_file_atomically(path, data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let list: serde_json::Value =
+            serde_json::from_str(include_str!("../../test-data/list_devices.json")).unwrap();
+        let info: HttpDeviceInfo = serde_json::from_value(list["data"][0].clone()).unwrap();
+
+        let mut device = Device::new(&info.sku, &info.device);
+        device.set_http_device_info(info.clone());
+
+        let dir = std::env::temp_dir().join(format!("govee-registry-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("devices.json");
+
+        assert!(load_registry(&path).unwrap().is_empty());
+        save_registry(&path, vec![PersistedDevice::from_device(&device)]).unwrap();
+
+        let loaded = load_registry(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(loaded.len(), 1);
+
+        let mut restored = Device::new(&loaded[0].sku, &loaded[0].id);
+        loaded[0].apply_to(&mut restored);
+        assert_eq!(restored.name(), device.name());
+        assert_eq!(restored.room_name(), None);
+        assert_eq!(
+            restored.http_device_info.map(|i| i.capabilities.len()),
+            Some(info.capabilities.len())
+        );
+    }
+
+    #[test]
+    fn undoc_only() {
+        let resp: crate::undoc_api::DevicesResponse =
+            crate::platform_api::from_json(include_str!("../../test-data/undoc-device-list.json"))
+                .unwrap();
+        let entry = resp.devices[0].clone();
+
+        let mut device = Device::new(&entry.sku, &entry.device);
+        device.set_undoc_device_info(entry.clone(), Some("Office"));
+
+        let dir = std::env::temp_dir().join(format!("govee-registry-undoc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("devices.json");
+
+        save_registry(&path, vec![PersistedDevice::from_device(&device)]).unwrap();
+        let loaded = load_registry(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        let mut restored = Device::new(&loaded[0].sku, &loaded[0].id);
+        loaded[0].apply_to(&mut restored);
+        assert_eq!(restored.govee_name(), Some(entry.device_name.as_str()));
+        assert_eq!(restored.room_name(), Some("Office"));
+        assert!(restored.undoc_device_info.is_some());
+    }
+}
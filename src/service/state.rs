@@ -1,17 +1,31 @@
-use crate::ble::{Base64HexBytes, SetHumidifierMode, SetHumidifierNightlightParams};
-use crate::lan_api::{Client as LanClient, DeviceStatus as LanDeviceStatus, LanDevice};
+use crate::ble::{Base64HexBytes, SetHumidifierNightlightParams};
+use crate::error::{error_kind, ErrorKind};
+use crate::lan_api::{
+    Client as LanClient, DeviceColor, DeviceStatus as LanDeviceStatus, LanDevice, LanError,
+};
 use crate::platform_api::{DeviceCapability, GoveeApiClient};
 use crate::service::coordinator::Coordinator;
-use crate::service::device::Device;
+use crate::service::device::{Device, DeviceState};
+use crate::service::events::{
+    CommandResult, DeviceRemoved, DeviceStateChanged, EventBus, ServiceEvent, StateHistory,
+};
 use crate::service::hass::{topic_safe_id, HassClient};
 use crate::service::iot::IotClient;
+use crate::service::metrics;
+use crate::service::registry::{load_registry, registry_file_name, save_registry, PersistedDevice};
+use crate::service::transport::{
+    CommandError, Transport, TransportCommand, TransportKind, TransportPreferences,
+};
+use crate::service::transport_health::{CircuitState, TransportHealth, TransportStats};
 use crate::temperature::{TemperatureScale, TemperatureValue};
-use crate::undoc_api::GoveeUndocumentedApi;
+use crate::undoc_api::{DevicesResponse, GoveeUndocumentedApi};
 use anyhow::Context;
+use chrono::Utc;
 use serde_json::Value as JsonValue;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Instant;
+use tokio::sync::broadcast::Receiver;
 use tokio::sync::{MappedMutexGuard, Mutex, MutexGuard, Semaphore};
 use tokio::time::{sleep, Duration};
 
@@ -26,6 +40,14 @@ pub struct State {
     hass_client: Mutex<Option<HassClient>>,
     hass_discovery_prefix: Mutex<String>,
     temperature_scale: Mutex<TemperatureScale>,
+    transport_preferences: Mutex<TransportPreferences>,
+    transport_health: Mutex<TransportHealth>,
+    registry_lock: Mutex<()>,
+    hass_config_topics: Mutex<HashMap<String, BTreeSet<String>>>,
+    device_list_complete: Mutex<bool>,
+    events: EventBus,
+    last_notified_state: Mutex<HashMap<String, DeviceState>>,
+    state_history: Mutex<StateHistory>,
 }
 
 pub type StateHandle = Arc<State>;
@@ -43,6 +65,10 @@ impl State {
         *self.temperature_scale.lock().await
     }
 
+    pub async fn set_transport_preferences(&self, prefs: TransportPreferences) {
+        *self.transport_preferences.lock().await = prefs;
+    }
+
     pub async fn set_hass_disco_prefix(&self, prefix: String) {
         *self.hass_discovery_prefix.lock().await = prefix;
     }
@@ -62,6 +88,63 @@ impl State {
         })
     }
 
+    /// Populate the device map from the persisted device registry.
+    /// Returns the number of devices that were restored.
+    pub async fn restore_device_registry(&self) -> anyhow::Result<usize> {
+        let devices = load_registry(&registry_file_name())?;
+        let count = devices.len();
+        for persisted in devices {
+            let mut device = self.device_mut(&persisted.sku, &persisted.id).await;
+            persisted.apply_to(&mut device);
+        }
+        Ok(count)
+    }
+
+    /// Save what we know about the current set of devices
+    /// to the device registry
+    pub async fn persist_device_registry(&self) -> anyhow::Result<()> {
+        let _guard = self.registry_lock.lock().await;
+        let devices = self
+            .devices()
+            .await
+            .iter()
+            .map(PersistedDevice::from_device)
+            .collect();
+        let path = registry_file_name();
+        tokio::task::spawn_blocking(move || save_registry(&path, devices)).await?
+    }
+
+    /// Forget about a device that is no longer present
+    pub async fn remove_device(&self, id: &str) -> Option<Device> {
+        self.semaphore_by_id.lock().await.remove(id);
+        self.last_notified_state.lock().await.remove(id);
+        let device = self.devices_by_id.lock().await.remove(id);
+        if device.is_some() {
+            self.publish_event(ServiceEvent::DeviceRemoved(DeviceRemoved {
+                device_id: id.to_string(),
+                timestamp: Utc::now(),
+            }));
+        }
+        device
+    }
+
+    /// Merge the device and room list from the undocumented API.
+    /// Returns the ids of the listed devices.
+    pub async fn merge_undoc_device_list(&self, info: DevicesResponse) -> HashSet<String> {
+        let mut group_by_id = HashMap::new();
+        for group in info.groups {
+            group_by_id.insert(group.group_id, group.group_name);
+        }
+        let mut ids = HashSet::new();
+        for entry in info.devices {
+            ids.insert(entry.device.clone());
+            let mut device = self.device_mut(&entry.sku, &entry.device).await;
+            let room_name = group_by_id.get(&entry.group_id).map(|name| name.as_str());
+            device.set_undoc_device_info(entry, room_name);
+        }
+        ids
+    }
+
     pub async fn devices(&self) -> Vec<Device> {
         self.devices_by_id.lock().await.values().cloned().collect()
     }
@@ -152,6 +235,58 @@ impl State {
         self.hass_client.lock().await.clone()
     }
 
+    /// Remember that we published a discovery config to `topic`
+    /// for the hass device with the specified identifier
+    pub async fn record_hass_config_topic(&self, identifier: &str, topic: &str) {
+        self.hass_config_topics
+            .lock()
+            .await
+            .entry(identifier.to_string())
+            .or_default()
+            .insert(topic.to_string());
+    }
+
+    /// Returns all of the discovery config topics that we have
+    /// published during this session
+    pub async fn all_hass_config_topics(&self) -> BTreeSet<String> {
+        self.hass_config_topics
+            .lock()
+            .await
+            .values()
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Returns and forgets all of the recorded discovery config topics
+    pub async fn take_all_hass_config_topics(&self) -> BTreeSet<String> {
+        std::mem::take(&mut *self.hass_config_topics.lock().await)
+            .into_values()
+            .flatten()
+            .collect()
+    }
+
+    /// Returns and forgets the discovery config topics that were
+    /// published for the hass device with the specified identifier
+    pub async fn take_hass_config_topics(&self, identifier: &str) -> BTreeSet<String> {
+        self.hass_config_topics
+            .lock()
+            .await
+            .remove(identifier)
+            .unwrap_or_default()
+    }
+
+    /// Record that every configured cloud API has answered with its
+    /// device list, so that the set of devices is known to be complete.
+    /// Returns true the first time that this is called.
+    pub async fn set_device_list_complete(&self) -> bool {
+        !std::mem::replace(&mut *self.device_list_complete.lock().await, true)
+    }
+
+    pub async fn is_device_list_complete(&self) -> bool {
+        *self.device_list_complete.lock().await
+    }
+
     pub async fn set_iot_client(&self, client: IotClient) {
         self.iot_client.lock().await.replace(client);
     }
@@ -246,7 +381,10 @@ impl State {
         Ok(false)
     }
 
-    async fn poll_lan_api<F: Fn(&LanDeviceStatus) -> bool>(
+    /// Poll the LAN API status of `device` until `acceptor` indicates
+    /// that the reported status reflects the command that we just sent.
+    /// Returns an error if that doesn't happen within a few seconds.
+    pub async fn poll_lan_api<F: Fn(&LanDeviceStatus) -> bool>(
         self: &Arc<Self>,
         device: &LanDevice,
         acceptor: F,
@@ -254,9 +392,10 @@ impl State {
         match self.get_lan_client().await {
             Some(client) => {
                 let deadline = Instant::now() + Duration::from_secs(5);
+                let mut accepted = false;
                 while Instant::now() <= deadline {
                     let status = client.query_status(device).await?;
-                    let accepted = (acceptor)(&status);
+                    accepted = (acceptor)(&status);
                     self.device_mut(&device.sku, &device.device)
                         .await
                         .set_lan_device_status(status);
@@ -266,9 +405,12 @@ impl State {
                     sleep(Duration::from_millis(100)).await;
                 }
                 self.notify_of_state_change(&device.device).await?;
+                if !accepted {
+                    return Err(LanError::UnexpectedStatus(device.device.clone()).into());
+                }
                 Ok(())
             }
-            None => anyhow::bail!("no lan client"),
+            None => Err(LanError::NoClient.into()),
         }
     }
 
@@ -290,6 +432,192 @@ impl State {
         anyhow::bail!("Unable to use Platform API to control {device}");
     }
 
+    /// Returns the transport of the specified kind for `device`,
+    /// if we have the means to talk to it that way.
+    pub async fn transport_for_device(
+        &self,
+        device: &Device,
+        kind: TransportKind,
+    ) -> Option<Arc<dyn Transport>> {
+        match kind {
+            TransportKind::Lan => device
+                .lan_device
+                .clone()
+                .map(|lan| Arc::new(lan) as Arc<dyn Transport>),
+            TransportKind::Iot => self
+                .get_iot_client()
+                .await
+                .map(|iot| Arc::new(iot) as Arc<dyn Transport>),
+            TransportKind::Platform => self
+                .get_platform_client()
+                .await
+                .map(|client| Arc::new(client) as Arc<dyn Transport>),
+        }
+    }
+
+    /// Returns the transports that are able to carry out `command`
+    /// for `device`, in the order in which they should be tried.
+    /// Transports whose circuit is open are moved to the end of
+    /// the list, so that they are used only as a last resort.
+    pub async fn transports_for_command(
+        &self,
+        device: &Device,
+        command: &TransportCommand,
+    ) -> Vec<Arc<dyn Transport>> {
+        let order = self
+            .transport_preferences
+            .lock()
+            .await
+            .order_for(device)
+            .unwrap_or_else(|| command.default_order())
+            .to_vec();
+
+        let mut healthy = vec![];
+        let mut open = vec![];
+        for kind in order {
+            let Some(transport) = self.transport_for_device(device, kind).await else {
+                continue;
+            };
+            if !transport.supports(device, command) {
+                continue;
+            }
+            let circuit = self.transport_health.lock().await.circuit(&device.id, kind);
+            if circuit == CircuitState::Open {
+                log::debug!("Circuit for {kind} to {device} is open, deprioritizing it");
+                open.push(transport);
+            } else {
+                healthy.push(transport);
+            }
+        }
+        healthy.append(&mut open);
+        healthy
+    }
+
+    /// Send `command` to `device`, trying each applicable transport
+    /// in turn until one of them succeeds.
+    /// Returns the kind of the transport that was successful.
+    pub async fn send_command_via_transports(
+        self: &Arc<Self>,
+        device: &Device,
+        command: TransportCommand,
+    ) -> anyhow::Result<TransportKind> {
+        let result = self.try_transports(device, &command).await;
+        self.publish_event(ServiceEvent::CommandResult(CommandResult {
+            device_id: device.id.clone(),
+            command: command.to_string(),
+            transport: result.as_ref().ok().copied(),
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|err| format!("{err:#}")),
+            error_kind: result.as_ref().err().map(error_kind),
+            timestamp: Utc::now(),
+        }));
+        result
+    }
+
+    async fn try_transports(
+        self: &Arc<Self>,
+        device: &Device,
+        command: &TransportCommand,
+    ) -> anyhow::Result<TransportKind> {
+        let transports = self.transports_for_command(device, command).await;
+        if transports.is_empty() {
+            return Err(CommandError::NoTransport {
+                command: command.to_string(),
+                device: device.to_string(),
+            }
+            .into());
+        }
+
+        let mut failures = vec![];
+        let mut kinds = vec![];
+        for transport in transports {
+            let kind = transport.kind();
+            log::info!("Using {kind} to set {device} {command}");
+            let started = Instant::now();
+            match transport.send_command(self, device, command).await {
+                Ok(()) => {
+                    let elapsed = started.elapsed();
+                    metrics::record_command(&device.id, kind.short_name(), true, elapsed);
+                    self.transport_health
+                        .lock()
+                        .await
+                        .record_success(&device.id, kind, elapsed);
+                    self.apply_command_side_effects(device, command).await;
+                    return Ok(kind);
+                }
+                Err(err) => {
+                    let failure = error_kind(&err);
+                    log::warn!("{kind} failed to set {device} {command} ({failure}): {err:#}");
+                    metrics::record_command(
+                        &device.id,
+                        kind.short_name(),
+                        false,
+                        started.elapsed(),
+                    );
+                    // Only failures that reflect on the health of the
+                    // transport count towards opening its circuit
+                    if matches!(failure, ErrorKind::Transient | ErrorKind::Auth) {
+                        let opened = self.transport_health.lock().await.record_failure(
+                            &device.id,
+                            kind,
+                            format!("{err:#}"),
+                        );
+                        if opened {
+                            self.notify_of_state_change(&device.id).await.ok();
+                        }
+                    }
+                    failures.push(format!("{kind}: {err:#}"));
+                    kinds.push(failure);
+                }
+            }
+        }
+
+        Err(CommandError::Failed {
+            command: command.to_string(),
+            device: device.to_string(),
+            kind: CommandError::overall_kind(&kinds),
+            failures,
+        }
+        .into())
+    }
+
+    pub async fn transport_health_for_device(
+        &self,
+        device_id: &str,
+    ) -> BTreeMap<TransportKind, TransportStats> {
+        self.transport_health
+            .lock()
+            .await
+            .stats_for_device(device_id)
+    }
+
+    pub async fn open_transport_circuits(&self) -> Vec<(String, TransportKind)> {
+        self.transport_health.lock().await.open_circuits()
+    }
+
+    pub async fn record_transport_probe_success(&self, device_id: &str, kind: TransportKind) {
+        self.transport_health
+            .lock()
+            .await
+            .record_probe_success(device_id, kind);
+    }
+
+    async fn apply_command_side_effects(&self, device: &Device, command: &TransportCommand) {
+        match command {
+            TransportCommand::ColorRgb(_) | TransportCommand::ColorTemperature(_) => {
+                self.device_mut(&device.sku, &device.id)
+                    .await
+                    .set_active_scene(None);
+            }
+            TransportCommand::Scene(scene) => {
+                self.device_mut(&device.sku, &device.id)
+                    .await
+                    .set_active_scene(Some(scene));
+            }
+            _ => {}
+        }
+    }
+
     pub async fn device_light_power_on(
         self: &Arc<Self>,
         device: &Device,
@@ -311,32 +639,12 @@ impl State {
                 )
             })?;
 
-        if let Some(lan_dev) = &device.lan_device {
-            log::info!("Using LAN API to set {device} light power state");
-            lan_dev.send_turn(on).await?;
-            self.poll_lan_api(lan_dev, |status| status.on == on).await?;
-            return Ok(());
-        }
-
-        if device.iot_api_supported() {
-            if let Some(iot) = self.get_iot_client().await {
-                if let Some(info) = &device.undoc_device_info {
-                    log::info!("Using IoT API to set {device} light power state");
-                    iot.set_power_state(&info.entry, on).await?;
-                    return Ok(());
-                }
-            }
-        }
-
-        if let Some(client) = self.get_platform_client().await {
-            if let Some(info) = &device.http_device_info {
-                log::info!("Using Platform API to set {device} light {instance_name} state");
-                client.set_toggle_state(info, instance_name, on).await?;
-                return Ok(());
-            }
-        }
-
-        anyhow::bail!("Unable to control light power state for {device}");
+        self.send_command_via_transports(
+            device,
+            TransportCommand::LightPowerOn { instance_name, on },
+        )
+        .await?;
+        Ok(())
     }
 
     pub async fn device_power_on(
@@ -344,32 +652,9 @@ impl State {
         device: &Device,
         on: bool,
     ) -> anyhow::Result<()> {
-        if let Some(lan_dev) = &device.lan_device {
-            log::info!("Using LAN API to set {device} power state");
-            lan_dev.send_turn(on).await?;
-            self.poll_lan_api(lan_dev, |status| status.on == on).await?;
-            return Ok(());
-        }
-
-        if device.iot_api_supported() {
-            if let Some(iot) = self.get_iot_client().await {
-                if let Some(info) = &device.undoc_device_info {
-                    log::info!("Using IoT API to set {device} power state");
-                    iot.set_power_state(&info.entry, on).await?;
-                    return Ok(());
-                }
-            }
-        }
-
-        if let Some(client) = self.get_platform_client().await {
-            if let Some(info) = &device.http_device_info {
-                log::info!("Using Platform API to set {device} power state");
-                client.set_power_state(info, on).await?;
-                return Ok(());
-            }
-        }
-
-        anyhow::bail!("Unable to control power state for {device}");
+        self.send_command_via_transports(device, TransportCommand::PowerOn(on))
+            .await?;
+        Ok(())
     }
 
     pub async fn device_set_brightness(
@@ -387,32 +672,9 @@ impl State {
             return Ok(());
         }
 
-        if let Some(lan_dev) = &device.lan_device {
-            log::info!("Using LAN API to set {device} brightness");
-            lan_dev.send_brightness(percent).await?;
-            self.poll_lan_api(lan_dev, |status| status.brightness == percent)
-                .await?;
-            return Ok(());
-        }
-
-        if device.iot_api_supported() {
-            if let Some(iot) = self.get_iot_client().await {
-                if let Some(info) = &device.undoc_device_info {
-                    log::info!("Using IoT API to set {device} brightness");
-                    iot.set_brightness(&info.entry, percent).await?;
-                    return Ok(());
-                }
-            }
-        }
-
-        if let Some(client) = self.get_platform_client().await {
-            if let Some(info) = &device.http_device_info {
-                log::info!("Using Platform API to set {device} brightness");
-                client.set_brightness(info, percent).await?;
-                return Ok(());
-            }
-        }
-        anyhow::bail!("Unable to control brightness for {device}");
+        self.send_command_via_transports(device, TransportCommand::Brightness(percent))
+            .await?;
+        Ok(())
     }
 
     pub async fn device_set_color_temperature(
@@ -420,38 +682,9 @@ impl State {
         device: &Device,
         kelvin: u32,
     ) -> anyhow::Result<()> {
-        if let Some(lan_dev) = &device.lan_device {
-            log::info!("Using LAN API to set {device} color temperature");
-            lan_dev.send_color_temperature_kelvin(kelvin).await?;
-            self.poll_lan_api(lan_dev, |status| status.color_temperature_kelvin == kelvin)
-                .await?;
-            self.device_mut(&device.sku, &device.id)
-                .await
-                .set_active_scene(None);
-            return Ok(());
-        }
-
-        if device.iot_api_supported() {
-            if let Some(iot) = self.get_iot_client().await {
-                if let Some(info) = &device.undoc_device_info {
-                    log::info!("Using IoT API to set {device} color temperature");
-                    iot.set_color_temperature(&info.entry, kelvin).await?;
-                    return Ok(());
-                }
-            }
-        }
-
-        if let Some(client) = self.get_platform_client().await {
-            if let Some(info) = &device.http_device_info {
-                log::info!("Using Platform API to set {device} color temperature");
-                client.set_color_temperature(info, kelvin).await?;
-                self.device_mut(&device.sku, &device.id)
-                    .await
-                    .set_active_scene(None);
-                return Ok(());
-            }
-        }
-        anyhow::bail!("Unable to control color temperature for {device}");
+        self.send_command_via_transports(device, TransportCommand::ColorTemperature(kelvin))
+            .await?;
+        Ok(())
     }
 
     // FIXME: this function probably shouldn't exist here
@@ -483,28 +716,9 @@ impl State {
         work_mode: i64,
         value: i64,
     ) -> anyhow::Result<()> {
-        if let Ok(command) = Base64HexBytes::encode_for_sku(
-            &device.sku,
-            &SetHumidifierMode {
-                mode: work_mode as u8,
-                param: value as u8,
-            },
-        ) {
-            if let Some(iot) = self.get_iot_client().await {
-                if let Some(info) = &device.undoc_device_info {
-                    iot.send_real(&info.entry, command.base64()).await?;
-                    return Ok(());
-                }
-            }
-        }
-
-        if let Some(client) = self.get_platform_client().await {
-            if let Some(info) = &device.http_device_info {
-                client.set_work_mode(info, work_mode, value).await?;
-                return Ok(());
-            }
-        }
-        anyhow::bail!("Unable to control humidifier parameter work_mode={work_mode} for {device}");
+        self.send_command_via_transports(device, TransportCommand::WorkMode { work_mode, value })
+            .await?;
+        Ok(())
     }
 
     pub async fn device_set_color_rgb(
@@ -526,39 +740,12 @@ impl State {
             return Ok(());
         }
 
-        if let Some(lan_dev) = &device.lan_device {
-            let color = crate::lan_api::DeviceColor { r, g, b };
-            log::info!("Using LAN API to set {device} color");
-            lan_dev.send_color_rgb(color).await?;
-            self.poll_lan_api(lan_dev, |status| status.color == color)
-                .await?;
-            self.device_mut(&device.sku, &device.id)
-                .await
-                .set_active_scene(None);
-            return Ok(());
-        }
-
-        if device.iot_api_supported() {
-            if let Some(iot) = self.get_iot_client().await {
-                if let Some(info) = &device.undoc_device_info {
-                    log::info!("Using IoT API to set {device} color");
-                    iot.set_color_rgb(&info.entry, r, g, b).await?;
-                    return Ok(());
-                }
-            }
-        }
-
-        if let Some(client) = self.get_platform_client().await {
-            if let Some(info) = &device.http_device_info {
-                log::info!("Using Platform API to set {device} color");
-                client.set_color_rgb(info, r, g, b).await?;
-                self.device_mut(&device.sku, &device.id)
-                    .await
-                    .set_active_scene(None);
-                return Ok(());
-            }
-        }
-        anyhow::bail!("Unable to control color for {device}");
+        self.send_command_via_transports(
+            device,
+            TransportCommand::ColorRgb(DeviceColor { r, g, b }),
+        )
+        .await?;
+        Ok(())
     }
 
     pub async fn poll_after_control(self: &Arc<Self>, id: String) {
@@ -640,48 +827,55 @@ impl State {
         scene: &str,
     ) -> anyhow::Result<()> {
         // TODO: some plumbing to maintain offline scene controls for preferred-LAN control
-        let avoid_platform_api = device.avoid_platform_api();
-
-        if !avoid_platform_api {
-            if let Some(client) = self.get_platform_client().await {
-                if let Some(info) = &device.http_device_info {
-                    log::info!("Using Platform API to set {device} to scene {scene}");
-                    client.set_scene_by_name(info, scene).await?;
-                    self.device_mut(&device.sku, &device.id)
-                        .await
-                        .set_active_scene(Some(scene));
-                    return Ok(());
-                }
-            }
-        }
-
-        if let Some(lan_dev) = &device.lan_device {
-            log::info!("Using LAN API to set {device} to scene {scene}");
-            lan_dev.set_scene_by_name(scene).await?;
-
-            self.device_mut(&device.sku, &device.id)
-                .await
-                .set_active_scene(Some(scene));
-            return Ok(());
-        }
-
-        anyhow::bail!("Unable to set scene for {device}");
+        self.send_command_via_transports(device, TransportCommand::Scene(scene.to_string()))
+            .await?;
+        Ok(())
     }
 
-    // Take care not to call this while you hold a mutable device
-    // reference, as that will deadlock!
+    /// Announce that something about the device may have changed
+    /// to the subscribers of the event bus.
+    /// Take care not to call this while you hold a mutable device
+    /// reference, as that will deadlock!
     pub async fn notify_of_state_change(self: &Arc<Self>, device_id: &str) -> anyhow::Result<()> {
         let Some(canonical_device) = self.device_by_id(&device_id).await else {
             anyhow::bail!("cannot find device {device_id}!?");
         };
 
-        if let Some(hass) = self.get_hass_client().await {
-            hass.advise_hass_of_light_state(&canonical_device, self)
-                .await?;
-        }
+        let new = canonical_device.device_state();
+        let old = {
+            let mut last = self.last_notified_state.lock().await;
+            match &new {
+                Some(new) => last.insert(device_id.to_string(), new.clone()),
+                None => last.remove(device_id),
+            }
+        };
+
+        self.publish_event(ServiceEvent::DeviceStateChanged(DeviceStateChanged {
+            device_id: canonical_device.id.clone(),
+            source: new.as_ref().map(|s| s.source),
+            old,
+            new,
+            timestamp: Utc::now(),
+        }));
 
         Ok(())
     }
+
+    pub fn publish_event(&self, event: ServiceEvent) {
+        self.events.publish(event);
+    }
+
+    pub fn subscribe_events(&self) -> Receiver<ServiceEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn record_state_history(&self, change: DeviceStateChanged) {
+        self.state_history.lock().await.record(change);
+    }
+
+    pub async fn state_history_for_device(&self, device_id: &str) -> Vec<DeviceStateChanged> {
+        self.state_history.lock().await.for_device(device_id)
+    }
 }
 
 pub fn sort_and_dedup_scenes(mut scenes: Vec<String>) -> Vec<String> {
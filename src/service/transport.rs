@@ -0,0 +1,561 @@
+use crate::ble::{Base64HexBytes, SetHumidifierMode};
+use crate::error::{Classify, ErrorKind};
+use crate::lan_api::{DeviceColor, LanDevice, LanError};
+use crate::opt_env_var;
+use crate::platform_api::{GoveeApiClient, PlatformApiError};
+use crate::service::device::Device;
+use crate::service::iot::{IotClient, IotError};
+use crate::service::state::StateHandle;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Identifies one of the ways in which we can talk to a device
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Lan,
+    Iot,
+    Platform,
+}
+
+impl TransportKind {
+    pub const ALL: [TransportKind; 3] = [Self::Lan, Self::Iot, Self::Platform];
+
+    /// The short name, as used in configuration and metrics
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Self::Lan => "lan",
+            Self::Iot => "iot",
+            Self::Platform => "platform",
+        }
+    }
+}
+
+impl std::fmt::Display for TransportKind {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fmt.write_str(match self {
+            Self::Lan => "LAN API",
+            Self::Iot => "IoT API",
+            Self::Platform => "Platform API",
+        })
+    }
+}
+
+impl FromStr for TransportKind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<TransportKind> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "lan" => Ok(Self::Lan),
+            "iot" => Ok(Self::Iot),
+            "platform" | "http" => Ok(Self::Platform),
+            _ => anyhow::bail!("Unknown transport {s}, expected one of lan, iot or platform"),
+        }
+    }
+}
+
+/// A control operation that can be carried out by one or more transports
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransportCommand {
+    PowerOn(bool),
+    /// Toggle just the light portion of a device, using the
+    /// specified platform API instance name.
+    LightPowerOn {
+        instance_name: &'static str,
+        on: bool,
+    },
+    Brightness(u8),
+    ColorTemperature(u32),
+    ColorRgb(DeviceColor),
+    Scene(String),
+    WorkMode {
+        work_mode: i64,
+        value: i64,
+    },
+}
+
+impl std::fmt::Display for TransportCommand {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::PowerOn(on) => write!(fmt, "power state to {on}"),
+            Self::LightPowerOn { instance_name, on } => {
+                write!(fmt, "light {instance_name} state to {on}")
+            }
+            Self::Brightness(percent) => write!(fmt, "brightness to {percent}%"),
+            Self::ColorTemperature(kelvin) => write!(fmt, "color temperature to {kelvin}K"),
+            Self::ColorRgb(DeviceColor { r, g, b }) => {
+                write!(fmt, "color to #{r:02x}{g:02x}{b:02x}")
+            }
+            Self::Scene(scene) => write!(fmt, "scene {scene}"),
+            Self::WorkMode { work_mode, value } => {
+                write!(fmt, "work_mode={work_mode} value={value}")
+            }
+        }
+    }
+}
+
+impl TransportCommand {
+    /// The order in which transports are tried when the user hasn't
+    /// configured a preference for the device
+    pub fn default_order(&self) -> &'static [TransportKind] {
+        match self {
+            // The platform API has the most complete scene catalog,
+            // so prefer it over the LAN API for scenes
+            Self::Scene(_) => &[
+                TransportKind::Platform,
+                TransportKind::Lan,
+                TransportKind::Iot,
+            ],
+            _ => &TransportKind::ALL,
+        }
+    }
+}
+
+/// Sending a command to a device failed via every applicable transport
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("Unable to set {command} for {device}: no suitable transport")]
+    NoTransport { command: String, device: String },
+    #[error("Unable to set {command} for {device}. {}", .failures.join(". "))]
+    Failed {
+        command: String,
+        device: String,
+        kind: ErrorKind,
+        failures: Vec<String>,
+    },
+}
+
+impl CommandError {
+    /// Summarizes the kinds of the failures of the individual transports.
+    /// If any of them might succeed if tried again, then so might the
+    /// command as a whole.
+    pub fn overall_kind(kinds: &[ErrorKind]) -> ErrorKind {
+        [ErrorKind::Transient, ErrorKind::Auth, ErrorKind::Permanent]
+            .into_iter()
+            .find(|kind| kinds.contains(kind))
+            .unwrap_or(ErrorKind::Unsupported)
+    }
+}
+
+impl Classify for CommandError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::NoTransport { .. } => ErrorKind::Unsupported,
+            Self::Failed { kind, .. } => *kind,
+        }
+    }
+}
+
+/// A Transport is a means of sending control commands to a device
+#[async_trait]
+pub trait Transport: Send + Sync {
+    fn kind(&self) -> TransportKind;
+
+    /// Returns true if this transport is able to carry out `command`
+    /// for `device`, based on the facts that we know about the device.
+    fn supports(&self, device: &Device, command: &TransportCommand) -> bool;
+
+    /// Send the command to the device. Implementations that can
+    /// observe the resulting device state should verify that the
+    /// command took effect and return an error if it did not, so
+    /// that the caller can fall back to the next transport.
+    async fn send_command(
+        &self,
+        state: &StateHandle,
+        device: &Device,
+        command: &TransportCommand,
+    ) -> anyhow::Result<()>;
+
+    /// Cheaply check whether the device is reachable via this transport,
+    /// without changing its state. Returns None if the transport has
+    /// no way to do that, or if the outcome isn't known yet.
+    async fn probe(&self, _state: &StateHandle, _device: &Device) -> Option<anyhow::Result<()>> {
+        None
+    }
+}
+
+#[async_trait]
+impl Transport for LanDevice {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Lan
+    }
+
+    fn supports(&self, _device: &Device, command: &TransportCommand) -> bool {
+        !matches!(command, TransportCommand::WorkMode { .. })
+    }
+
+    async fn send_command(
+        &self,
+        state: &StateHandle,
+        _device: &Device,
+        command: &TransportCommand,
+    ) -> anyhow::Result<()> {
+        let client = state.get_lan_client().await.ok_or(LanError::NoClient)?;
+        match command {
+            TransportCommand::PowerOn(on) | TransportCommand::LightPowerOn { on, .. } => {
+                let on = *on;
+                self.send_turn(&client, on).await?;
+                state.poll_lan_api(self, |status| status.on == on).await
+            }
+            TransportCommand::Brightness(percent) => {
+                let percent = *percent;
+                self.send_brightness(&client, percent).await?;
+                state
+                    .poll_lan_api(self, |status| status.brightness == percent)
+                    .await
+            }
+            TransportCommand::ColorTemperature(kelvin) => {
+                let kelvin = *kelvin;
+                self.send_color_temperature_kelvin(&client, kelvin).await?;
+                state
+                    .poll_lan_api(self, |status| status.color_temperature_kelvin == kelvin)
+                    .await
+            }
+            TransportCommand::ColorRgb(color) => {
+                let color = *color;
+                self.send_color_rgb(&client, color).await?;
+                state
+                    .poll_lan_api(self, |status| status.color == color)
+                    .await
+            }
+            TransportCommand::Scene(scene) => self.set_scene_by_name(&client, scene).await,
+            TransportCommand::WorkMode { .. } => {
+                Err(LanError::Unsupported(command.to_string()).into())
+            }
+        }
+    }
+
+    async fn probe(&self, state: &StateHandle, _device: &Device) -> Option<anyhow::Result<()>> {
+        let client = state.get_lan_client().await?;
+        Some(client.query_status(self).await.map(|_| ()))
+    }
+}
+
+#[async_trait]
+impl Transport for IotClient {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Iot
+    }
+
+    fn supports(&self, device: &Device, command: &TransportCommand) -> bool {
+        let Some(info) = &device.undoc_device_info else {
+            return false;
+        };
+        if !self.is_device_compatible(&info.entry) {
+            return false;
+        }
+        match command {
+            TransportCommand::Scene(_) => false,
+            TransportCommand::WorkMode { .. } => encode_work_mode(device, command).is_some(),
+            _ => device.iot_api_supported(),
+        }
+    }
+
+    async fn send_command(
+        &self,
+        _state: &StateHandle,
+        device: &Device,
+        command: &TransportCommand,
+    ) -> anyhow::Result<()> {
+        let info = device
+            .undoc_device_info
+            .as_ref()
+            .ok_or_else(|| IotError::NoDeviceInfo(device.to_string()))?;
+        let entry = &info.entry;
+
+        match command {
+            TransportCommand::PowerOn(on) | TransportCommand::LightPowerOn { on, .. } => {
+                self.set_power_state(entry, *on).await
+            }
+            TransportCommand::Brightness(percent) => self.set_brightness(entry, *percent).await,
+            TransportCommand::ColorTemperature(kelvin) => {
+                self.set_color_temperature(entry, *kelvin).await
+            }
+            TransportCommand::ColorRgb(DeviceColor { r, g, b }) => {
+                self.set_color_rgb(entry, *r, *g, *b).await
+            }
+            TransportCommand::WorkMode { .. } => match encode_work_mode(device, command) {
+                Some(encoded) => self.send_real(entry, encoded).await,
+                None => Err(IotError::Unsupported(command.to_string()).into()),
+            },
+            TransportCommand::Scene(_) => Err(IotError::Unsupported(command.to_string()).into()),
+        }
+    }
+
+    /// The device replies to a status request asynchronously,
+    /// so a request only tells us something at the next probe
+    async fn probe(&self, state: &StateHandle, device: &Device) -> Option<anyhow::Result<()>> {
+        if updated_since_circuit_opened(
+            state,
+            device,
+            TransportKind::Iot,
+            device.last_iot_device_status_update,
+        )
+        .await
+        {
+            return Some(Ok(()));
+        }
+        let info = device.undoc_device_info.as_ref()?;
+        if let Err(err) = self.request_status_update(&info.entry).await {
+            return Some(Err(err));
+        }
+        None
+    }
+}
+
+/// Returns true if `updated` shows that we heard from the device
+/// via `kind` after its circuit was opened
+async fn updated_since_circuit_opened(
+    state: &StateHandle,
+    device: &Device,
+    kind: TransportKind,
+    updated: Option<DateTime<Utc>>,
+) -> bool {
+    let opened_at = state
+        .transport_health_for_device(&device.id)
+        .await
+        .get(&kind)
+        .and_then(|stats| stats.opened_at);
+    matches!((updated, opened_at), (Some(updated), Some(opened)) if updated > opened)
+}
+
+fn encode_work_mode(device: &Device, command: &TransportCommand) -> Option<Vec<String>> {
+    let TransportCommand::WorkMode { work_mode, value } = command else {
+        return None;
+    };
+    Base64HexBytes::encode_for_sku(
+        &device.sku,
+        &SetHumidifierMode {
+            mode: *work_mode as u8,
+            param: *value as u8,
+        },
+    )
+    .ok()
+    .map(|command| command.base64())
+}
+
+#[async_trait]
+impl Transport for GoveeApiClient {
+    fn kind(&self) -> TransportKind {
+        TransportKind::Platform
+    }
+
+    fn supports(&self, device: &Device, command: &TransportCommand) -> bool {
+        if device.http_device_info.is_none() {
+            return false;
+        }
+        match command {
+            TransportCommand::Scene(_) => !device.avoid_platform_api(),
+            _ => true,
+        }
+    }
+
+    async fn send_command(
+        &self,
+        _state: &StateHandle,
+        device: &Device,
+        command: &TransportCommand,
+    ) -> anyhow::Result<()> {
+        let info = device
+            .http_device_info
+            .as_ref()
+            .ok_or_else(|| PlatformApiError::NoDeviceInfo(device.to_string()))?;
+
+        match command {
+            TransportCommand::PowerOn(on) => {
+                self.set_power_state(info, *on).await?;
+            }
+            TransportCommand::LightPowerOn { instance_name, on } => {
+                self.set_toggle_state(info, instance_name, *on).await?;
+            }
+            TransportCommand::Brightness(percent) => {
+                self.set_brightness(info, *percent).await?;
+            }
+            TransportCommand::ColorTemperature(kelvin) => {
+                self.set_color_temperature(info, *kelvin).await?;
+            }
+            TransportCommand::ColorRgb(DeviceColor { r, g, b }) => {
+                self.set_color_rgb(info, *r, *g, *b).await?;
+            }
+            TransportCommand::Scene(scene) => {
+                self.set_scene_by_name(info, scene).await?;
+            }
+            TransportCommand::WorkMode { work_mode, value } => {
+                self.set_work_mode(info, *work_mode, *value).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Making a request here would spend quota on every probe, so
+    /// instead rely on periodic_state_poll, whose requests are
+    /// budgeted against the quota, having succeeded since the
+    /// circuit was opened
+    async fn probe(&self, state: &StateHandle, device: &Device) -> Option<anyhow::Result<()>> {
+        updated_since_circuit_opened(
+            state,
+            device,
+            TransportKind::Platform,
+            device.last_http_device_state_update,
+        )
+        .await
+        .then_some(Ok(()))
+    }
+}
+
+/// A user-specified transport order for a device id or SKU.
+/// Parsed from `KEY=transport,transport`, eg: `H6199=iot,platform`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TransportPreference {
+    pub key: String,
+    pub order: Vec<TransportKind>,
+}
+
+impl FromStr for TransportPreference {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<TransportPreference> {
+        // Device ids contain colons but never '=', so split on the last '='
+        let (key, order) = s.rsplit_once('=').ok_or_else(|| {
+            anyhow::anyhow!("invalid transport preference '{s}', expected KEY=lan,iot,platform")
+        })?;
+        let key = key.trim();
+        if key.is_empty() {
+            anyhow::bail!("invalid transport preference '{s}': missing device id or SKU");
+        }
+
+        let mut kinds = vec![];
+        for kind in order.split(',') {
+            let kind: TransportKind = kind.parse()?;
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+
+        Ok(Self {
+            key: key.to_string(),
+            order: kinds,
+        })
+    }
+}
+
+/// The set of configured transport preferences, keyed by
+/// device id or SKU (case insensitively)
+#[derive(Clone, Debug, Default)]
+pub struct TransportPreferences {
+    by_key: HashMap<String, Vec<TransportKind>>,
+}
+
+impl TransportPreferences {
+    pub fn new(prefs: impl IntoIterator<Item = TransportPreference>) -> Self {
+        let mut by_key = HashMap::new();
+        for pref in prefs {
+            by_key.insert(pref.key.to_ascii_lowercase(), pref.order);
+        }
+        Self { by_key }
+    }
+
+    /// Resolve the configured order for a device. A preference keyed
+    /// by device id wins over one keyed by SKU, which in turn wins
+    /// over the `*` wildcard.
+    pub fn order_for(&self, device: &Device) -> Option<&[TransportKind]> {
+        [device.id.as_str(), device.sku.as_str(), "*"]
+            .into_iter()
+            .find_map(|key| self.by_key.get(&key.to_ascii_lowercase()))
+            .map(|order| order.as_slice())
+    }
+}
+
+#[derive(clap::Parser, Debug)]
+pub struct TransportArguments {
+    /// Specify the order in which transports are used to control a
+    /// device, as `KEY=transport,...` where KEY is a device id, a SKU
+    /// or `*` to match all devices, and the transports are some
+    /// combination of `lan`, `iot` and `platform`.
+    /// Transports not listed will not be used for matching devices.
+    /// Can be specified multiple times.
+    /// You may also set GOVEE_TRANSPORT_PREFERENCE=H6199=iot,platform;*=lan,iot
+    /// via the environment.
+    #[arg(long)]
+    pub transport_preference: Vec<TransportPreference>,
+}
+
+impl TransportArguments {
+    pub fn transport_preferences(&self) -> anyhow::Result<TransportPreferences> {
+        let mut prefs = self.transport_preference.clone();
+
+        if let Some(v) = opt_env_var::<String>("GOVEE_TRANSPORT_PREFERENCE")? {
+            for pref in v.split(';') {
+                if pref.trim().is_empty() {
+                    continue;
+                }
+                prefs.push(pref.parse()?);
+            }
+        }
+
+        Ok(TransportPreferences::new(prefs))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_preference() {
+        let pref: TransportPreference =
+            "AA:BB:CC:DD:EE:FF:42:2A=iot, Platform,iot".parse().unwrap();
+        assert_eq!(pref.key, "AA:BB:CC:DD:EE:FF:42:2A");
+        assert_eq!(
+            pref.order,
+            vec![TransportKind::Iot, TransportKind::Platform]
+        );
+
+        assert!("H6199".parse::<TransportPreference>().is_err());
+        assert!("=lan".parse::<TransportPreference>().is_err());
+        assert!("H6199=bluetooth".parse::<TransportPreference>().is_err());
+    }
+
+    #[test]
+    fn overall_kind() {
+        use ErrorKind::*;
+        assert_eq!(
+            CommandError::overall_kind(&[Unsupported, Transient]),
+            Transient
+        );
+        assert_eq!(CommandError::overall_kind(&[Permanent, Auth]), Auth);
+        assert_eq!(
+            CommandError::overall_kind(&[Unsupported, Permanent]),
+            Permanent
+        );
+        assert_eq!(CommandError::overall_kind(&[Unsupported]), Unsupported);
+    }
+
+    #[test]
+    fn preference_precedence() {
+        let prefs = TransportPreferences::new(
+            ["*=platform", "h6199=iot", "AA:BB=lan"]
+                .into_iter()
+                .map(|s| s.parse().unwrap()),
+        );
+
+        let device = Device::new("H6199", "aa:bb");
+        assert_eq!(prefs.order_for(&device), Some(&[TransportKind::Lan][..]));
+
+        let device = Device::new("H6199", "cc:dd");
+        assert_eq!(prefs.order_for(&device), Some(&[TransportKind::Iot][..]));
+
+        let device = Device::new("H6000", "cc:dd");
+        assert_eq!(
+            prefs.order_for(&device),
+            Some(&[TransportKind::Platform][..])
+        );
+
+        assert_eq!(TransportPreferences::default().order_for(&device), None);
+    }
+}
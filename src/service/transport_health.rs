@@ -0,0 +1,271 @@
+use crate::service::state::StateHandle;
+use crate::service::transport::TransportKind;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// How many consecutive failures open the circuit for a transport
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long an open circuit stays open before we allow a real
+/// command to try the transport again
+const OPEN_DURATION: chrono::Duration = chrono::Duration::minutes(5);
+/// How often the background task probes open circuits.
+/// LAN and IoT probes query the device; the Platform probe only
+/// looks for a successful poll, to avoid spending quota.
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+/// Weight given to the most recent sample in the latency average
+const LATENCY_EWMA_WEIGHT: f64 = 0.3;
+
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// The transport is healthy and is used normally
+    Closed,
+    /// The transport has failed repeatedly and is only used
+    /// as a last resort
+    Open,
+    /// The transport was open, but enough time has passed that
+    /// the next command may try it again
+    HalfOpen,
+}
+
+/// Tracks the outcomes of commands sent to a device via a transport
+#[derive(Serialize, JsonSchema, Clone, Debug)]
+pub struct TransportStats {
+    pub successes: u64,
+    pub failures: u64,
+    /// Fraction of commands that succeeded; only populated in snapshots
+    pub success_rate: Option<f64>,
+    pub consecutive_failures: u32,
+    /// Exponentially weighted average latency of successful commands
+    pub avg_latency_ms: Option<f64>,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub circuit: CircuitState,
+    pub opened_at: Option<DateTime<Utc>>,
+}
+
+impl Default for TransportStats {
+    fn default() -> Self {
+        Self {
+            successes: 0,
+            failures: 0,
+            success_rate: None,
+            consecutive_failures: 0,
+            avg_latency_ms: None,
+            last_success: None,
+            last_failure: None,
+            last_error: None,
+            circuit: CircuitState::Closed,
+            opened_at: None,
+        }
+    }
+}
+
+impl TransportStats {
+    /// The fraction of commands that succeeded, if any were sent
+    fn compute_success_rate(&self) -> Option<f64> {
+        let total = self.successes + self.failures;
+        if total == 0 {
+            None
+        } else {
+            Some(self.successes as f64 / total as f64)
+        }
+    }
+
+    /// Returns the circuit state, taking into account whether an
+    /// open circuit has cooled down enough to be tried again
+    pub fn circuit_at(&self, now: DateTime<Utc>) -> CircuitState {
+        match (self.circuit, self.opened_at) {
+            (CircuitState::Open, Some(opened)) if now - opened > OPEN_DURATION => {
+                CircuitState::HalfOpen
+            }
+            (circuit, _) => circuit,
+        }
+    }
+
+    fn record_success(&mut self, latency: Duration) {
+        let now = Utc::now();
+        let ms = latency.as_micros() as f64 / 1000.;
+        self.successes += 1;
+        self.consecutive_failures = 0;
+        self.avg_latency_ms = Some(match self.avg_latency_ms {
+            Some(avg) => avg + LATENCY_EWMA_WEIGHT * (ms - avg),
+            None => ms,
+        });
+        self.last_success.replace(now);
+        self.close();
+    }
+
+    /// Returns true if this failure caused the circuit to open
+    fn record_failure(&mut self, error: String) -> bool {
+        let now = Utc::now();
+        self.failures += 1;
+        self.consecutive_failures += 1;
+        self.last_failure.replace(now);
+        self.last_error.replace(error);
+
+        let was_open = self.circuit == CircuitState::Open;
+        if self.consecutive_failures >= FAILURE_THRESHOLD
+            || self.circuit_at(now) == CircuitState::HalfOpen
+        {
+            self.circuit = CircuitState::Open;
+            self.opened_at.replace(now);
+        }
+        !was_open && self.circuit == CircuitState::Open
+    }
+
+    fn close(&mut self) {
+        self.circuit = CircuitState::Closed;
+        self.opened_at.take();
+    }
+
+    /// Returns a copy with the circuit state resolved for `now`
+    fn snapshot(&self, now: DateTime<Utc>) -> Self {
+        let mut stats = self.clone();
+        stats.circuit = self.circuit_at(now);
+        stats.success_rate = self.compute_success_rate();
+        stats
+    }
+}
+
+/// Per-device, per-transport health information
+#[derive(Default, Debug)]
+pub struct TransportHealth {
+    stats: HashMap<(String, TransportKind), TransportStats>,
+}
+
+impl TransportHealth {
+    pub fn record_success(&mut self, device_id: &str, kind: TransportKind, latency: Duration) {
+        self.stats
+            .entry((device_id.to_string(), kind))
+            .or_default()
+            .record_success(latency);
+    }
+
+    /// Returns true if the failure caused the circuit to open
+    pub fn record_failure(&mut self, device_id: &str, kind: TransportKind, error: String) -> bool {
+        let opened = self
+            .stats
+            .entry((device_id.to_string(), kind))
+            .or_default()
+            .record_failure(error);
+        if opened {
+            log::warn!("Opening circuit for {kind} to device {device_id} after repeated failures");
+        }
+        opened
+    }
+
+    /// A successful probe closes the circuit, but isn't counted
+    /// as a successful command
+    pub fn record_probe_success(&mut self, device_id: &str, kind: TransportKind) {
+        if let Some(stats) = self.stats.get_mut(&(device_id.to_string(), kind)) {
+            log::info!("Closing circuit for {kind} to device {device_id}: probe succeeded");
+            stats.consecutive_failures = 0;
+            stats.close();
+        }
+    }
+
+    pub fn circuit(&self, device_id: &str, kind: TransportKind) -> CircuitState {
+        self.stats
+            .get(&(device_id.to_string(), kind))
+            .map(|stats| stats.circuit_at(Utc::now()))
+            .unwrap_or(CircuitState::Closed)
+    }
+
+    pub fn stats_for_device(&self, device_id: &str) -> BTreeMap<TransportKind, TransportStats> {
+        let now = Utc::now();
+        self.stats
+            .iter()
+            .filter(|((id, _), _)| id == device_id)
+            .map(|((_, kind), stats)| (*kind, stats.snapshot(now)))
+            .collect()
+    }
+
+    /// Returns the (device_id, transport) pairs whose circuits are open
+    pub fn open_circuits(&self) -> Vec<(String, TransportKind)> {
+        self.stats
+            .iter()
+            .filter(|(_, stats)| stats.circuit == CircuitState::Open)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+}
+
+/// Periodically probe transports with open circuits so that we
+/// notice when they recover, without having to wait for a
+/// command to be routed via them
+pub async fn periodic_transport_probe(state: StateHandle) -> anyhow::Result<()> {
+    loop {
+        sleep(PROBE_INTERVAL).await;
+
+        for (device_id, kind) in state.open_transport_circuits().await {
+            let Some(device) = state.device_by_id(&device_id).await else {
+                continue;
+            };
+            let Some(transport) = state.transport_for_device(&device, kind).await else {
+                continue;
+            };
+            match transport.probe(&state, &device).await {
+                Some(Ok(())) => {
+                    state.record_transport_probe_success(&device_id, kind).await;
+                    state.notify_of_state_change(&device_id).await.ok();
+                }
+                Some(Err(err)) => {
+                    log::debug!("Probe of {kind} for {device} failed: {err:#}");
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn circuit_opens_and_closes() {
+        let mut health = TransportHealth::default();
+        let id = "AA:BB";
+
+        assert_eq!(health.circuit(id, TransportKind::Lan), CircuitState::Closed);
+
+        assert!(!health.record_failure(id, TransportKind::Lan, "timeout".to_string()));
+        assert!(!health.record_failure(id, TransportKind::Lan, "timeout".to_string()));
+        assert!(health.record_failure(id, TransportKind::Lan, "timeout".to_string()));
+        assert_eq!(health.circuit(id, TransportKind::Lan), CircuitState::Open);
+        assert_eq!(health.circuit(id, TransportKind::Iot), CircuitState::Closed);
+        assert_eq!(
+            health.open_circuits(),
+            vec![(id.to_string(), TransportKind::Lan)]
+        );
+
+        health.record_success(id, TransportKind::Lan, Duration::from_millis(100));
+        assert_eq!(health.circuit(id, TransportKind::Lan), CircuitState::Closed);
+
+        let stats = &health.stats_for_device(id)[&TransportKind::Lan];
+        assert_eq!(stats.successes, 1);
+        assert_eq!(stats.failures, 3);
+        assert_eq!(stats.success_rate, Some(0.25));
+        assert_eq!(stats.avg_latency_ms, Some(100.));
+    }
+
+    #[test]
+    fn half_open_after_cooldown() {
+        let mut stats = TransportStats::default();
+        for _ in 0..FAILURE_THRESHOLD {
+            stats.record_failure("boom".to_string());
+        }
+        let opened = stats.opened_at.unwrap();
+        assert_eq!(stats.circuit_at(opened), CircuitState::Open);
+        assert_eq!(
+            stats.circuit_at(opened + OPEN_DURATION + chrono::Duration::seconds(1)),
+            CircuitState::HalfOpen
+        );
+    }
+}
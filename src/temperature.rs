@@ -1,3 +1,4 @@
+use serde::Serialize;
 use std::str::FromStr;
 
 pub const UNIT_CELSIUS: &str = "°C";
@@ -5,7 +6,7 @@ pub const UNIT_FAHRENHEIT: &str = "°F";
 pub const DEVICE_CLASS_TEMPERATURE: &str = "temperature";
 
 #[allow(unused)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
 pub enum TemperatureUnits {
     Celsius,
     CelsiusTimes100,
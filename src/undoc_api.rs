@@ -1,5 +1,7 @@
 #![allow(unused)]
 use crate::cache::{cache_get, CacheComputeResult, CacheGetOptions};
+use crate::error::{error_kind, Classify, ErrorKind};
+use crate::http_client::{http_client, send_with_retry};
 use crate::lan_api::{boolean_int, truthy};
 use crate::opt_env_var;
 use crate::platform_api::{
@@ -7,11 +9,13 @@ use crate::platform_api::{
     EnumOption,
 };
 use reqwest::Method;
+use schemars::JsonSchema;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 use std::path::PathBuf;
 use std::time::Duration;
+use thiserror::Error;
 use uuid::Uuid;
 
 // <https://github.com/constructorfleet/homebridge-ultimate-govee/blob/main/src/data/clients/RestClient.ts>
@@ -23,10 +27,83 @@ const ONE_WEEK: Duration = Duration::from_secs(86400 * 7);
 const FIFTEEN_MINS: Duration = Duration::from_secs(60 * 15);
 
 /// Some data is not meant for human eyes except in very unusual circumstances.
-#[derive(Deserialize, Serialize, Clone)]
+/// It is serialized as-is, so that it can be cached, except within
+/// `to_redacted_json`.
+#[derive(Deserialize, Clone)]
 #[serde(transparent)]
 pub struct Redacted<T: std::fmt::Debug>(T);
 
+/// How `Redacted` values are serialized by the current thread
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Redaction {
+    /// As-is, for caching
+    Off,
+    /// Redacted, unless GOVEE_LOG_SENSITIVE_DATA is enabled
+    UnlessSensitive,
+    /// Redacted, regardless of GOVEE_LOG_SENSITIVE_DATA
+    Always,
+}
+
+thread_local! {
+    static REDACT_ON_SERIALIZE: std::cell::Cell<Redaction> = const { std::cell::Cell::new(Redaction::Off) };
+}
+
+fn to_json_with_redaction<T: Serialize>(
+    value: &T,
+    redaction: Redaction,
+) -> anyhow::Result<JsonValue> {
+    let prior = REDACT_ON_SERIALIZE.with(|redact| redact.replace(redaction));
+    let result = serde_json::to_value(value);
+    REDACT_ON_SERIALIZE.with(|redact| redact.set(prior));
+    Ok(result?)
+}
+
+/// Serialize `value` as JSON that is suitable for showing to humans,
+/// replacing the contents of any `Redacted` fields, unless
+/// GOVEE_LOG_SENSITIVE_DATA is enabled.
+pub fn to_redacted_json<T: Serialize>(value: &T) -> anyhow::Result<JsonValue> {
+    to_json_with_redaction(value, Redaction::UnlessSensitive)
+}
+
+/// Serialize `value` as JSON that is suitable for sharing with others,
+/// such as in a diagnostics bundle attached to an issue report.
+/// The contents of any `Redacted` fields are always replaced.
+pub fn to_shareable_json<T: Serialize>(value: &T) -> anyhow::Result<JsonValue> {
+    to_json_with_redaction(value, Redaction::Always)
+}
+
+impl<T: std::fmt::Debug + JsonSchema> JsonSchema for Redacted<T> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        T::json_schema(gen)
+    }
+}
+
+impl<T: std::fmt::Debug + Serialize> Serialize for Redacted<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let redact = match REDACT_ON_SERIALIZE.with(|redact| redact.get()) {
+            Redaction::Off => false,
+            Redaction::UnlessSensitive => !should_log_sensitive_data(),
+            Redaction::Always => true,
+        };
+        if redact {
+            "REDACTED".serialize(serializer)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
 pub fn should_log_sensitive_data() -> bool {
     if let Ok(Some(v)) = opt_env_var::<String>("GOVEE_LOG_SENSITIVE_DATA") {
         truthy(&v).unwrap_or(false)
@@ -71,6 +148,30 @@ pub fn ms_timestamp() -> String {
         .to_string()
 }
 
+/// The ways in which using the undocumented API can fail,
+/// other than an unsuccessful request
+#[derive(Error, Debug)]
+pub enum UndocApiError {
+    #[error("logging in to Govee failed")]
+    LoginFailed(#[source] anyhow::Error),
+    #[error("device {0} has no topic, is it a BLE-only device?")]
+    NoDeviceTopic(String),
+}
+
+impl Classify for UndocApiError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            // Unless Govee is having problems, a failed login is most
+            // likely due to the credentials that we were given
+            Self::LoginFailed(err) => match error_kind(err) {
+                ErrorKind::Transient => ErrorKind::Transient,
+                _ => ErrorKind::Auth,
+            },
+            Self::NoDeviceTopic(_) => ErrorKind::Unsupported,
+        }
+    }
+}
+
 #[derive(clap::Parser, Debug)]
 pub struct UndocApiArguments {
     /// The email address you registered with Govee.
@@ -172,19 +273,18 @@ impl GoveeUndocumentedApi {
             async {
                 let app_version = resolve_app_version()?;
                 let ua = user_agent(&app_version);
-                let response = reqwest::Client::builder()
-                    .timeout(Duration::from_secs(30))
-                    .build()?
-                    .request(Method::GET, "https://app2.govee.com/app/v1/account/iot/key")
-                    .header("Authorization", format!("Bearer {token}"))
-                    .header("appVersion", app_version.as_str())
-                    .header("clientId", &self.client_id)
-                    .header("clientType", "1")
-                    .header("iotVersion", "0")
-                    .header("timestamp", ms_timestamp())
-                    .header("User-Agent", ua)
-                    .send()
-                    .await?;
+                let response = send_with_retry("undoc", "get_iot_key", || {
+                    Ok(http_client()
+                        .request(Method::GET, "https://app2.govee.com/app/v1/account/iot/key")
+                        .header("Authorization", format!("Bearer {token}"))
+                        .header("appVersion", app_version.as_str())
+                        .header("clientId", &self.client_id)
+                        .header("clientType", "1")
+                        .header("iotVersion", "0")
+                        .header("timestamp", ms_timestamp())
+                        .header("User-Agent", &ua))
+                })
+                .await?;
 
                 #[derive(Deserialize, Debug)]
                 #[allow(non_snake_case, dead_code)]
@@ -209,29 +309,31 @@ impl GoveeUndocumentedApi {
     async fn login_account_impl(&self) -> anyhow::Result<CacheComputeResult<LoginAccountResponse>> {
         let app_version = resolve_app_version()?;
         let ua = user_agent(&app_version);
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()?
-            .request(
-                Method::POST,
-                "https://app2.govee.com/account/rest/account/v1/login",
-            )
-            .header("appVersion", app_version.as_str())
-            .header("AppVersion", app_version.as_str())
-            .header("clientId", &self.client_id)
-            .header("clientType", "1")
-            .header("iotVersion", "0")
-            .header("timestamp", ms_timestamp())
-            .header("User-Agent", ua)
-            .json(&serde_json::json!({
-                "email": self.email,
-                "password": self.password,
-                "client": &self.client_id,
-            }))
-            .send()
-            .await?;
-
-        let resp: Response = http_response_body(response).await?;
+        let response = send_with_retry("undoc", "login_account", || {
+            Ok(http_client()
+                .request(
+                    Method::POST,
+                    "https://app2.govee.com/account/rest/account/v1/login",
+                )
+                .header("appVersion", app_version.as_str())
+                .header("AppVersion", app_version.as_str())
+                .header("clientId", &self.client_id)
+                .header("clientType", "1")
+                .header("iotVersion", "0")
+                .header("timestamp", ms_timestamp())
+                .header("User-Agent", &ua)
+                .json(&serde_json::json!({
+                    "email": self.email,
+                    "password": self.password,
+                    "client": &self.client_id,
+                })))
+        })
+        .await
+        .map_err(UndocApiError::LoginFailed)?;
+
+        let resp: Response = http_response_body(response)
+            .await
+            .map_err(UndocApiError::LoginFailed)?;
 
         #[derive(Deserialize, Serialize, Debug)]
         #[allow(non_snake_case, dead_code)]
@@ -269,22 +371,21 @@ impl GoveeUndocumentedApi {
     pub async fn get_device_list(&self, token: &str) -> anyhow::Result<DevicesResponse> {
         let app_version = resolve_app_version()?;
         let ua = user_agent(&app_version);
-        let response = reqwest::Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()?
-            .request(
-                Method::POST,
-                "https://app2.govee.com/device/rest/devices/v1/list",
-            )
-            .header("Authorization", format!("Bearer {token}"))
-            .header("appVersion", app_version.as_str())
-            .header("clientId", &self.client_id)
-            .header("clientType", "1")
-            .header("iotVersion", "0")
-            .header("timestamp", ms_timestamp())
-            .header("User-Agent", ua)
-            .send()
-            .await?;
+        let response = send_with_retry("undoc", "get_device_list", || {
+            Ok(http_client()
+                .request(
+                    Method::POST,
+                    "https://app2.govee.com/device/rest/devices/v1/list",
+                )
+                .header("Authorization", format!("Bearer {token}"))
+                .header("appVersion", app_version.as_str())
+                .header("clientId", &self.client_id)
+                .header("clientType", "1")
+                .header("iotVersion", "0")
+                .header("timestamp", ms_timestamp())
+                .header("User-Agent", &ua))
+        })
+        .await?;
 
         if response.status() == reqwest::StatusCode::UNAUTHORIZED {
             self.invalidate_account_login();
@@ -311,16 +412,16 @@ impl GoveeUndocumentedApi {
                 allow_stale: false,
             },
             async {
-                let response = reqwest::Client::builder()
-                    .timeout(Duration::from_secs(60))
-                    .build()?
-                    .request(Method::POST, "https://community-api.govee.com/os/v1/login")
-                    .json(&serde_json::json!({
-                        "email": self.email,
-                        "password": self.password,
-                    }))
-                    .send()
-                    .await?;
+                let response = send_with_retry("undoc", "login_community", || {
+                    Ok(http_client()
+                        .request(Method::POST, "https://community-api.govee.com/os/v1/login")
+                        .json(&serde_json::json!({
+                            "email": self.email,
+                            "password": self.password,
+                        })))
+                })
+                .await
+                .map_err(UndocApiError::LoginFailed)?;
 
                 #[derive(Deserialize, Debug)]
                 #[allow(non_snake_case, dead_code)]
@@ -341,7 +442,9 @@ impl GoveeUndocumentedApi {
                     token: String,
                 }
 
-                let resp: Response = http_response_body(response).await?;
+                let resp: Response = http_response_body(response)
+                    .await
+                    .map_err(UndocApiError::LoginFailed)?;
 
                 let ts_ms = std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
@@ -372,19 +475,18 @@ impl GoveeUndocumentedApi {
             async {
                 let app_version = resolve_app_version()?;
                 let ua = user_agent(&app_version);
-                let response = reqwest::Client::builder()
-                    .timeout(Duration::from_secs(10))
-                    .build()?
-                    .request(
-                        Method::GET,
-                        format!(
-                            "https://app2.govee.com/appsku/v1/light-effect-libraries?sku={sku}"
-                        ),
-                    )
-                    .header("AppVersion", app_version.as_str())
-                    .header("User-Agent", ua)
-                    .send()
-                    .await?;
+                let response = send_with_retry("undoc", "get_scenes_for_device", || {
+                    Ok(http_client()
+                        .request(
+                            Method::GET,
+                            format!(
+                                "https://app2.govee.com/appsku/v1/light-effect-libraries?sku={sku}"
+                            ),
+                        )
+                        .header("AppVersion", app_version.as_str())
+                        .header("User-Agent", &ua))
+                })
+                .await?;
 
                 let resp: LightEffectLibraryResponse = http_response_body(response).await?;
 
@@ -442,22 +544,21 @@ impl GoveeUndocumentedApi {
             async {
                 let app_version = resolve_app_version()?;
                 let ua = user_agent(&app_version);
-                let response = reqwest::Client::builder()
-                    .timeout(Duration::from_secs(10))
-                    .build()?
-                    .request(
-                        Method::GET,
-                        "https://app2.govee.com/bff-app/v1/exec-plat/home",
-                    )
-                    .header("Authorization", format!("Bearer {community_token}"))
-                    .header("appVersion", app_version.as_str())
-                    .header("clientId", &self.client_id)
-                    .header("clientType", "1")
-                    .header("iotVersion", "0")
-                    .header("timestamp", ms_timestamp())
-                    .header("User-Agent", ua)
-                    .send()
-                    .await?;
+                let response = send_with_retry("undoc", "get_saved_one_click_shortcuts", || {
+                    Ok(http_client()
+                        .request(
+                            Method::GET,
+                            "https://app2.govee.com/bff-app/v1/exec-plat/home",
+                        )
+                        .header("Authorization", format!("Bearer {community_token}"))
+                        .header("appVersion", app_version.as_str())
+                        .header("clientId", &self.client_id)
+                        .header("clientType", "1")
+                        .header("iotVersion", "0")
+                        .header("timestamp", ms_timestamp())
+                        .header("User-Agent", &ua))
+                })
+                .await?;
 
                 if response.status() == reqwest::StatusCode::UNAUTHORIZED {
                     self.invalidate_community_login();
@@ -499,13 +600,13 @@ impl GoveeUndocumentedApi {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ParsedOneClick {
     pub name: String,
     pub entries: Vec<ParsedOneClickEntry>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ParsedOneClickEntry {
     pub topic: Redacted<String>,
     pub msgs: Vec<JsonValue>,
@@ -696,7 +797,7 @@ pub struct OneClickIotRuleDevice {
 
     pub topic: Option<Redacted<String>>,
 
-    pub ble_address: Option<String>,
+    pub ble_address: Option<Redacted<String>>,
     pub ble_name: Option<String>,
     pub device_splicing_status: u32,
     pub feast_id: u64,
@@ -763,7 +864,7 @@ pub struct GroupEntry {
     pub group_name: String,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct DeviceEntry {
@@ -793,16 +894,11 @@ impl DeviceEntry {
             .topic
             .as_ref()
             .map(|t| t.as_str())
-            .ok_or_else(|| {
-                anyhow::anyhow!(
-                    "device {id} has no topic, is it a BLE-only device?",
-                    id = self.device
-                )
-            })
+            .ok_or_else(|| UndocApiError::NoDeviceTopic(self.device.clone()).into())
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct DeviceEntryExt {
@@ -814,16 +910,16 @@ pub struct DeviceEntryExt {
     pub last_device_data: LastDeviceData,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct DeviceSettings {
     /// Maybe be absent for BLE devices
     pub wifi_name: Option<String>,
-    pub address: Option<String>,
+    pub address: Option<Redacted<String>>,
     pub ble_name: Option<String>,
     pub topic: Option<Redacted<String>>,
-    pub wifi_mac: Option<String>,
+    pub wifi_mac: Option<Redacted<String>>,
     pub pact_type: Option<u32>,
     pub pact_code: Option<u32>,
     pub dsp_version_soft: Option<JsonValue>,
@@ -886,7 +982,7 @@ pub struct DeviceSettings {
     pub support_ble_broad_v3: Option<bool>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct ExtResources {
@@ -899,7 +995,7 @@ pub struct ExtResources {
     pub ic: Option<u32>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[cfg_attr(debug_assertions, serde(deny_unknown_fields))]
 pub struct LastDeviceData {
@@ -951,6 +1047,29 @@ mod test {
         k9::assert_matches_snapshot!(format!("{resp:#?}"));
     }
 
+    #[test]
+    fn redacted_json() {
+        let resp: DevicesResponse =
+            from_json(include_str!("../test-data/undoc-device-list.json")).unwrap();
+        let entry = &resp.devices[0];
+        let topic = entry.device_topic().unwrap().to_string();
+
+        let plain = serde_json::to_string(entry).unwrap();
+        assert!(plain.contains(&topic));
+
+        if !should_log_sensitive_data() {
+            let redacted = to_redacted_json(entry).unwrap().to_string();
+            assert!(!redacted.contains(&topic));
+            assert!(redacted.contains("REDACTED"));
+        }
+
+        let shareable = to_shareable_json(entry).unwrap().to_string();
+        assert!(!shareable.contains(&topic));
+
+        // The redaction is scoped to the call
+        assert_eq!(serde_json::to_string(entry).unwrap(), plain);
+    }
+
     #[test]
     fn get_one_click() {
         let resp: OneClickResponse =